@@ -40,7 +40,7 @@ export function LogModal({ isOpen, onClose, monitorId }: LogModalProps) {
 
         const fetchLogs = async () => {
             try {
-                const response = await fetch(`/log/${encodeURIComponent(monitorId)}`);
+                const response = await fetch(`log/${encodeURIComponent(monitorId)}`);
                 if (!response.ok) {
                     throw new Error(`HTTP error! status: ${response.status}`);
                 }
@@ -14,7 +14,7 @@ function useStatusData() {
 
     // Fetch config once at startup
     const fetchConfig = async (): Promise<Config> => {
-        const response = await fetch('/config.json');
+        const response = await fetch('config.json');
         if (!response.ok) {
             throw new Error(`Failed to fetch config: ${response.status}`);
         }
@@ -34,7 +34,7 @@ function useStatusData() {
         try {
             setLoading(true);
             setError(null);
-            const response = await fetch('/status.json');
+            const response = await fetch('status.json');
             if (!response.ok) {
                 throw new Error(`HTTP error! status: ${response.status}`);
             }
@@ -254,7 +254,7 @@ function Header({ title, description, themeMode, onThemeToggle }: HeaderProps) {
     return (
         <div className="header">
             <div className="header-content">
-                <img className="logo" src="/stylus.svg" alt="Stylus Logo" />
+                <img className="logo" src="stylus.svg" alt="Stylus Logo" />
                 <div className="header-text">
                     <h1 className="title">{title}</h1>
                     <p className="description">{description}</p>
@@ -402,7 +402,7 @@ function MonitorsTab({ data, loading, onRefetch, onShowLog }: MonitorsTabProps)
             
             <div style={{ marginTop: '40px', textAlign: 'center' }}>
                 <p>
-                    <a href="/status.json" target="_blank" rel="noopener noreferrer">
+                    <a href="status.json" target="_blank" rel="noopener noreferrer">
                         View Raw JSON
                     </a>
                 </p>
@@ -30,7 +30,7 @@ export function SVGVisualization({ state, url }: SVGVisualizationProps) {
         }
 
         // Fetch CSS directly with cache buster
-        fetch(`/style.css?t=${new Date().valueOf()}`)
+        fetch(`style.css?t=${new Date().valueOf()}`)
             .then(res => res.text())
             .then(cssText => {
                 styleRef.current!.textContent = cssText;
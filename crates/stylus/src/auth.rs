@@ -0,0 +1,471 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use axum::http::{header, HeaderMap, Method};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::listener::Remote;
+
+/// The maximum number of verified `Authorization` headers that are remembered.
+const VERIFIED_CACHE_CAPACITY: usize = 1024;
+
+/// What a request is allowed to do. Roles are ordered, so that admins can do everything that
+/// readers can.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthRole {
+    #[default]
+    None,
+    Read,
+    Admin,
+}
+
+/// Restricts who may use the HTTP server. Without this section, the server is open to anyone
+/// who can reach it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    /// The role of requests that don't have any credentials.
+    #[serde(default)]
+    pub anonymous: AuthRole,
+    /// The role required to read the logs of monitors, which contain raw script output.
+    #[serde(default = "default_logs")]
+    pub logs: AuthRole,
+    /// Named static bearer tokens.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tokens: BTreeMap<String, AuthTokenConfig>,
+    /// Users for HTTP basic authentication.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub users: BTreeMap<String, AuthUserConfig>,
+    /// Trust the user name set by a reverse proxy in front of the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<AuthProxyConfig>,
+}
+
+fn default_logs() -> AuthRole {
+    AuthRole::Read
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthTokenConfig {
+    #[serde(default, skip_serializing)]
+    pub token: String,
+    pub role: AuthRole,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthUserConfig {
+    /// A bcrypt hash of the password.
+    #[serde(default, skip_serializing)]
+    pub password: String,
+    pub role: AuthRole,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthProxyConfig {
+    /// The header containing the name of the user.
+    #[serde(default = "default_proxy_header")]
+    pub header: String,
+    /// The addresses or CIDR ranges of the proxies that are trusted to set the header, or `unix`
+    /// for connections to a Unix socket.
+    pub trusted: Vec<String>,
+    /// The role of proxied users that aren't listed in `users`.
+    #[serde(default = "default_proxy_role")]
+    pub role: AuthRole,
+}
+
+fn default_proxy_header() -> String {
+    "X-Forwarded-User".to_string()
+}
+
+fn default_proxy_role() -> AuthRole {
+    AuthRole::Read
+}
+
+/// Who made a request, and what they are allowed to do.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identity {
+    /// The name of the token or user, if any credentials were given.
+    pub name: Option<String>,
+    pub role: AuthRole,
+}
+
+impl Identity {
+    /// Whether this identity may see raw script output, which is also included in events.
+    pub fn can_read_logs(&self, config: &AuthConfig) -> bool {
+        self.role >= config.logs
+    }
+}
+
+#[derive(Debug, Display, Error, PartialEq)]
+pub enum AuthError {
+    #[display("Unsupported authorization scheme")]
+    UnsupportedScheme,
+    #[display("Invalid credentials")]
+    InvalidCredentials,
+}
+
+/// The role required for a request. Every request that isn't a read changes something, so
+/// requires an admin.
+pub fn required_role(config: &AuthConfig, method: &Method, path: &str) -> AuthRole {
+    if method != Method::GET && method != Method::HEAD {
+        AuthRole::Admin
+    } else if path.starts_with("/log/") {
+        config.logs
+    } else {
+        AuthRole::Read
+    }
+}
+
+/// Authenticates requests, remembering which basic credentials were valid, as bcrypt is slow
+/// by design.
+#[derive(Debug, Default)]
+pub struct Authenticator {
+    /// Maps an `Authorization` header to the password hash that it was verified against.
+    verified: Mutex<HashMap<String, String>>,
+}
+
+impl Authenticator {
+    /// Work out who made a request from its headers and the address it came from. This may
+    /// block while a password is verified.
+    pub fn authenticate(
+        &self,
+        config: &AuthConfig,
+        headers: &HeaderMap,
+        remote: Option<Remote>,
+    ) -> Result<Identity, AuthError> {
+        if let Some(proxy) = &config.proxy {
+            let trusted = remote.is_some_and(|remote| {
+                proxy
+                    .trusted
+                    .iter()
+                    .any(|trusted| address_matches(trusted, remote))
+            });
+            let user = headers
+                .get(proxy.header.as_str())
+                .and_then(|user| user.to_str().ok())
+                .filter(|user| !user.is_empty());
+            if let (true, Some(user)) = (trusted, user) {
+                let role = config.users.get(user).map_or(proxy.role, |user| user.role);
+                return Ok(Identity {
+                    name: Some(user.to_owned()),
+                    role,
+                });
+            }
+        }
+
+        let Some(authorization) = headers.get(header::AUTHORIZATION) else {
+            return Ok(Identity {
+                name: None,
+                role: config.anonymous,
+            });
+        };
+        let authorization = authorization
+            .to_str()
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        let (scheme, credentials) = authorization
+            .split_once(' ')
+            .ok_or(AuthError::UnsupportedScheme)?;
+        let credentials = credentials.trim();
+
+        if scheme.eq_ignore_ascii_case("bearer") {
+            // Check every token so that the time taken doesn't reveal which one nearly matched
+            let mut found = None;
+            for (name, token) in &config.tokens {
+                if !token.token.is_empty() && constant_time_eq(&token.token, credentials) {
+                    found = Some(Identity {
+                        name: Some(name.clone()),
+                        role: token.role,
+                    });
+                }
+            }
+            found.ok_or(AuthError::InvalidCredentials)
+        } else if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(credentials)
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .ok_or(AuthError::InvalidCredentials)?;
+            let (name, password) = decoded
+                .split_once(':')
+                .ok_or(AuthError::InvalidCredentials)?;
+            let user = config
+                .users
+                .get(name)
+                .ok_or(AuthError::InvalidCredentials)?;
+            let identity = Identity {
+                name: Some(name.to_owned()),
+                role: user.role,
+            };
+
+            // The cache is keyed by the hash too, so that changing a password takes effect
+            if self.verified.lock().unwrap().get(authorization) == Some(&user.password) {
+                return Ok(identity);
+            }
+            if !bcrypt::verify(password, &user.password).unwrap_or_default() {
+                return Err(AuthError::InvalidCredentials);
+            }
+            let mut verified = self.verified.lock().unwrap();
+            if verified.len() >= VERIFIED_CACHE_CAPACITY {
+                verified.clear();
+            }
+            verified.insert(authorization.to_owned(), user.password.clone());
+            Ok(identity)
+        } else {
+            Err(AuthError::UnsupportedScheme)
+        }
+    }
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Whether a connection matches a single address, a CIDR range like `10.0.0.0/8`, or `unix`.
+fn address_matches(trusted: &str, remote: Remote) -> bool {
+    let remote = match remote {
+        Remote::Ip(remote) => remote,
+        Remote::Unix => return trusted == "unix",
+    };
+    let (address, prefix) = match trusted.split_once('/') {
+        Some((address, prefix)) => (address, prefix.parse::<u32>().ok()),
+        None => (trusted, None),
+    };
+    let Ok(address) = address.trim().parse::<IpAddr>() else {
+        return false;
+    };
+    match (address, remote.to_canonical()) {
+        (IpAddr::V4(address), IpAddr::V4(remote)) => {
+            let prefix = prefix.unwrap_or(32).min(32);
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(address) & mask == u32::from(remote) & mask
+        }
+        (IpAddr::V6(address), IpAddr::V6(remote)) => {
+            let prefix = prefix.unwrap_or(128).min(128);
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(address) & mask == u128::from(remote) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            anonymous: AuthRole::None,
+            logs: AuthRole::Admin,
+            tokens: BTreeMap::from([
+                (
+                    "grafana".to_string(),
+                    AuthTokenConfig {
+                        token: "s3cret".to_string(),
+                        role: AuthRole::Read,
+                    },
+                ),
+                (
+                    "deploy".to_string(),
+                    AuthTokenConfig {
+                        token: "t0ken".to_string(),
+                        role: AuthRole::Admin,
+                    },
+                ),
+            ]),
+            users: BTreeMap::from([(
+                "alice".to_string(),
+                AuthUserConfig {
+                    password: bcrypt::hash("hunter2", 4).unwrap(),
+                    role: AuthRole::Admin,
+                },
+            )]),
+            proxy: Some(AuthProxyConfig {
+                header: default_proxy_header(),
+                trusted: vec!["127.0.0.1".to_string(), "10.0.0.0/8".to_string()],
+                role: AuthRole::Read,
+            }),
+        }
+    }
+
+    fn headers(headers: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        map
+    }
+
+    fn basic(user: &str, password: &str) -> String {
+        format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"))
+        )
+    }
+
+    fn identity(name: &str, role: AuthRole) -> Result<Identity, AuthError> {
+        Ok(Identity {
+            name: Some(name.to_string()),
+            role,
+        })
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let config = config();
+        let auth = Authenticator::default();
+        let remote = Some(Remote::Ip("192.168.1.10".parse().unwrap()));
+        let authenticate =
+            |h: &[(&'static str, &str)]| auth.authenticate(&config, &headers(h), remote);
+
+        assert_eq!(
+            authenticate(&[]),
+            Ok(Identity {
+                name: None,
+                role: AuthRole::None
+            })
+        );
+        assert_eq!(
+            authenticate(&[("authorization", "Bearer s3cret")]),
+            identity("grafana", AuthRole::Read)
+        );
+        assert_eq!(
+            authenticate(&[("authorization", "bearer t0ken")]),
+            identity("deploy", AuthRole::Admin)
+        );
+        assert_eq!(
+            authenticate(&[("authorization", "Bearer s3cre")]),
+            Err(AuthError::InvalidCredentials)
+        );
+        assert_eq!(
+            authenticate(&[("authorization", "Digest abc")]),
+            Err(AuthError::UnsupportedScheme)
+        );
+
+        // Twice, so that the cached path is taken too
+        for _ in 0..2 {
+            assert_eq!(
+                authenticate(&[("authorization", &basic("alice", "hunter2"))]),
+                identity("alice", AuthRole::Admin)
+            );
+        }
+        assert_eq!(
+            authenticate(&[("authorization", &basic("alice", "hunter3"))]),
+            Err(AuthError::InvalidCredentials)
+        );
+        assert_eq!(
+            authenticate(&[("authorization", &basic("bob", "hunter2"))]),
+            Err(AuthError::InvalidCredentials)
+        );
+        assert_eq!(
+            authenticate(&[("authorization", "Basic !!!")]),
+            Err(AuthError::InvalidCredentials)
+        );
+
+        // The proxy header is ignored unless the request came from a trusted proxy
+        assert_eq!(
+            authenticate(&[("x-forwarded-user", "alice")]),
+            Ok(Identity {
+                name: None,
+                role: AuthRole::None
+            })
+        );
+    }
+
+    #[test]
+    fn test_authenticate_proxy() {
+        let config = config();
+        let auth = Authenticator::default();
+        let from = |remote: &str, user: &str| {
+            auth.authenticate(
+                &config,
+                &headers(&[("x-forwarded-user", user)]),
+                Some(Remote::Ip(remote.parse().unwrap())),
+            )
+        };
+
+        assert_eq!(from("127.0.0.1", "bob"), identity("bob", AuthRole::Read));
+        assert_eq!(
+            from("10.1.2.3", "alice"),
+            identity("alice", AuthRole::Admin)
+        );
+        assert_eq!(
+            from("::ffff:10.1.2.3", "bob"),
+            identity("bob", AuthRole::Read)
+        );
+        assert_eq!(from("11.1.2.3", "alice").unwrap().role, AuthRole::None);
+        assert_eq!(from("127.0.0.1", "").unwrap().role, AuthRole::None);
+    }
+
+    #[test]
+    fn test_address_matches() {
+        let ip = |ip: &str| Remote::Ip(ip.parse().unwrap());
+        assert!(address_matches("127.0.0.1", ip("127.0.0.1")));
+        assert!(!address_matches("127.0.0.1", ip("127.0.0.2")));
+        assert!(address_matches("172.16.0.0/12", ip("172.31.255.255")));
+        assert!(!address_matches("172.16.0.0/12", ip("172.32.0.0")));
+        assert!(address_matches("0.0.0.0/0", ip("8.8.8.8")));
+        assert!(address_matches("fd00::/8", ip("fd12::1")));
+        assert!(!address_matches("fd00::/8", ip("fe80::1")));
+        assert!(!address_matches("::1", ip("127.0.0.1")));
+        assert!(!address_matches("garbage", ip("127.0.0.1")));
+        assert!(address_matches("unix", Remote::Unix));
+        assert!(!address_matches("unix", ip("127.0.0.1")));
+        assert!(!address_matches("127.0.0.1", Remote::Unix));
+    }
+
+    #[test]
+    fn test_required_role() {
+        let config = config();
+        assert_eq!(
+            required_role(&config, &Method::GET, "/status.json"),
+            AuthRole::Read
+        );
+        assert_eq!(
+            required_role(&config, &Method::GET, "/log/web"),
+            AuthRole::Admin
+        );
+        assert_eq!(
+            required_role(&config, &Method::POST, "/monitor/web/run"),
+            AuthRole::Admin
+        );
+        assert_eq!(
+            required_role(&config, &Method::POST, "/reload"),
+            AuthRole::Admin
+        );
+    }
+
+    #[test]
+    fn test_can_read_logs() {
+        let config = config();
+        let auth = Authenticator::default();
+        let remote = Some(Remote::Ip("192.168.1.10".parse().unwrap()));
+        let authenticate = |token: &str| {
+            auth.authenticate(
+                &config,
+                &headers(&[("authorization", &format!("Bearer {token}"))]),
+                remote,
+            )
+            .unwrap()
+        };
+
+        assert!(!authenticate("s3cret").can_read_logs(&config));
+        assert!(authenticate("t0ken").can_read_logs(&config));
+
+        let config = AuthConfig {
+            logs: AuthRole::Read,
+            ..config.clone()
+        };
+        assert!(authenticate("s3cret").can_read_logs(&config));
+    }
+}
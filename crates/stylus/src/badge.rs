@@ -0,0 +1,158 @@
+use serde::Deserialize;
+
+use crate::status::{MonitorStatus, StatusState};
+
+/// The shape of a badge, named as on shields.io.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BadgeStyle {
+    #[default]
+    Flat,
+    FlatSquare,
+}
+
+/// The text and colour shown for a status, unless the `css.metadata` for the status sets a
+/// `color`.
+pub fn status_message(status: Option<StatusState>) -> (&'static str, &'static str) {
+    match status {
+        None | Some(StatusState::Blank) => ("unknown", "#9f9f9f"),
+        Some(StatusState::Green) => ("up", "#4c1"),
+        Some(StatusState::Yellow) => ("timeout", "#dfb317"),
+        Some(StatusState::Red) => ("down", "#e05d44"),
+        Some(StatusState::Blue) => ("info", "#007ec6"),
+        Some(StatusState::Orange) => ("degraded", "#fe7d37"),
+    }
+}
+
+/// An approximation of the width of text in 11px Verdana, which badges are drawn with.
+fn text_width(text: &str) -> f32 {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | '.' | ',' | ':' | ';' | '|' | '!' | '\'' => 3.5,
+            'f' | 'r' | 't' | ' ' | '-' | '(' | ')' | '[' | ']' | '/' => 4.5,
+            'm' | 'w' | 'M' | 'W' => 10.0,
+            'A'..='Z' => 7.5,
+            _ => 6.8,
+        })
+        .sum()
+}
+
+/// Is this a light `#rgb` or `#rrggbb` colour, which needs dark text?
+pub fn is_light(color: &str) -> bool {
+    let hex = color.trim_start_matches('#');
+    let channel = |i: usize, len: usize| {
+        u8::from_str_radix(hex.get(i * len..(i + 1) * len)?, 16)
+            .ok()
+            .map(|v| if len == 1 { v * 17 } else { v } as f32)
+    };
+    let len = match hex.len() {
+        3 => 1,
+        6 => 2,
+        _ => return false,
+    };
+    match (channel(0, len), channel(1, len), channel(2, len)) {
+        (Some(r), Some(g), Some(b)) => (0.299 * r + 0.587 * g + 0.114 * b) / 255.0 > 0.69,
+        _ => false,
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render a badge with a grey label on the left and a coloured message on the right.
+pub fn render(label: &str, message: &str, color: &str, style: BadgeStyle) -> String {
+    let label_width = (text_width(label) + 10.0).round();
+    let message_width = (text_width(message) + 10.0).round();
+    let width = label_width + message_width;
+    let (label, message, color) = (escape(label), escape(message), escape(color));
+    let (radius, gradient) = match style {
+        BadgeStyle::Flat => (
+            3,
+            r##"<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>"##,
+        ),
+        BadgeStyle::FlatSquare => (0, ""),
+    };
+    let overlay = if gradient.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<rect width="{width}" height="20" fill="url(#s)"/>"#)
+    };
+
+    let text = |x: f32, text: &str, dark: bool| {
+        let (fill, shadow) = if dark {
+            ("#333", "#ccc")
+        } else {
+            ("#fff", "#010101")
+        };
+        let shadow = if style == BadgeStyle::Flat {
+            format!(r#"<text x="{x}" y="15" fill="{shadow}" fill-opacity=".3">{text}</text>"#)
+        } else {
+            String::new()
+        };
+        format!(r#"{shadow}<text x="{x}" y="14" fill="{fill}">{text}</text>"#)
+    };
+    let label_text = text(label_width / 2.0, &label, false);
+    let message_text = text(
+        label_width + message_width / 2.0,
+        &message,
+        is_light(&color),
+    );
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {message}"><title>{label}: {message}</title>{gradient}<clipPath id="r"><rect width="{width}" height="20" rx="{radius}" fill="#fff"/></clipPath><g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="#555"/><rect x="{label_width}" width="{message_width}" height="20" fill="{color}"/>{overlay}</g><g text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">{label_text}{message_text}</g></svg>"##
+    )
+}
+
+/// Render the badge for a monitor or group child's status.
+pub fn status_badge(label: &str, status: &MonitorStatus, style: BadgeStyle) -> String {
+    let (message, default_color) = status_message(status.status);
+    let color = status
+        .css
+        .metadata
+        .get("color")
+        .map(String::as_str)
+        .unwrap_or(default_color);
+    render(label, message, color, style)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_status_badge() {
+        let mut status = MonitorStatus {
+            status: Some(StatusState::Red),
+            ..Default::default()
+        };
+        let svg = status_badge("nas <1>", &status, BadgeStyle::Flat);
+        assert!(svg.contains(r#"aria-label="nas &lt;1&gt;: down""#), "{svg}");
+        assert!(svg.contains(r##"fill="#e05d44""##), "{svg}");
+        assert!(svg.contains(r##"fill="#fff">down</text>"##), "{svg}");
+
+        status.css.metadata = Arc::new(BTreeMap::from([(
+            "color".to_string(),
+            "#d0e6a5".to_string(),
+        )]));
+        let svg = status_badge("nas", &status, BadgeStyle::FlatSquare);
+        assert!(svg.contains(r##"fill="#d0e6a5""##), "{svg}");
+        assert!(svg.contains(r##"fill="#333">down</text>"##), "{svg}");
+        assert!(svg.contains(r#"rx="0""#), "{svg}");
+    }
+
+    #[test]
+    fn test_is_light() {
+        assert!(is_light("#fff"));
+        assert!(is_light("#d0e6a5"));
+        assert!(!is_light("#4c1"));
+        assert!(!is_light("#555555"));
+        assert!(!is_light("red"));
+    }
+}
@@ -145,6 +145,7 @@ fn canonicalize(
 
 pub fn parse_config_string(file: &Path, s: String) -> Result<Config, Box<dyn Error>> {
     let mut config: Config = serde_yaml_ng::from_str(&s)?;
+    config.config_file = file.into();
     if Iterator::count(config.base_path.components()) == 0 {
         config.base_path = Path::parent(Path::new(&file))
             .ok_or("Failed to get base path")?
@@ -203,6 +204,64 @@ pub fn parse_config_string(file: &Path, s: String) -> Result<Config, Box<dyn Err
         Some(&config.base_path),
         &mut config.monitor.dir,
     )?;
+    if let Some(state_file) = &mut config.monitor.state.file {
+        *state_file = config.base_path.join(&state_file);
+    }
+    if let Some(history_dir) = &mut config.monitor.history.dir {
+        *history_dir = config.base_path.join(&history_dir);
+    }
+    for (name, view) in &config.views {
+        view.validate(name)?;
+    }
+    for listener in &mut config.server.listeners {
+        if let Some(unix) = &mut listener.unix {
+            *unix = config.base_path.join(&unix);
+        }
+        if let Some(tls) = &mut listener.tls {
+            tls.cert = config.base_path.join(&tls.cert);
+            tls.key = config.base_path.join(&tls.key);
+        }
+        listener.validate()?;
+        if let Some(view) = &listener.view {
+            if !config.views.contains_key(view) {
+                return Err(
+                    format!("Listener {} refers to an unknown view {}", listener, view).into(),
+                );
+            }
+        }
+    }
+
+    for (name, display) in &config.displays {
+        display
+            .validate()
+            .map_err(|e| format!("Display {}: {}", name, e))?;
+        if let Some(view) = &display.view {
+            if !config.views.contains_key(view) {
+                return Err(format!("Display {} refers to an unknown view {}", name, view).into());
+            }
+        }
+    }
+
+    resolve_hooks(&config.base_path, &mut config.notify.hooks);
+
+    for route in &config.notify.routes {
+        for target in route.targets.iter().chain(&route.escalate_to) {
+            if !config.notify.targets.contains_key(target) {
+                return Err(format!("Unknown notification target: {}", target).into());
+            }
+        }
+        if route.escalate_after.is_some() == route.escalate_to.is_empty() {
+            return Err("escalate_after and escalate_to must be specified together".into());
+        }
+        // Escalating before the first notification would skip straight past the usual targets
+        if let (Some(notify_after), Some(escalate_after)) =
+            (route.notify_after, route.escalate_after)
+        {
+            if escalate_after < notify_after {
+                return Err("escalate_after may not be shorter than notify_after".into());
+            }
+        }
+    }
 
     Ok(config)
 }
@@ -229,7 +288,8 @@ pub fn parse_monitor_configs(root: &Path) -> Result<Vec<MonitorDirConfig>, Box<d
             let mut p = e.into_path();
             p.push("config.yaml");
             if p.exists() {
-                monitor_configs.push(parse_monitor_config(&p)?);
+                monitor_configs
+                    .push(parse_monitor_config(&p).map_err(|e| format!("{}: {}", p.display(), e))?);
                 info!("Found monitor in {:?}", p);
             } else {
                 debug!("Ignoring {:?} as there was no config.yaml", p);
@@ -250,6 +310,24 @@ pub fn parse_monitor_configs(root: &Path) -> Result<Vec<MonitorDirConfig>, Box<d
     }
 }
 
+/// Resolve hook commands that are executables relative to `base_path`. Anything else is left
+/// as-is to be run as a shell command line.
+fn resolve_hooks(base_path: &Path, hooks: &mut HooksConfig) {
+    hooks.base_path = base_path.to_owned();
+    for command in [
+        &mut hooks.on_change,
+        &mut hooks.on_fail,
+        &mut hooks.on_recover,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if let Ok(executable) = base_path.join(&command).canonicalize() {
+            *command = executable;
+        }
+    }
+}
+
 pub fn parse_monitor_config(file: &Path) -> Result<MonitorDirConfig, Box<dyn Error>> {
     let s = std::fs::read_to_string(file)?;
     parse_monitor_config_string(file, s)
@@ -277,6 +355,8 @@ pub fn parse_monitor_config_string(
             .to_string();
     }
 
+    resolve_hooks(&config.base_path, &mut config.hooks);
+
     let test = config.root.test_mut();
     let executable = config.base_path.join(&test.command);
     if executable.exists() {
@@ -328,6 +408,21 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn path_prefix_test() {
+        let prefix = |path_prefix: Option<&str>| {
+            ServerConfig {
+                path_prefix: path_prefix.map(str::to_owned),
+                ..Default::default()
+            }
+            .path_prefix()
+        };
+        assert_eq!(prefix(None), None);
+        assert_eq!(prefix(Some("/")), None);
+        assert_eq!(prefix(Some("/status/")), Some("/status".to_owned()));
+        assert_eq!(prefix(Some("home/status")), Some("/home/status".to_owned()));
+    }
+
     #[test]
     fn deserialize_monitor_test() -> Result<(), Box<dyn Error>> {
         let config = parse_monitor_config_string(
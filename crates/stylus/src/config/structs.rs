@@ -1,13 +1,25 @@
 use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
+use crate::auth::AuthConfig;
+use crate::display::DisplayConfig;
+use crate::export::ExporterConfig;
+use crate::listener::ListenerConfig;
 use crate::monitor::MonitorMessageProcessor;
+use crate::monitors::mqtt::MqttMonitorConfig;
 use crate::monitors::ping::PingMonitorConfig;
+use crate::monitors::prometheus::PrometheusMonitorConfig;
 use crate::monitors::snmp::SnmpNetworkMonitorConfig;
+use crate::mqtt::MqttConfig;
+use crate::notify::smtp::SmtpTargetConfig;
+use crate::notify::webhook::WebhookTargetConfig;
+use crate::status::StatusState;
+use crate::view::ViewConfig;
 
 pub enum OperationMode {
     Run(Config, bool),
@@ -32,28 +44,89 @@ fn default_monitor_dir() -> PathBuf {
     "monitor.d".into()
 }
 
+fn default_state_file() -> Option<PathBuf> {
+    Some("state.json".into())
+}
+
+fn default_state_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_history_dir() -> Option<PathBuf> {
+    Some("history".into())
+}
+
+fn default_history_retention() -> Duration {
+    Duration::from_secs(31 * 24 * 60 * 60)
+}
+
+fn default_events_capacity() -> usize {
+    1000
+}
+
+fn default_events_log_lines() -> usize {
+    20
+}
+
+fn default_hook_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_retry_attempts() -> u32 {
+    3
+}
+
+fn default_retry_backoff() -> Duration {
+    Duration::from_secs(5)
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     pub version: u32,
     pub server: ServerConfig,
+    /// Who may use the HTTP server. Without this, the server is open to anyone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthConfig>,
+    /// Named, filtered views of the status, each served under its own path.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub views: BTreeMap<String, ViewConfig>,
+    /// Named dashboards rendered to PNG images, for e-ink frames and other low-power displays.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub displays: BTreeMap<String, DisplayConfig>,
     #[serde(default)]
     pub monitor: MonitorConfig,
     #[serde(default)]
     pub css: CssConfig,
+    #[serde(default, skip_serializing_if = "NotifyConfig::is_empty")]
+    pub notify: NotifyConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mqtt: Option<MqttConfig>,
+    #[serde(default, skip_serializing_if = "default")]
+    pub metrics: MetricsConfig,
+    /// Named targets that the result of every run is pushed to.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub exporters: BTreeMap<String, ExporterConfig>,
     #[serde(default, skip_serializing_if = "default")]
     pub base_path: PathBuf,
     #[serde(default, skip_serializing_if = "default")]
     pub ui: Option<serde_value::Value>,
     #[serde(default, skip_serializing_if = "default")]
     pub config_d: HashMap<String, serde_value::Value>,
+    /// The `config.yaml` this configuration was loaded from.
+    #[serde(skip)]
+    pub config_file: PathBuf,
 }
 
 fn default<T: Default + PartialEq>(t: &T) -> bool {
     *t == Default::default()
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct ServerConfig {
     #[serde(default = "default_server_port")]
@@ -62,6 +135,13 @@ pub struct ServerConfig {
     pub listen_addr: String,
     #[serde(default, rename = "static")]
     pub static_path: Option<PathBuf>,
+    /// The addresses to listen on. If empty, the server listens on `listen_addr` and `port`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<ListenerConfig>,
+    /// The URL path that every route is served under, for hosting behind a reverse proxy that
+    /// doesn't strip it (eg: `/status/`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
 }
 
 impl Default for ServerConfig {
@@ -70,6 +150,35 @@ impl Default for ServerConfig {
             port: default_server_port(),
             listen_addr: default_listen_addr(),
             static_path: Some(default_server_static()),
+            listeners: vec![],
+            path_prefix: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The configured listeners, or a single one on `listen_addr` and `port`.
+    pub fn listeners(&self) -> Result<Vec<ListenerConfig>, String> {
+        if !self.listeners.is_empty() {
+            return Ok(self.listeners.clone());
+        }
+        let ip_addr = self
+            .listen_addr
+            .parse::<IpAddr>()
+            .map_err(|e| format!("Invalid listen address {}: {}", self.listen_addr, e))?;
+        Ok(vec![ListenerConfig {
+            address: Some(SocketAddr::new(ip_addr, self.port)),
+            ..Default::default()
+        }])
+    }
+
+    /// The path prefix without a trailing slash, or `None` if the server is at the root.
+    pub fn path_prefix(&self) -> Option<String> {
+        let prefix = self.path_prefix.as_deref()?.trim_matches('/');
+        if prefix.is_empty() {
+            None
+        } else {
+            Some(format!("/{prefix}"))
         }
     }
 }
@@ -79,12 +188,290 @@ impl Default for ServerConfig {
 pub struct MonitorConfig {
     #[serde(default = "default_monitor_dir")]
     pub dir: PathBuf,
+    #[serde(default)]
+    pub state: MonitorStateConfig,
+    #[serde(default)]
+    pub history: MonitorHistoryConfig,
+    #[serde(default)]
+    pub events: MonitorEventsConfig,
 }
 
 impl Default for MonitorConfig {
     fn default() -> Self {
         Self {
             dir: default_monitor_dir(),
+            state: Default::default(),
+            history: Default::default(),
+            events: Default::default(),
+        }
+    }
+}
+
+/// Configures where the monitor state is persisted across restarts.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorStateConfig {
+    /// The state file, relative to the base path. Set to `null` to disable persistence.
+    #[serde(default = "default_state_file")]
+    pub file: Option<PathBuf>,
+    #[serde(with = "humantime_serde", default = "default_state_interval")]
+    pub interval: Duration,
+}
+
+/// Configures the status history store.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorHistoryConfig {
+    /// The history directory, relative to the base path. Set to `null` to disable history.
+    #[serde(default = "default_history_dir")]
+    pub dir: Option<PathBuf>,
+    /// How long history is kept for.
+    #[serde(with = "humantime_serde", default = "default_history_retention")]
+    pub retention: Duration,
+    /// Metadata keys that are recorded alongside each result.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub metadata: Vec<String>,
+}
+
+impl Default for MonitorHistoryConfig {
+    fn default() -> Self {
+        Self {
+            dir: default_history_dir(),
+            retention: default_history_retention(),
+            metadata: vec![],
+        }
+    }
+}
+
+/// Configures the in-memory log of status changes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorEventsConfig {
+    /// The number of events that are kept.
+    #[serde(default = "default_events_capacity")]
+    pub capacity: usize,
+    /// The number of lines of the run log included with each event.
+    #[serde(default = "default_events_log_lines")]
+    pub log_lines: usize,
+}
+
+impl Default for MonitorEventsConfig {
+    fn default() -> Self {
+        Self {
+            capacity: default_events_capacity(),
+            log_lines: default_events_log_lines(),
+        }
+    }
+}
+
+impl Default for MonitorStateConfig {
+    fn default() -> Self {
+        Self {
+            file: default_state_file(),
+            interval: default_state_interval(),
+        }
+    }
+}
+
+/// Configures the Prometheus metrics served from `/metrics`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Whether numeric metadata values are exported.
+    #[serde(default = "default_true")]
+    pub metadata: bool,
+    /// The metadata keys to export. All numeric metadata is exported if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub metadata_include: Vec<String>,
+    /// Metadata keys that are never exported.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub metadata_exclude: Vec<String>,
+}
+
+impl MetricsConfig {
+    /// Should this metadata key be exported?
+    pub fn is_exported(&self, key: &str) -> bool {
+        self.metadata
+            && (self.metadata_include.is_empty() || self.metadata_include.iter().any(|k| k == key))
+            && !self.metadata_exclude.iter().any(|k| k == key)
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            metadata: true,
+            metadata_include: vec![],
+            metadata_exclude: vec![],
+        }
+    }
+}
+
+/// Configures where status changes are sent.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// Named notification targets.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub targets: BTreeMap<String, NotifyTargetConfig>,
+    /// Rules selecting which status changes are sent to which targets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<NotifyRouteConfig>,
+    /// Commands that are run when any monitor changes status.
+    #[serde(default, skip_serializing_if = "HooksConfig::is_empty")]
+    pub hooks: HooksConfig,
+}
+
+impl NotifyConfig {
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty() && self.routes.is_empty() && self.hooks.is_empty()
+    }
+}
+
+/// Commands that are run when a monitor changes status. Commands are either executables
+/// (relative to the directory containing the configuration) or shell command lines.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Run on every status change, except for the first run of a monitor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_change: Option<PathBuf>,
+    /// Run when the status changes to red, orange or yellow.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_fail: Option<PathBuf>,
+    /// Run when the status changes from red, orange or yellow to any other status.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_recover: Option<PathBuf>,
+    #[serde(with = "humantime_serde", default = "default_hook_timeout")]
+    pub timeout: Duration,
+    /// The directory that commands are run in.
+    #[serde(skip)]
+    pub base_path: PathBuf,
+}
+
+impl HooksConfig {
+    pub fn is_empty(&self) -> bool {
+        self.on_change.is_none() && self.on_fail.is_none() && self.on_recover.is_none()
+    }
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            on_change: None,
+            on_fail: None,
+            on_recover: None,
+            timeout: default_hook_timeout(),
+            base_path: Default::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyTargetConfig {
+    #[serde(flatten)]
+    pub target: NotifyTargetType,
+    #[serde(default)]
+    pub retry: NotifyRetryConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum NotifyTargetType {
+    Webhook(WebhookTargetConfig),
+    Smtp(SmtpTargetConfig),
+}
+
+impl NotifyTargetType {
+    /// How long to wait after a status change for others to send with it.
+    pub fn digest(&self) -> Duration {
+        match self {
+            NotifyTargetType::Webhook(_) => Duration::ZERO,
+            NotifyTargetType::Smtp(smtp) => smtp.digest,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyRouteConfig {
+    /// The targets to notify.
+    pub targets: Vec<String>,
+    /// The monitors or group children this route applies to. Applies to all monitors if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub monitors: Vec<String>,
+    /// The previous statuses this route applies to. Applies to any status other than blank (ie:
+    /// not the first run of a monitor) if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub from: Vec<StatusState>,
+    /// The new statuses this route applies to. Applies to any status if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub to: Vec<StatusState>,
+    /// Only notify if the monitor is still failing after this long.
+    #[serde(
+        with = "humantime_serde",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub notify_after: Option<Duration>,
+    /// Send a reminder this often while the monitor is still failing.
+    #[serde(
+        with = "humantime_serde",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub repeat_every: Option<Duration>,
+    /// Also notify the `escalate_to` targets if the monitor is still failing after this long,
+    /// which may not be before `notify_after`.
+    #[serde(
+        with = "humantime_serde",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub escalate_after: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub escalate_to: Vec<String>,
+    /// Notify when a monitor that was alerted on recovers.
+    #[serde(default = "default_true")]
+    pub recovery: bool,
+}
+
+impl NotifyRouteConfig {
+    /// Does this route have an alert policy, rather than notifying of every status change?
+    pub fn is_alert(&self) -> bool {
+        self.notify_after.is_some() || self.repeat_every.is_some() || self.escalate_after.is_some()
+    }
+
+    /// Is this status a failure for an alert route? This is any status in `to`, or any failing
+    /// status if `to` is empty.
+    pub fn is_failing(&self, status: StatusState) -> bool {
+        if self.to.is_empty() {
+            status.is_failing()
+        } else {
+            self.to.contains(&status)
+        }
+    }
+}
+
+/// Configures how failed deliveries are retried. The delay doubles after every attempt.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyRetryConfig {
+    /// The total number of delivery attempts.
+    #[serde(default = "default_retry_attempts")]
+    pub attempts: u32,
+    /// The delay before the first retry.
+    #[serde(with = "humantime_serde", default = "default_retry_backoff")]
+    pub backoff: Duration,
+}
+
+impl Default for NotifyRetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: default_retry_attempts(),
+            backoff: default_retry_backoff(),
         }
     }
 }
@@ -133,7 +520,7 @@ impl Default for CssMetadataConfig {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MonitorDirConfig {
     #[serde(flatten)]
@@ -142,6 +529,8 @@ pub struct MonitorDirConfig {
     pub base_path: PathBuf,
     #[serde(default, skip_serializing_if = "default")]
     pub id: String,
+    #[serde(default, skip_serializing_if = "HooksConfig::is_empty")]
+    pub hooks: HooksConfig,
 }
 
 impl Default for MonitorDirConfig {
@@ -150,11 +539,12 @@ impl Default for MonitorDirConfig {
             root: MonitorDirRootConfig::Test(MonitorDirTestConfig::default()),
             base_path: Default::default(),
             id: Default::default(),
+            hooks: Default::default(),
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub enum MonitorDirRootConfig {
@@ -162,6 +552,8 @@ pub enum MonitorDirRootConfig {
     Group(MonitorDirGroupConfig),
     Snmp(SnmpNetworkMonitorConfig),
     Ping(PingMonitorConfig),
+    Mqtt(MqttMonitorConfig),
+    Prometheus(PrometheusMonitorConfig),
 }
 
 impl MonitorDirRootConfig {
@@ -176,6 +568,12 @@ impl MonitorDirRootConfig {
             MonitorDirRootConfig::Ping(ref ping) => {
                 ping.test.as_ref().expect("test_mut was not called")
             }
+            MonitorDirRootConfig::Mqtt(ref mqtt) => {
+                mqtt.test.as_ref().expect("test_mut was not called")
+            }
+            MonitorDirRootConfig::Prometheus(ref prometheus) => {
+                prometheus.test.as_ref().expect("test_mut was not called")
+            }
         }
     }
 
@@ -196,11 +594,23 @@ impl MonitorDirRootConfig {
                 }
                 ping.test.as_mut().unwrap()
             }
+            MonitorDirRootConfig::Mqtt(ref mut mqtt) => {
+                if mqtt.test.is_none() {
+                    mqtt.test = Some(mqtt.test());
+                }
+                mqtt.test.as_mut().unwrap()
+            }
+            MonitorDirRootConfig::Prometheus(ref mut prometheus) => {
+                if prometheus.test.is_none() {
+                    prometheus.test = Some(prometheus.test());
+                }
+                prometheus.test.as_mut().unwrap()
+            }
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MonitorDirGroupConfig {
     pub id: String,
@@ -210,7 +620,7 @@ pub struct MonitorDirGroupConfig {
     pub children: BTreeMap<String, MonitorDirChildConfig>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MonitorDirChildConfig {
     pub axes: BTreeMap<String, MonitorDirAxisValue>,
@@ -225,7 +635,7 @@ pub enum MonitorDirAxisValue {
     Number(i64),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MonitorDirAxisConfig {
     pub values: Vec<MonitorDirAxisValue>,
@@ -245,3 +655,13 @@ pub struct MonitorDirTestConfig {
     #[serde(skip)]
     pub processor: Option<Arc<dyn MonitorMessageProcessor>>,
 }
+
+impl PartialEq for MonitorDirTestConfig {
+    /// The processor is built from the rest of the monitor's configuration, so is not compared.
+    fn eq(&self, other: &Self) -> bool {
+        self.interval == other.interval
+            && self.timeout == other.timeout
+            && self.command == other.command
+            && self.args == other.args
+    }
+}
@@ -4,18 +4,30 @@ use crate::status::*;
 
 pub fn generate_css_for_state(config: &CssConfig, status: &Status) -> String {
     let mut css = format!("/* Generated at {:?} */\n", chrono::Utc::now().to_rfc3339());
+    css += &generate_css_rules(config, status);
+    css
+}
+
+/// The css for every monitor, without the time it was generated.
+pub fn generate_css_rules(config: &CssConfig, status: &Status) -> String {
+    let mut css = String::new();
     for monitor in &status.monitors {
         css += "\n";
-        let mut monitor = monitor.write();
-
-        // Build the css from cache
-        let mut cache = monitor.css.take();
-        css += cache.get_or_insert_with(|| generate_css_for_monitor(config, &monitor));
-        monitor.css = cache;
+        css += &cached_css_for_monitor(config, &mut monitor.write());
     }
     css
 }
 
+/// The css for a monitor, which is only regenerated when its state changes.
+pub fn cached_css_for_monitor(config: &CssConfig, monitor: &mut MonitorState) -> String {
+    let mut cache = monitor.css.take();
+    let css = cache
+        .get_or_insert_with(|| generate_css_for_monitor(config, monitor))
+        .clone();
+    monitor.css = cache;
+    css
+}
+
 pub fn generate_css_for_monitor(config: &CssConfig, monitor: &MonitorState) -> String {
     let mut css = format!("/* {} */\n", monitor.id);
 
@@ -35,6 +47,15 @@ pub fn generate_css_for_monitor(config: &CssConfig, monitor: &MonitorState) -> S
         monitor.status.description
     )
     .as_str();
+    if let Some(stale_since) = monitor.status.stale_since {
+        css += format!("  --monitor-stale-since: {:?};\n", stale_since.to_rfc3339()).as_str();
+    }
+    if monitor.paused {
+        css += "  --monitor-paused: 1;\n";
+    }
+    if monitor.status.is_acknowledged() {
+        css += "  --monitor-acknowledged: 1;\n";
+    }
     for (k, v) in monitor.status.metadata.iter() {
         if k.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
             css += format!("  --monitor-metadata-{k}: {};\n", v).as_str();
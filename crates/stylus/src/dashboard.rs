@@ -0,0 +1,532 @@
+use std::collections::BTreeMap;
+
+use handlebars::Handlebars;
+use serde::{Deserialize, Serialize};
+
+use crate::config::MonitorDirAxisValue;
+use crate::status::{MonitorChildStatus, MonitorStatus, Status, StatusState};
+
+/// Groups with more children than this show counts of each status instead of every child, as in
+/// the web UI.
+const MAX_CHILD_INDICATORS: usize = 8;
+
+/// The parts of the `ui` section that the server-rendered dashboard understands. The web UI
+/// reads the same section, so anything else in it is ignored.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct UiConfig {
+    title: Option<String>,
+    description: Option<String>,
+    visualizations: Vec<VisualizationConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct VisualizationConfig {
+    title: String,
+    description: String,
+    #[serde(rename = "type")]
+    kind: String,
+    url: Option<String>,
+    size: Option<String>,
+    stacks: Vec<StackConfig>,
+    columns: Vec<ColumnConfig>,
+}
+
+/// A column of a `row` visualization, which is a visualization of its own.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ColumnConfig {
+    width: Option<f64>,
+    #[serde(flatten)]
+    visualization: VisualizationConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StackConfig {
+    title: String,
+    rows: Vec<StackRowConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StackRowConfig {
+    id: String,
+    layout: String,
+    order: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Page {
+    title: String,
+    description: String,
+    updated: String,
+    logs: bool,
+    visualizations: Vec<Visualization>,
+}
+
+#[derive(Default, Serialize)]
+struct Visualization {
+    title: String,
+    description: String,
+    #[serde(rename = "type")]
+    kind: String,
+    url: Option<String>,
+    size: Option<String>,
+    monitors: Vec<TableRow>,
+    stacks: Vec<Stack>,
+    columns: Vec<Column>,
+}
+
+#[derive(Serialize)]
+struct Column {
+    /// The share of the row's width, as a percentage.
+    width: u32,
+    visualization: Visualization,
+}
+
+#[derive(Serialize)]
+struct TableRow {
+    id: String,
+    status: StatusState,
+    description: String,
+    code: i64,
+    children: Vec<Indicator>,
+    /// Counts of each status, for groups with too many children to show.
+    counts: Vec<Count>,
+}
+
+#[derive(Serialize)]
+struct Count {
+    status: StatusState,
+    count: usize,
+}
+
+#[derive(Clone, Serialize)]
+struct Indicator {
+    id: String,
+    status: StatusState,
+}
+
+#[derive(Serialize)]
+struct Stack {
+    title: String,
+    rows: Vec<StackRow>,
+}
+
+#[derive(Serialize)]
+struct StackRow {
+    id: String,
+    /// Each group is a grid of rows of indicators.
+    groups: Vec<Vec<Vec<Indicator>>>,
+}
+
+/// A layout like `1x5x2`: groups of columns by rows, filled row by row unless it starts with
+/// `~`.
+#[derive(Debug, PartialEq)]
+struct Layout {
+    groups: usize,
+    columns: usize,
+    rows: usize,
+    column_wise: bool,
+}
+
+fn state(status: &MonitorStatus) -> StatusState {
+    status.status.unwrap_or(StatusState::Blank)
+}
+
+/// Parse a stack layout, such as `1x5x2 1x1x2` or `~12x2`.
+fn parse_layout(layout: &str) -> Vec<Layout> {
+    layout
+        .split_whitespace()
+        .map(|group| {
+            let (group, column_wise) = match group.strip_prefix('~') {
+                Some(group) => (group, true),
+                None => (group, false),
+            };
+            let parts: Option<Vec<usize>> = group.split('x').map(|n| n.parse().ok()).collect();
+            let (groups, columns, rows) = match parts.as_deref() {
+                Some(&[groups, columns, rows]) => (groups, columns, rows),
+                Some(&[columns, rows]) => (1, columns, rows),
+                Some(&[columns]) => (1, columns, 1),
+                _ => (1, 1, 1),
+            };
+            Layout {
+                groups,
+                columns,
+                rows,
+                column_wise,
+            }
+        })
+        .collect()
+}
+
+/// Parse a stack order of indexes and ranges, such as `15-22 12 4-3`. Reversed ranges count
+/// down.
+fn parse_order(order: &str) -> Vec<i64> {
+    let mut result = vec![];
+    for part in order.split_whitespace() {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) else {
+                    continue;
+                };
+                if start <= end {
+                    result.extend(start..=end);
+                } else {
+                    result.extend((end..=start).rev());
+                }
+            }
+            None => result.extend(part.parse::<i64>()),
+        }
+    }
+    result
+}
+
+fn axis_index(child: &MonitorChildStatus) -> Option<i64> {
+    match child.axes.get("index") {
+        Some(MonitorDirAxisValue::Number(index)) => Some(*index),
+        _ => None,
+    }
+}
+
+/// The children of a group in stack order: by their `index` axis, then by id.
+fn stack_children(
+    children: &BTreeMap<String, MonitorChildStatus>,
+    order: Option<&str>,
+) -> Vec<Indicator> {
+    let mut children: Vec<_> = children.iter().collect();
+    children.sort_by(
+        |(a_id, a), (b_id, b)| match (axis_index(a), axis_index(b)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => a_id.cmp(b_id),
+        },
+    );
+    let children: Vec<_> = children
+        .into_iter()
+        .enumerate()
+        .map(|(position, (id, child))| {
+            let index = axis_index(child).unwrap_or(position as i64 + 1);
+            let indicator = Indicator {
+                id: id.clone(),
+                status: state(&child.status),
+            };
+            (index, indicator)
+        })
+        .collect();
+    match order {
+        Some(order) => parse_order(order)
+            .into_iter()
+            .filter_map(|index| children.iter().find(|(i, _)| *i == index))
+            .map(|(_, indicator)| indicator.clone())
+            .collect(),
+        None => children
+            .into_iter()
+            .map(|(_, indicator)| indicator)
+            .collect(),
+    }
+}
+
+/// Lay the children out into the groups of a stack row. Missing children are left blank.
+fn stack_groups(children: &[Indicator], layout: &str) -> Vec<Vec<Vec<Indicator>>> {
+    let blank = Indicator {
+        id: String::new(),
+        status: StatusState::Blank,
+    };
+    let mut start = 0;
+    let mut groups = vec![];
+    for layout in parse_layout(layout) {
+        for _ in 0..layout.groups {
+            let group = (0..layout.rows)
+                .map(|row| {
+                    (0..layout.columns)
+                        .map(|column| {
+                            let offset = if layout.column_wise {
+                                column * layout.rows + row
+                            } else {
+                                row * layout.columns + column
+                            };
+                            children
+                                .get(start + offset)
+                                .cloned()
+                                .unwrap_or_else(|| blank.clone())
+                        })
+                        .collect()
+                })
+                .collect();
+            groups.push(group);
+            start += layout.columns * layout.rows;
+        }
+    }
+    groups
+}
+
+fn table(status: &Status) -> Vec<TableRow> {
+    status
+        .monitors
+        .iter()
+        .map(|monitor| {
+            let monitor = monitor.read();
+            let mut children = vec![];
+            let mut counts: Vec<Count> = vec![];
+            for (id, child) in &monitor.children {
+                let indicator = Indicator {
+                    id: id.clone(),
+                    status: state(&child.status),
+                };
+                if monitor.children.len() <= MAX_CHILD_INDICATORS
+                    || indicator.status == StatusState::Red
+                {
+                    children.push(indicator);
+                } else if let Some(count) = counts.iter_mut().find(|c| c.status == indicator.status)
+                {
+                    count.count += 1;
+                } else {
+                    counts.push(Count {
+                        status: indicator.status,
+                        count: 1,
+                    });
+                }
+            }
+            TableRow {
+                id: monitor.id.clone(),
+                status: state(&monitor.status),
+                description: monitor.status.description.clone(),
+                code: monitor.status.code,
+                children,
+                counts,
+            }
+        })
+        .collect()
+}
+
+fn visualization(config: &VisualizationConfig, status: &Status) -> Visualization {
+    let mut visualization = Visualization {
+        title: config.title.clone(),
+        description: config.description.clone(),
+        kind: config.kind.clone(),
+        url: config.url.clone(),
+        size: config.size.clone(),
+        ..Default::default()
+    };
+    match config.kind.as_str() {
+        "table" => visualization.monitors = table(status),
+        "stack" => {
+            visualization.stacks = config
+                .stacks
+                .iter()
+                .map(|stack| Stack {
+                    title: stack.title.clone(),
+                    rows: stack
+                        .rows
+                        .iter()
+                        .map(|row| {
+                            let children = status
+                                .monitors
+                                .iter()
+                                .find(|m| m.read().id == row.id)
+                                .map(|m| stack_children(&m.read().children, row.order.as_deref()))
+                                .unwrap_or_default();
+                            StackRow {
+                                id: row.id.clone(),
+                                groups: stack_groups(&children, &row.layout),
+                            }
+                        })
+                        .collect(),
+                })
+                .collect()
+        }
+        "row" => {
+            let total: f64 = config
+                .columns
+                .iter()
+                .map(|column| column.width.unwrap_or(1.0))
+                .sum();
+            visualization.columns = config
+                .columns
+                .iter()
+                .map(|column| Column {
+                    width: (column.width.unwrap_or(1.0) / total * 100.0).round() as u32,
+                    visualization: self::visualization(&column.visualization, status),
+                })
+                .collect();
+        }
+        _ => {}
+    }
+    visualization
+}
+
+/// Render the dashboard as plain HTML, for browsers that can't run the web UI. The `table`,
+/// `row`, `stack` and `iframe` visualizations of the `ui` section are supported, and a table of
+/// every monitor is shown if there are none.
+pub fn render(
+    ui: Option<&serde_value::Value>,
+    status: &Status,
+    logs: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let ui = match ui {
+        Some(ui) => UiConfig::deserialize(ui.clone())?,
+        None => UiConfig::default(),
+    };
+    let mut visualizations: Vec<_> = ui
+        .visualizations
+        .iter()
+        .map(|config| visualization(config, status))
+        .collect();
+    if visualizations.is_empty() {
+        visualizations.push(Visualization {
+            kind: "table".into(),
+            monitors: table(status),
+            ..Default::default()
+        });
+    }
+
+    let mut handlebars = Handlebars::new();
+    handlebars.register_template_string("index", include_str!("./index.html"))?;
+    handlebars.register_partial("visualization", include_str!("./visualization.html"))?;
+    let page = Page {
+        title: ui.title.unwrap_or_else(|| "Stylus Monitor".into()),
+        description: ui.description.unwrap_or_else(|| "Monitor Dashboard".into()),
+        updated: chrono::Utc::now()
+            .format("%Y-%m-%d %H:%M:%S UTC")
+            .to_string(),
+        logs,
+        visualizations,
+    };
+    Ok(handlebars.render("index", &page)?.trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use keepcalm::SharedMut;
+
+    use super::*;
+    use crate::status::MonitorState;
+
+    fn status() -> Status {
+        let mut monitor = MonitorState::new_test("switch");
+        monitor.status.status = Some(StatusState::Green);
+        monitor.status.description = "<Success>".into();
+        for index in 1..=10 {
+            monitor.children.insert(
+                format!("switch-{index:02}"),
+                MonitorChildStatus {
+                    axes: BTreeMap::from([("index".into(), MonitorDirAxisValue::Number(index))]),
+                    status: MonitorStatus {
+                        status: Some(if index == 3 {
+                            StatusState::Red
+                        } else {
+                            StatusState::Green
+                        }),
+                        ..Default::default()
+                    },
+                },
+            );
+        }
+        Status {
+            monitors: vec![SharedMut::new(monitor)],
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_order("4-6 1 3-2 x"), vec![4, 5, 6, 1, 3, 2]);
+        assert_eq!(
+            parse_layout("1x5x2 ~3x1 4"),
+            vec![
+                Layout {
+                    groups: 1,
+                    columns: 5,
+                    rows: 2,
+                    column_wise: false
+                },
+                Layout {
+                    groups: 1,
+                    columns: 3,
+                    rows: 1,
+                    column_wise: true
+                },
+                Layout {
+                    groups: 1,
+                    columns: 4,
+                    rows: 1,
+                    column_wise: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack() {
+        let status = status();
+        let monitor = status.monitors[0].read();
+        let ids = |groups: Vec<Vec<Vec<Indicator>>>| -> Vec<Vec<Vec<String>>> {
+            groups
+                .into_iter()
+                .map(|g| {
+                    g.into_iter()
+                        .map(|r| r.into_iter().map(|i| i.id).collect())
+                        .collect()
+                })
+                .collect()
+        };
+
+        let children = stack_children(&monitor.children, Some("2-1 10"));
+        assert_eq!(
+            ids(stack_groups(&children, "~2x2")),
+            vec![vec![vec!["switch-02", "switch-10"], vec!["switch-01", ""]]]
+        );
+        let children = stack_children(&monitor.children, None);
+        assert_eq!(
+            ids(stack_groups(&children, "2x1x2")),
+            vec![
+                vec![vec!["switch-01"], vec!["switch-02"]],
+                vec![vec!["switch-03"], vec!["switch-04"]]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let ui = serde_value::to_value(serde_json::json!({
+            "title": "Home",
+            "visualizations": [
+                { "title": "Monitors", "type": "table" },
+                { "title": "Layout", "type": "row", "columns": [
+                    { "type": "stack", "width": 3, "stacks": [
+                        { "title": "Rack", "rows": [{ "id": "switch", "layout": "5x2" }] }
+                    ] },
+                    { "type": "iframe", "url": "iframe.html?a=1&b=2" },
+                    { "type": "isoflow", "config": "isoflow" }
+                ] }
+            ]
+        }))
+        .unwrap();
+        let html = render(Some(&ui), &status(), true).unwrap();
+        assert!(html.contains("<title>Home</title>"), "{html}");
+        assert!(
+            html.contains(r#"<a href="log/switch">switch</a>"#),
+            "{html}"
+        );
+        assert!(html.contains("&lt;Success&gt;"), "{html}");
+        // Too many children to show, so only the failed one is shown separately
+        assert!(
+            html.contains(r#"9 <span class="indicator small status-green">"#),
+            "{html}"
+        );
+        assert!(html.contains(r#"title="switch-03""#), "{html}");
+        assert!(html.contains(r#"width="60%""#), "{html}");
+        assert!(
+            html.contains(r#"src="iframe.html?a&#x3D;1&amp;b&#x3D;2""#),
+            "{html}"
+        );
+        assert!(html.contains("needs JavaScript"), "{html}");
+        assert!(!html.contains("<script"), "{html}");
+
+        let html = render(None, &status(), false).unwrap();
+        assert!(html.contains("<h1>Stylus Monitor</h1>"), "{html}");
+        assert!(html.contains(r#"<tr data-monitor-id="switch">"#), "{html}");
+        assert!(!html.contains("log/switch"), "{html}");
+    }
+}
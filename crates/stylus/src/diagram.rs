@@ -0,0 +1,254 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Component, Path, PathBuf};
+
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesCData, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+use crate::config::{Config, MonitorDirTestConfig};
+use crate::interpolate::interpolate_monitor;
+use crate::status::{MonitorStatus, Status};
+
+/// The attribute that ties an element, and everything inside it, to a monitor or group child.
+const MONITOR_ID: &[u8] = b"data-monitor-id";
+
+/// Find an SVG diagram in the static directory or, failing that, `config.d`. Diagrams in
+/// `config.d` are only found if `config_d` allows their name.
+pub fn find(config: &Config, file: &str, config_d: impl Fn(&str) -> bool) -> Option<PathBuf> {
+    // Only relative paths to SVG files, which can't escape the directories
+    let path = Path::new(file);
+    if path.extension().is_none_or(|extension| extension != "svg")
+        || !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    let static_file = config
+        .server
+        .static_path
+        .as_ref()
+        .map(|static_path| static_path.join(path))
+        .filter(|full_path| full_path.is_file());
+    static_file.or_else(|| {
+        Some(config.base_path.join("config.d").join(path)).filter(|full_path| {
+            full_path.is_file()
+                && path
+                    .file_stem()
+                    .is_some_and(|name| config_d(&name.to_string_lossy()))
+        })
+    })
+}
+
+/// Render an SVG diagram with the current status, for viewers that can't run the web UI. Elements
+/// tagged with `data-monitor-id` are filled with the status colour, unless they have their own
+/// `fill`, and templates in the text and attributes of a tagged element are rendered for that
+/// monitor, as for CSS rules. The CSS is also embedded in a `<style>` element, for viewers that
+/// support it.
+pub fn render(svg: &str, css: &str, status: &Status) -> Result<String, Box<dyn Error>> {
+    let mut monitors: BTreeMap<String, (MonitorDirTestConfig, MonitorStatus)> = BTreeMap::new();
+    for monitor in &status.monitors {
+        let monitor = monitor.read();
+        for (id, child) in &monitor.children {
+            monitors.insert(id.clone(), (monitor.config.clone(), child.status.clone()));
+        }
+        monitors.insert(
+            monitor.id.clone(),
+            (monitor.config.clone(), monitor.status.clone()),
+        );
+    }
+    let interpolate = |id: &str, raw: &[u8]| -> Option<Vec<u8>> {
+        let raw = std::str::from_utf8(raw).ok()?;
+        if !raw.contains("{{") {
+            return None;
+        }
+        let (config, status) = monitors.get(id)?;
+        match interpolate_monitor(id, config, status, raw) {
+            Ok(rendered) => Some(rendered.into_bytes()),
+            Err(e) => {
+                warn!("[{}] Failed to render diagram template: {}", id, e);
+                None
+            }
+        }
+    };
+
+    let mut reader = Reader::from_str(svg);
+    reader.config_mut().check_end_names = false;
+    let mut writer = Writer::new(Vec::with_capacity(svg.len() + css.len()));
+    // The monitor that each open element belongs to, if any
+    let mut stack: Vec<Option<String>> = vec![];
+    let mut styled = false;
+
+    loop {
+        match reader.read_event()? {
+            Event::Eof => break,
+            event @ (Event::Start(_) | Event::Empty(_)) => {
+                let (element, is_start) = match event {
+                    Event::Start(element) => (element, true),
+                    Event::Empty(element) => (element, false),
+                    _ => unreachable!(),
+                };
+                let own_id = element
+                    .try_get_attribute(MONITOR_ID)?
+                    .map(|attr| String::from_utf8_lossy(&attr.value).into_owned());
+                // Attributes work in viewers that ignore the embedded CSS, and are inherited by
+                // children without a fill of their own
+                let color = own_id
+                    .as_ref()
+                    .and_then(|id| monitors.get(id))
+                    .and_then(|(_, status)| status.css.metadata.get("color"));
+                let id = own_id.or_else(|| stack.last().cloned().flatten());
+                let mut element = match &id {
+                    Some(id) => interpolate_attributes(&element, |raw| interpolate(id, raw))?,
+                    None => element,
+                };
+                if let Some(color) = color {
+                    if element.try_get_attribute("fill")?.is_none() {
+                        element.push_attribute(("fill", color.as_str()));
+                    }
+                }
+                let is_root = !styled && element.local_name().as_ref() == b"svg";
+                if is_start {
+                    writer.write_event(Event::Start(element))?;
+                    stack.push(id);
+                    if is_root {
+                        writer.write_event(Event::Start(BytesStart::new("style")))?;
+                        for css in BytesCData::escaped(css) {
+                            writer.write_event(Event::CData(css))?;
+                        }
+                        writer.write_event(Event::End(BytesEnd::new("style")))?;
+                        styled = true;
+                    }
+                } else {
+                    writer.write_event(Event::Empty(element))?;
+                }
+            }
+            Event::End(element) => {
+                stack.pop();
+                writer.write_event(Event::End(element))?;
+            }
+            Event::Text(text) => {
+                let rendered = match stack.last().cloned().flatten() {
+                    Some(id) => interpolate(&id, &text),
+                    None => None,
+                };
+                match rendered {
+                    Some(rendered) => writer.write_event(Event::Text(BytesText::from_escaped(
+                        String::from_utf8(rendered)?,
+                    )))?,
+                    None => writer.write_event(Event::Text(text))?,
+                }
+            }
+            event => writer.write_event(event)?,
+        }
+    }
+
+    if !styled {
+        return Err("Not an SVG document".into());
+    }
+    Ok(String::from_utf8(writer.into_inner())?)
+}
+
+/// A copy of an element with templates in its attributes rendered. Attribute values are kept
+/// escaped, and the rendered values are escaped by the template engine.
+fn interpolate_attributes<'a>(
+    element: &BytesStart<'a>,
+    interpolate: impl Fn(&[u8]) -> Option<Vec<u8>>,
+) -> Result<BytesStart<'a>, Box<dyn Error>> {
+    let mut rendered = element.clone();
+    rendered.clear_attributes();
+    for attr in element.attributes() {
+        let attr = attr?;
+        let value = match interpolate(&attr.value) {
+            Some(value) => Cow::Owned(value),
+            None => attr.value,
+        };
+        rendered.push_attribute(Attribute {
+            key: attr.key,
+            value,
+        });
+    }
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use keepcalm::SharedMut;
+
+    use super::*;
+    use crate::status::{MonitorChildStatus, MonitorCssStatus, MonitorState, StatusState};
+
+    fn css(color: &str) -> MonitorCssStatus {
+        MonitorCssStatus {
+            metadata: Arc::new(BTreeMap::from([("color".to_string(), color.to_string())])),
+        }
+    }
+
+    fn status() -> Status {
+        let mut monitor = MonitorState::new_test("router");
+        monitor.status = MonitorStatus {
+            status: Some(StatusState::Green),
+            css: css("#d0e6a5"),
+            metadata: BTreeMap::from([("rtt".to_string(), "<12>".to_string())]),
+            ..Default::default()
+        };
+        monitor.children.insert(
+            "router-wan".to_string(),
+            MonitorChildStatus {
+                axes: Default::default(),
+                status: MonitorStatus {
+                    status: Some(StatusState::Red),
+                    css: css("#fa897b"),
+                    ..Default::default()
+                },
+            },
+        );
+        Status {
+            monitors: vec![SharedMut::new(monitor)],
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let svg = r##"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg">
+<g data-monitor-id="router" data-status="{{monitor.status.status}}">
+  <rect fill="#fff"/>
+  <text>RTT {{monitor.status.metadata.rtt}} &amp; more</text>
+  <g data-monitor-id="router-wan"><text>WAN {{monitor.status.status}}</text></g>
+  <text>{{monitor.status.status}}</text>
+</g>
+<rect data-monitor-id="router-wan" fill="{{monitor.status.css.metadata.color}}"/>
+<rect data-monitor-id="router" fill="none"/>
+<text>{{monitor.id}}</text>
+<g data-monitor-id="unknown"><text>{{monitor.id}}</text></g>
+</svg>"##;
+        let rendered = render(svg, "g > rect { fill: red }", &status()).unwrap();
+        assert_eq!(
+            rendered,
+            r##"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg"><style><![CDATA[g > rect { fill: red }]]></style>
+<g data-monitor-id="router" data-status="green" fill="#d0e6a5">
+  <rect fill="#fff"/>
+  <text>RTT &lt;12&gt; &amp; more</text>
+  <g data-monitor-id="router-wan" fill="#fa897b"><text>WAN red</text></g>
+  <text>green</text>
+</g>
+<rect data-monitor-id="router-wan" fill="#fa897b"/>
+<rect data-monitor-id="router" fill="none"/>
+<text>{{monitor.id}}</text>
+<g data-monitor-id="unknown"><text>{{monitor.id}}</text></g>
+</svg>"##
+        );
+    }
+
+    #[test]
+    fn test_render_invalid() {
+        assert!(render("<html></html>", "", &status()).is_err());
+        assert!(render("<svg><g></svg", "", &status()).is_err());
+    }
+}
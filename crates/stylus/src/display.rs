@@ -0,0 +1,512 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+use quick_xml::escape::escape;
+use resvg::tiny_skia::{Color, Pixmap, Transform};
+use resvg::usvg::{self, fontdb};
+use serde::{Deserialize, Serialize};
+
+use crate::badge::{is_light, status_message};
+use crate::status::{MonitorStatus, Status, StatusState};
+
+fn default_width() -> u32 {
+    800
+}
+
+fn default_height() -> u32 {
+    480
+}
+
+fn default_depth() -> u8 {
+    24
+}
+
+fn default_dither() -> bool {
+    true
+}
+
+/// The largest image that a display may be rendered to, in either direction.
+const MAX_SIZE: u32 = 4096;
+
+const FONT_FAMILY: &str = "DejaVu Sans, Arial, Helvetica, sans-serif";
+
+/// A dashboard rendered to a PNG image, for e-ink frames and other displays that can't run the
+/// web UI.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DisplayConfig {
+    /// The width of the image in pixels.
+    #[serde(default = "default_width")]
+    pub width: u32,
+    /// The height of the image in pixels.
+    #[serde(default = "default_height")]
+    pub height: u32,
+    /// How far the dashboard is rotated clockwise within the image, in degrees, for displays
+    /// mounted on their side.
+    #[serde(default)]
+    pub rotate: u16,
+    /// Bits per pixel: 1, 2, 4 or 8 for grayscale, or 24 for colour.
+    #[serde(default = "default_depth")]
+    pub depth: u8,
+    /// Whether grayscale images are dithered, rather than rounded to the nearest shade.
+    #[serde(default = "default_dither")]
+    pub dither: bool,
+    /// An SVG diagram from the static directory or `config.d`, rendered as for `/render`. A grid
+    /// of monitors is shown if this isn't set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagram: Option<String>,
+    /// Only show the monitors of this view.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view: Option<String>,
+    /// The number of columns in the grid of monitors (default: as square as possible).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub columns: Option<u32>,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            width: default_width(),
+            height: default_height(),
+            rotate: 0,
+            depth: default_depth(),
+            dither: default_dither(),
+            diagram: None,
+            view: None,
+            columns: None,
+        }
+    }
+}
+
+impl DisplayConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_SIZE).contains(&self.width) || !(1..=MAX_SIZE).contains(&self.height) {
+            return Err(format!(
+                "Display size must be between 1 and {} pixels",
+                MAX_SIZE
+            ));
+        }
+        if ![0, 90, 180, 270].contains(&self.rotate) {
+            return Err(format!(
+                "Invalid rotation {}, must be 0, 90, 180 or 270",
+                self.rotate
+            ));
+        }
+        if ![1, 2, 4, 8, 24].contains(&self.depth) {
+            return Err(format!(
+                "Invalid depth {}, must be 1, 2, 4, 8 or 24",
+                self.depth
+            ));
+        }
+        if self.columns == Some(0) {
+            return Err("Display columns must be at least 1".into());
+        }
+        Ok(())
+    }
+
+    /// The size of the dashboard before it is rotated into the image.
+    pub fn content_size(&self) -> (u32, u32) {
+        match self.rotate {
+            90 | 270 => (self.height, self.width),
+            _ => (self.width, self.height),
+        }
+    }
+}
+
+/// The fill and text colours of a tile. Grayscale displays use shades that stay distinct after
+/// conversion, from white for green to black for red.
+fn tile_colors(status: &MonitorStatus, color: bool) -> (String, &'static str) {
+    let fill = if color {
+        let (_, default_color) = status_message(status.status);
+        status
+            .css
+            .metadata
+            .get("color")
+            .cloned()
+            .unwrap_or_else(|| default_color.to_owned())
+    } else {
+        match status.status {
+            Some(StatusState::Red) => "#000",
+            Some(StatusState::Orange) => "#666",
+            Some(StatusState::Yellow) => "#bbb",
+            _ => "#fff",
+        }
+        .to_owned()
+    };
+    let text = if is_light(&fill) { "#000" } else { "#fff" };
+    (fill, text)
+}
+
+/// The number of columns that makes the tiles as square as possible.
+fn auto_columns(count: u32, width: u32, height: u32) -> u32 {
+    (1..=count.max(1))
+        .max_by(|a, b| {
+            let side = |columns: u32| {
+                let rows = count.div_ceil(columns).max(1);
+                (width as f32 / columns as f32).min(height as f32 / rows as f32)
+            };
+            side(*a).total_cmp(&side(*b)).then(b.cmp(a))
+        })
+        .unwrap_or(1)
+}
+
+/// Shorten text to roughly fit a width, given the font size.
+fn truncate(text: &str, width: f32, font_size: f32) -> String {
+    let max_chars = (width / (font_size * 0.6)).floor().max(1.0) as usize;
+    if text.chars().count() <= max_chars {
+        text.to_owned()
+    } else {
+        let mut text: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+        text.push('…');
+        text
+    }
+}
+
+/// A grid of monitors as an SVG of the given size, with each monitor's id, status and
+/// description, and a row of squares for the children of groups.
+pub fn grid(status: &Status, columns: Option<u32>, width: u32, height: u32, color: bool) -> String {
+    let monitors: Vec<_> = status.monitors.iter().map(|m| m.read().clone()).collect();
+    let count = monitors.len() as u32;
+    let columns = columns.unwrap_or_else(|| auto_columns(count, width, height));
+    let rows = count.div_ceil(columns).max(1);
+    let (width, height) = (width as f32, height as f32);
+    let gap = (width.min(height) / 100.0).max(2.0);
+    let tile_width = (width - gap) / columns as f32 - gap;
+    let tile_height = (height - gap) / rows as f32 - gap;
+    let padding = gap * 2.0;
+    let inner_width = tile_width - padding * 2.0;
+    // The same size of text in every tile, as large as the longest id allows
+    let longest_id = monitors.iter().map(|m| m.id.chars().count()).max();
+    let title_size = (tile_height * 0.22)
+        .min(inner_width / (longest_id.unwrap_or_default().max(1) as f32 * 0.6))
+        .clamp(6.0, 48.0);
+    let message_size = title_size * 0.8;
+    let description_size = title_size * 0.6;
+
+    let mut svg = format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="{FONT_FAMILY}"><rect width="{width}" height="{height}" fill="#fff"/>"##
+    );
+    if monitors.is_empty() {
+        svg += &format!(
+            r#"<text x="{}" y="{}" text-anchor="middle" font-size="{}">No monitors</text>"#,
+            width / 2.0,
+            height / 2.0,
+            (height / 10.0).min(32.0)
+        );
+    }
+    for (index, monitor) in monitors.iter().enumerate() {
+        let x = gap + (index as u32 % columns) as f32 * (tile_width + gap);
+        let y = gap + (index as u32 / columns) as f32 * (tile_height + gap);
+        let (fill, text) = tile_colors(&monitor.status, color);
+        let (message, _) = status_message(monitor.status.status);
+
+        svg += &format!(
+            r##"<g transform="translate({x} {y})"><rect width="{tile_width}" height="{tile_height}" rx="{gap}" fill="{}" stroke="#000" stroke-width="1"/>"##,
+            escape(&fill)
+        );
+        let mut line = padding + title_size;
+        svg += &format!(
+            r#"<text x="{padding}" y="{line}" font-size="{title_size}" fill="{text}">{}</text>"#,
+            escape(truncate(&monitor.id, inner_width, title_size))
+        );
+        line += message_size * 1.3;
+        svg += &format!(
+            r#"<text x="{padding}" y="{line}" font-size="{message_size}" font-weight="bold" fill="{text}">{}</text>"#,
+            message.to_uppercase()
+        );
+
+        let child_size = if monitor.children.is_empty() {
+            0.0
+        } else {
+            (tile_height * 0.15)
+                .min(inner_width / monitor.children.len() as f32 - 1.0)
+                .max(3.0)
+        };
+        line += description_size * 1.5;
+        if !monitor.status.description.is_empty() && line < tile_height - padding - child_size - gap
+        {
+            svg += &format!(
+                r#"<text x="{padding}" y="{line}" font-size="{description_size}" fill="{text}">{}</text>"#,
+                escape(truncate(
+                    &monitor.status.description,
+                    inner_width,
+                    description_size
+                ))
+            );
+        }
+        let mut child_x = padding;
+        for child in monitor.children.values() {
+            if child_x + child_size > tile_width - padding {
+                break;
+            }
+            let (fill, _) = tile_colors(&child.status, color);
+            svg += &format!(
+                r#"<rect x="{child_x}" y="{}" width="{child_size}" height="{child_size}" fill="{}" stroke="{text}" stroke-width="1"/>"#,
+                tile_height - padding - child_size,
+                escape(&fill)
+            );
+            child_x += child_size + 1.0;
+        }
+        svg += "</g>";
+    }
+    svg += "</svg>";
+    svg
+}
+
+/// The system fonts, which are slow to load, so only loaded once.
+fn fonts() -> Arc<fontdb::Database> {
+    static FONTS: OnceLock<Arc<fontdb::Database>> = OnceLock::new();
+    FONTS
+        .get_or_init(|| {
+            let mut fonts = fontdb::Database::new();
+            fonts.load_system_fonts();
+            fonts.set_sans_serif_family("DejaVu Sans");
+            Arc::new(fonts)
+        })
+        .clone()
+}
+
+/// Render an SVG to a PNG for a display. The SVG is scaled to fit, centred and rotated.
+pub fn rasterize(
+    svg: &str,
+    display: &DisplayConfig,
+    resources_dir: Option<PathBuf>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let options = usvg::Options {
+        resources_dir,
+        fontdb: fonts(),
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_str(svg, &options)?;
+
+    let (content_width, content_height) = display.content_size();
+    let size = tree.size();
+    let scale = (content_width as f32 / size.width()).min(content_height as f32 / size.height());
+    let fit = Transform::from_row(
+        scale,
+        0.0,
+        0.0,
+        scale,
+        (content_width as f32 - size.width() * scale) / 2.0,
+        (content_height as f32 - size.height() * scale) / 2.0,
+    );
+    let (width, height) = (display.width as f32, display.height as f32);
+    let rotate = match display.rotate {
+        90 => Transform::from_row(0.0, 1.0, -1.0, 0.0, width, 0.0),
+        180 => Transform::from_row(-1.0, 0.0, 0.0, -1.0, width, height),
+        270 => Transform::from_row(0.0, -1.0, 1.0, 0.0, 0.0, height),
+        _ => Transform::identity(),
+    };
+
+    let mut pixmap = Pixmap::new(display.width, display.height).ok_or("Invalid display size")?;
+    pixmap.fill(Color::WHITE);
+    resvg::render(&tree, rotate.pre_concat(fit), &mut pixmap.as_mut());
+    encode(&pixmap, display.depth, display.dither)
+}
+
+/// Reduce grayscale values from 0 to 255 to `2^depth` levels. When dithering, the error is
+/// spread to the neighbouring pixels (Floyd-Steinberg).
+fn quantize(mut gray: Vec<f32>, width: usize, depth: u8, dither: bool) -> Vec<u8> {
+    let max = ((1u32 << depth) - 1) as f32;
+    let height = gray.len() / width;
+    let mut levels = vec![0; gray.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let value = gray[i].clamp(0.0, 255.0);
+            let level = (value / 255.0 * max).round();
+            levels[i] = level as u8;
+            if !dither {
+                continue;
+            }
+            let error = value - level * 255.0 / max;
+            if x + 1 < width {
+                gray[i + 1] += error * 7.0 / 16.0;
+            }
+            if y + 1 < height {
+                if x > 0 {
+                    gray[i + width - 1] += error * 3.0 / 16.0;
+                }
+                gray[i + width] += error * 5.0 / 16.0;
+                if x + 1 < width {
+                    gray[i + width + 1] += error / 16.0;
+                }
+            }
+        }
+    }
+    levels
+}
+
+/// Pack levels into rows of bytes, with the leftmost pixel in the highest bits.
+fn pack(levels: &[u8], width: usize, depth: u8) -> Vec<u8> {
+    let per_byte = 8 / depth as usize;
+    levels
+        .chunks(width)
+        .flat_map(|row| {
+            row.chunks(per_byte).map(|pixels| {
+                pixels.iter().enumerate().fold(0u8, |byte, (i, level)| {
+                    byte | level << (8 - depth as usize * (i + 1))
+                })
+            })
+        })
+        .collect()
+}
+
+fn encode(pixmap: &Pixmap, depth: u8, dither: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+    let pixels = pixmap.pixels().iter().map(|pixel| pixel.demultiply());
+    let (color_type, bit_depth, data) = if depth == 24 {
+        let data = pixels
+            .flat_map(|pixel| [pixel.red(), pixel.green(), pixel.blue()])
+            .collect();
+        (png::ColorType::Rgb, png::BitDepth::Eight, data)
+    } else {
+        let gray = pixels
+            .map(|pixel| {
+                0.299 * pixel.red() as f32
+                    + 0.587 * pixel.green() as f32
+                    + 0.114 * pixel.blue() as f32
+            })
+            .collect();
+        let levels = quantize(gray, pixmap.width() as usize, depth, dither);
+        let bit_depth = match depth {
+            1 => png::BitDepth::One,
+            2 => png::BitDepth::Two,
+            4 => png::BitDepth::Four,
+            _ => png::BitDepth::Eight,
+        };
+        (
+            png::ColorType::Grayscale,
+            bit_depth,
+            pack(&levels, pixmap.width() as usize, depth),
+        )
+    };
+
+    let mut png = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut png, pixmap.width(), pixmap.height());
+        encoder.set_color(color_type);
+        encoder.set_depth(bit_depth);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+    }
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use keepcalm::SharedMut;
+
+    use super::*;
+    use crate::status::MonitorState;
+
+    fn decode(png: &[u8]) -> (png::OutputInfo, Vec<u8>) {
+        let mut reader = png::Decoder::new(png).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        data.truncate(info.buffer_size());
+        (info, data)
+    }
+
+    #[test]
+    fn test_quantize() {
+        // Mid-gray dithers to an even mix of black and white
+        let levels = quantize(vec![128.0; 64], 8, 1, true);
+        let white = levels.iter().filter(|level| **level == 1).count();
+        assert!((28..=36).contains(&white), "{levels:?}");
+        assert_eq!(quantize(vec![128.0; 64], 8, 1, false), vec![1; 64]);
+        assert_eq!(
+            quantize(vec![0.0, 85.0, 170.0, 255.0], 4, 2, false),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(
+            pack(&[1, 0, 1, 1, 0, 0, 0, 0, 1], 9, 1),
+            vec![0b10110000, 0b10000000]
+        );
+        assert_eq!(pack(&[3, 0, 2], 3, 2), vec![0b11001000]);
+    }
+
+    #[test]
+    fn test_rasterize() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"><rect width="10" height="10" fill="#000"/></svg>"##;
+        let display = DisplayConfig {
+            width: 40,
+            height: 20,
+            depth: 1,
+            ..Default::default()
+        };
+        let (info, data) = decode(&rasterize(svg, &display, None).unwrap());
+        assert_eq!((info.width, info.height), (40, 20));
+        assert_eq!(info.bit_depth, png::BitDepth::One);
+        // The left half is black and the right half is white
+        assert_eq!(&data[..5], &[0, 0, 0b00001111, 0xff, 0xff][..]);
+
+        // Rotated on its side, the black half is at the top
+        let display = DisplayConfig {
+            width: 10,
+            height: 20,
+            depth: 8,
+            rotate: 90,
+            ..Default::default()
+        };
+        let (info, data) = decode(&rasterize(svg, &display, None).unwrap());
+        assert_eq!((info.width, info.height), (10, 20));
+        assert_eq!(data[0], 0);
+        assert_eq!(data[10 * 19], 255);
+
+        let display = DisplayConfig {
+            width: 4,
+            height: 2,
+            ..Default::default()
+        };
+        let (info, _) = decode(&rasterize(svg, &display, None).unwrap());
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+    }
+
+    #[test]
+    fn test_grid() {
+        let monitor = |id: &str, status: StatusState| {
+            let mut monitor = MonitorState::new_test(id);
+            monitor.status.status = Some(status);
+            monitor.status.description = "<Failed>".into();
+            SharedMut::new(monitor)
+        };
+        let status = Status {
+            monitors: vec![
+                monitor("router", StatusState::Green),
+                monitor("nas", StatusState::Red),
+                monitor("plex", StatusState::Yellow),
+            ],
+        };
+        let svg = grid(&status, None, 800, 480, false);
+        assert!(
+            svg.contains(">router</text>") && svg.contains(">DOWN</text>"),
+            "{svg}"
+        );
+        assert!(svg.contains("&lt;Failed&gt;"), "{svg}");
+        assert!(usvg::Tree::from_str(&svg, &Default::default()).is_ok());
+
+        assert_eq!(auto_columns(3, 800, 480), 3);
+        assert_eq!(auto_columns(4, 800, 480), 2);
+        assert_eq!(auto_columns(1, 480, 800), 1);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(DisplayConfig::default().validate().is_ok());
+        let invalid = |display: DisplayConfig| display.validate().is_err();
+        assert!(invalid(DisplayConfig {
+            rotate: 45,
+            ..Default::default()
+        }));
+        assert!(invalid(DisplayConfig {
+            depth: 3,
+            ..Default::default()
+        }));
+        assert!(invalid(DisplayConfig {
+            width: 0,
+            ..Default::default()
+        }));
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::config::MonitorEventsConfig;
+use crate::status::{MonitorStatus, StatusState};
+
+/// A change in the [`StatusState`] of a monitor or group child.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct MonitorEvent {
+    /// A sequence number that increases by one for every event.
+    pub id: u64,
+    pub time: DateTime<Utc>,
+    pub monitor: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub child: Option<String>,
+    /// The previous status, which is missing if the monitor was never initialized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_status: Option<StatusState>,
+    pub status: StatusState,
+    pub code: i64,
+    pub description: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// The tail of the log for the run that caused the change.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub log: Vec<String>,
+}
+
+impl MonitorEvent {
+    /// Create an event if the status of a monitor or group child changed. The id is assigned
+    /// when the event is added to the [`EventLog`].
+    pub fn from_change(
+        monitor: &str,
+        child: Option<&str>,
+        old_status: Option<StatusState>,
+        status: &MonitorStatus,
+        log: &VecDeque<String>,
+    ) -> Option<Self> {
+        let new_status = status.status?;
+        if old_status == Some(new_status) {
+            return None;
+        }
+        Some(MonitorEvent {
+            id: 0,
+            time: Utc::now(),
+            monitor: monitor.to_owned(),
+            child: child.map(str::to_owned),
+            old_status,
+            status: new_status,
+            code: status.code,
+            description: status.description.clone(),
+            metadata: status.metadata.clone(),
+            log: log.iter().cloned().collect(),
+        })
+    }
+
+    /// Does this event belong to the given monitor or group child?
+    pub fn matches_id(&self, id: &str) -> bool {
+        self.monitor == id || self.child.as_deref() == Some(id)
+    }
+}
+
+/// Which events to return from [`EventLog::query`].
+#[derive(Debug, Default)]
+pub struct EventFilter {
+    /// Only events with an id greater than this.
+    pub after: Option<u64>,
+    /// Only events at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only events for this monitor or group child.
+    pub id: Option<String>,
+    /// Only events that changed to this status.
+    pub status: Option<StatusState>,
+    /// At most this many events (the most recent are kept).
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug)]
+struct SequenceLogState<T> {
+    items: VecDeque<T>,
+    next_seq: u64,
+}
+
+/// A bounded, in-memory log of items that are numbered in sequence from 1. New items are also
+/// broadcast to any subscribers.
+#[derive(Debug)]
+pub struct SequenceLog<T> {
+    capacity: usize,
+    state: Mutex<SequenceLogState<T>>,
+    sender: broadcast::Sender<T>,
+}
+
+impl<T: Clone> SequenceLog<T> {
+    pub fn new(capacity: usize) -> Self {
+        SequenceLog {
+            capacity,
+            state: Mutex::new(SequenceLogState {
+                items: VecDeque::new(),
+                next_seq: 1,
+            }),
+            sender: broadcast::channel(capacity.max(16)).0,
+        }
+    }
+
+    /// Add the item built from the next sequence number, and return it.
+    pub fn push(&self, item: impl FnOnce(u64) -> T) -> T {
+        let mut state = self.state.lock().unwrap();
+        let item = item(state.next_seq);
+        state.next_seq += 1;
+        state.items.push_back(item.clone());
+        if state.items.len() > self.capacity {
+            state.items.pop_front();
+        }
+        // Nobody may be listening, which is fine
+        let _ = self.sender.send(item.clone());
+        item
+    }
+
+    /// Receive every item added after this call.
+    pub fn subscribe(&self) -> broadcast::Receiver<T> {
+        self.sender.subscribe()
+    }
+
+    /// Read the items still in the log along with the last sequence number, while nothing can
+    /// be added.
+    pub fn read<R>(&self, f: impl FnOnce(&VecDeque<T>, u64) -> R) -> R {
+        let state = self.state.lock().unwrap();
+        f(&state.items, state.next_seq - 1)
+    }
+}
+
+/// A bounded, in-memory log of the most recent status changes. New events are also broadcast to
+/// any subscribers.
+#[derive(Debug)]
+pub struct EventLog {
+    config: MonitorEventsConfig,
+    log: SequenceLog<MonitorEvent>,
+}
+
+impl EventLog {
+    pub fn new(config: &MonitorEventsConfig) -> Self {
+        EventLog {
+            config: config.clone(),
+            log: SequenceLog::new(config.capacity),
+        }
+    }
+
+    /// Receive every event added after this call.
+    pub fn subscribe(&self) -> broadcast::Receiver<MonitorEvent> {
+        self.log.subscribe()
+    }
+
+    /// Add events to the log, assigning their ids and trimming their logs. The stored events are
+    /// returned.
+    pub fn push(&self, events: impl IntoIterator<Item = MonitorEvent>) -> Vec<MonitorEvent> {
+        let mut added = vec![];
+        for mut event in events {
+            if event.log.len() > self.config.log_lines {
+                event.log.drain(..event.log.len() - self.config.log_lines);
+            }
+            let event = self.log.push(|id| MonitorEvent { id, ..event });
+            info!(
+                "[{}] Status changed from {} to {}: {}",
+                event.child.as_deref().unwrap_or(&event.monitor),
+                event
+                    .old_status
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| "none".into()),
+                event.status,
+                event.description
+            );
+            added.push(event);
+        }
+        added
+    }
+
+    /// Get the events matching a filter, oldest first.
+    pub fn query(&self, filter: &EventFilter) -> Vec<MonitorEvent> {
+        let mut events: Vec<_> = self.log.read(|events, _| {
+            events
+                .iter()
+                .filter(|event| filter.after.is_none_or(|after| event.id > after))
+                .filter(|event| filter.since.is_none_or(|since| event.time >= since))
+                .filter(|event| filter.id.as_ref().is_none_or(|id| event.matches_id(id)))
+                .filter(|event| filter.status.is_none_or(|status| event.status == status))
+                .cloned()
+                .collect()
+        });
+        if let Some(limit) = filter.limit {
+            events.drain(..events.len().saturating_sub(limit));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(monitor: &str, child: Option<&str>, status: StatusState) -> MonitorEvent {
+        let status = MonitorStatus {
+            status: Some(status),
+            code: 1,
+            description: "Failed".into(),
+            ..Default::default()
+        };
+        let log = (0..30).map(|i| i.to_string()).collect();
+        MonitorEvent::from_change(monitor, child, Some(StatusState::Blank), &status, &log).unwrap()
+    }
+
+    #[test]
+    fn test_ring_and_query() {
+        let log = EventLog::new(&MonitorEventsConfig {
+            capacity: 3,
+            log_lines: 5,
+        });
+        log.push([
+            event("a", None, StatusState::Red),
+            event("b", None, StatusState::Red),
+            event("b", Some("b-1"), StatusState::Yellow),
+            event("c", None, StatusState::Green),
+        ]);
+
+        let all = log.query(&EventFilter::default());
+        assert_eq!(all.iter().map(|e| e.id).collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(all[0].log, vec!["25", "26", "27", "28", "29"]);
+
+        let filter = EventFilter {
+            id: Some("b".into()),
+            ..Default::default()
+        };
+        // Events for the children of a group are included
+        assert_eq!(log.query(&filter).len(), 2);
+        let filter = EventFilter {
+            id: Some("b-1".into()),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&filter)[0].id, 3);
+        let filter = EventFilter {
+            after: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&filter)[0].monitor, "c");
+        let filter = EventFilter {
+            status: Some(StatusState::Red),
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&filter)[0].id, 2);
+    }
+}
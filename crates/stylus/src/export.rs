@@ -0,0 +1,571 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::metrics::axis_labels;
+use crate::monitor::Monitor;
+use crate::status::{MonitorState, MonitorStatus, StatusState};
+
+/// The largest UDP datagram that is sent, which avoids fragmentation on most networks.
+const MAX_DATAGRAM: usize = 1400;
+
+/// How long to wait for a Graphite connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pushes the result of every run of every monitor and group child to a time-series database.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExporterConfig {
+    #[serde(flatten)]
+    pub target: ExporterTargetType,
+    /// The InfluxDB measurement, or the prefix of Graphite metric names.
+    #[serde(default = "default_measurement")]
+    pub measurement: String,
+    /// Send as soon as this many lines are waiting, and at most this many at once.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Send any waiting lines this often.
+    #[serde(with = "humantime_serde", default = "default_flush_interval")]
+    pub flush_interval: Duration,
+    /// Export numeric metadata as well as the status.
+    #[serde(default = "default_metadata")]
+    pub metadata: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum ExporterTargetType {
+    /// InfluxDB line protocol over HTTP.
+    Influxdb(InfluxdbTargetConfig),
+    /// InfluxDB line protocol over UDP.
+    InfluxdbUdp(SocketTargetConfig),
+    /// Graphite plaintext protocol over TCP.
+    Graphite(SocketTargetConfig),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxdbTargetConfig {
+    /// The write endpoint, including the database or org and bucket, such as
+    /// `http://influxdb:8086/api/v2/write?org=home&bucket=stylus`.
+    pub url: String,
+    /// The API token, sent as `Authorization: Token <token>`.
+    #[serde(default, skip_serializing)]
+    pub token: Option<String>,
+    #[serde(with = "humantime_serde", default = "default_timeout")]
+    pub timeout: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SocketTargetConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+fn default_measurement() -> String {
+    "stylus".to_string()
+}
+
+fn default_batch_size() -> usize {
+    100
+}
+
+fn default_flush_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_metadata() -> bool {
+    true
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// A number for each status that increases with severity, for databases that only store numbers.
+fn severity(status: StatusState) -> u8 {
+    match status {
+        StatusState::Blank => 0,
+        StatusState::Green => 1,
+        StatusState::Blue => 2,
+        StatusState::Yellow => 3,
+        StatusState::Orange => 4,
+        StatusState::Red => 5,
+    }
+}
+
+/// The result of a run for a single monitor or group child.
+struct Point<'a> {
+    tags: Vec<(String, String)>,
+    status: StatusState,
+    time: DateTime<Utc>,
+    result: &'a MonitorStatus,
+    metadata: Vec<(&'a str, f64)>,
+}
+
+impl ExporterConfig {
+    fn points<'a>(&self, state: &'a MonitorState) -> Vec<Point<'a>> {
+        let monitor = std::iter::once((&state.id, None, &state.status));
+        let children = state
+            .children
+            .iter()
+            .map(|(id, child)| (id, Some(&child.axes), &child.status));
+
+        let mut points = vec![];
+        for (id, axes, result) in monitor.chain(children) {
+            let (Some(status), Some(time)) = (result.status, result.last_run) else {
+                continue;
+            };
+            let mut tags = vec![
+                ("id".to_string(), id.clone()),
+                ("monitor".to_string(), state.id.clone()),
+            ];
+            if let Some(axes) = axes {
+                tags.extend(axis_labels(axes));
+            }
+            let metadata = if self.metadata {
+                result
+                    .metadata
+                    .iter()
+                    .filter_map(|(k, v)| Some((k.as_str(), v.trim().parse().ok()?)))
+                    .collect()
+            } else {
+                vec![]
+            };
+            points.push(Point {
+                tags,
+                status,
+                time,
+                result,
+                metadata,
+            });
+        }
+        points
+    }
+
+    /// Format the result of a run in the protocol of the target.
+    pub fn lines(&self, state: &MonitorState) -> Vec<String> {
+        let points = self.points(state);
+        match self.target {
+            ExporterTargetType::Influxdb(_) | ExporterTargetType::InfluxdbUdp(_) => points
+                .iter()
+                .map(|point| influxdb_line(&self.measurement, point))
+                .collect(),
+            ExporterTargetType::Graphite(_) => points
+                .iter()
+                .flat_map(|point| graphite_lines(&self.measurement, point))
+                .collect(),
+        }
+    }
+
+    /// Send the waiting lines in batches, removing those that were sent.
+    async fn flush(
+        &self,
+        client: &reqwest::Client,
+        pending: &mut Vec<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        while !pending.is_empty() {
+            let count = pending.len().min(self.batch_size.max(1));
+            self.target.send(client, &pending[..count]).await?;
+            pending.drain(..count);
+        }
+        Ok(())
+    }
+}
+
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\n' => escaped.push(' '),
+            c if special.contains(&c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Format a point as a line of the InfluxDB line protocol, with a nanosecond timestamp.
+fn influxdb_line(measurement: &str, point: &Point) -> String {
+    let mut line = escape(measurement, &[',', ' ']);
+    for (name, value) in &point.tags {
+        // Empty tag values are not allowed
+        if !value.is_empty() {
+            line += &format!(
+                ",{}={}",
+                escape(name, &[',', '=', ' ']),
+                escape(value, &[',', '=', ' '])
+            );
+        }
+    }
+    let status = point.status.to_string().to_lowercase();
+    line += &format!(
+        " status=\"{}\",severity={}i,exit_code={}i",
+        status,
+        severity(point.status),
+        point.result.code
+    );
+    if let Some(duration_ms) = point.result.duration_ms {
+        line += &format!(",duration={}", duration_ms as f64 / 1000.0);
+    }
+    for (key, value) in &point.metadata {
+        if value.is_finite() {
+            line += &format!(",metadata_{}={}", escape(key, &[',', '=', ' ']), value);
+        }
+    }
+    let time = point.time.timestamp_nanos_opt().unwrap_or_default();
+    line + &format!(" {}", time)
+}
+
+/// Graphite names and tags may not contain these characters.
+fn graphite_safe(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            ' ' | ';' | '~' | '=' | '!' | '^' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Format a point as Graphite plaintext lines, with the tags in the Graphite 1.1 tag format.
+fn graphite_lines(prefix: &str, point: &Point) -> Vec<String> {
+    let tags: String = point
+        .tags
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!(";{}={}", graphite_safe(name), graphite_safe(value)))
+        .collect();
+    let mut values = vec![
+        ("severity".to_string(), severity(point.status) as f64),
+        ("exit_code".to_string(), point.result.code as f64),
+    ];
+    if let Some(duration_ms) = point.result.duration_ms {
+        values.push(("duration".to_string(), duration_ms as f64 / 1000.0));
+    }
+    for (key, value) in &point.metadata {
+        values.push((format!("metadata.{}", graphite_safe(key)), *value));
+    }
+    values
+        .into_iter()
+        .filter(|(_, value)| value.is_finite())
+        .map(|(name, value)| {
+            format!(
+                "{}.{}{} {} {}",
+                graphite_safe(prefix),
+                name,
+                tags,
+                value,
+                point.time.timestamp()
+            )
+        })
+        .collect()
+}
+
+impl ExporterTargetType {
+    async fn send(
+        &self,
+        client: &reqwest::Client,
+        lines: &[String],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            ExporterTargetType::Influxdb(influxdb) => {
+                let mut request = client
+                    .post(&influxdb.url)
+                    .timeout(influxdb.timeout)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body(lines.join("\n") + "\n");
+                if let Some(token) = &influxdb.token {
+                    request = request.header("Authorization", format!("Token {}", token));
+                }
+                let response = request.send().await?;
+                if !response.status().is_success() {
+                    return Err(format!("InfluxDB returned {}", response.status()).into());
+                }
+            }
+            ExporterTargetType::InfluxdbUdp(socket) => {
+                let address = tokio::net::lookup_host((socket.host.as_str(), socket.port))
+                    .await?
+                    .next()
+                    .ok_or_else(|| format!("Failed to resolve {}", socket.host))?;
+                // The local address must be of the same family as the server's
+                let local = if address.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let udp = UdpSocket::bind(local).await?;
+                udp.connect(address).await?;
+                // Pack as many whole lines as fit into each datagram
+                let mut datagram = String::new();
+                for line in lines {
+                    if !datagram.is_empty() && datagram.len() + line.len() + 1 > MAX_DATAGRAM {
+                        udp.send(datagram.as_bytes()).await?;
+                        datagram.clear();
+                    }
+                    datagram += line;
+                    datagram += "\n";
+                }
+                if !datagram.is_empty() {
+                    udp.send(datagram.as_bytes()).await?;
+                }
+            }
+            ExporterTargetType::Graphite(socket) => {
+                let connect = TcpStream::connect((socket.host.as_str(), socket.port));
+                let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, connect)
+                    .await
+                    .map_err(|_| "Timed out connecting to Graphite")??;
+                stream
+                    .write_all((lines.join("\n") + "\n").as_bytes())
+                    .await?;
+                stream.shutdown().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Send the result of each run to an exporter, batching lines until there are enough or the
+/// flush interval passes. Lines that fail to send are retried at the next flush.
+async fn export(
+    name: String,
+    config: ExporterConfig,
+    mut pending: Vec<String>,
+    mut runs: broadcast::Receiver<MonitorState>,
+) {
+    let client = reqwest::Client::new();
+    let mut interval = tokio::time::interval(config.flush_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        let closed = tokio::select! {
+            run = runs.recv() => match run {
+                Ok(state) => {
+                    pending.extend(config.lines(&state));
+                    if pending.len() < config.batch_size {
+                        continue;
+                    }
+                    false
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Exporter {} fell behind and skipped {} runs", name, skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => true,
+            },
+            _ = interval.tick() => false,
+        };
+
+        if let Err(e) = config.flush(&client, &mut pending).await {
+            warn!("Failed to send to exporter {}: {}", name, e);
+            // Keep retrying, but don't hold on to everything if the target stays down
+            let limit = config.batch_size.max(1) * 10;
+            if pending.len() > limit {
+                pending.drain(..pending.len() - limit);
+            }
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+/// Start each configured exporter.
+pub async fn run(monitor: Arc<Monitor>) {
+    for (name, config) in monitor.config().exporters.clone() {
+        info!("Exporting results to {}", name);
+        // Monitors start running before we subscribe, so begin with the current results
+        let runs = monitor.subscribe_runs();
+        let pending = monitor
+            .status()
+            .monitors
+            .iter()
+            .flat_map(|state| config.lines(&state.read()))
+            .collect();
+        tokio::spawn(export(name, config, pending, runs));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MonitorDirAxisValue;
+    use crate::status::MonitorChildStatus;
+    use axum::{http::HeaderMap, routing::post, Router};
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    use tokio::io::AsyncReadExt;
+
+    fn state() -> MonitorState {
+        let mut state = MonitorState::new_test("switch");
+        let time = "2025-01-01T00:00:00Z".parse().unwrap();
+        state.status.status = Some(StatusState::Red);
+        state.status.code = 1;
+        state.status.duration_ms = Some(1500);
+        state.status.last_run = Some(time);
+        state.status.metadata = BTreeMap::from([
+            ("rtt_avg".to_string(), "1234".to_string()),
+            ("model".to_string(), "GS108".to_string()),
+        ]);
+        let mut child = MonitorChildStatus::default();
+        child
+            .axes
+            .insert("index".into(), MonitorDirAxisValue::Number(1));
+        child.axes.insert(
+            "name".into(),
+            MonitorDirAxisValue::String("Port 1, uplink".into()),
+        );
+        child.status.status = Some(StatusState::Green);
+        child.status.last_run = Some(time);
+        state.children.insert("switch-1".into(), child);
+        // Children that have never run are not exported
+        state
+            .children
+            .insert("switch-2".into(), MonitorChildStatus::default());
+        state
+    }
+
+    fn config(target: &str) -> ExporterConfig {
+        serde_yaml_ng::from_str(target).unwrap()
+    }
+
+    #[test]
+    fn test_influxdb_lines() {
+        let config = config("influxdb: { url: http://localhost:8086/write?db=stylus }");
+        assert_eq!(
+            config.lines(&state()),
+            vec![
+                "stylus,id=switch,monitor=switch status=\"red\",severity=5i,exit_code=1i,duration=1.5,metadata_rtt_avg=1234 1735689600000000000",
+                "stylus,id=switch-1,monitor=switch,index=1,name=Port\\ 1\\,\\ uplink status=\"green\",severity=1i,exit_code=0i 1735689600000000000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_graphite_lines() {
+        let config = config("{ graphite: { host: localhost, port: 2003 }, metadata: false, measurement: home.stylus }");
+        assert_eq!(
+            config.lines(&state()),
+            vec![
+                "home.stylus.severity;id=switch;monitor=switch 5 1735689600",
+                "home.stylus.exit_code;id=switch;monitor=switch 1 1735689600",
+                "home.stylus.duration;id=switch;monitor=switch 1.5 1735689600",
+                "home.stylus.severity;id=switch-1;monitor=switch;index=1;name=Port_1,_uplink 1 1735689600",
+                "home.stylus.exit_code;id=switch-1;monitor=switch;index=1;name=Port_1,_uplink 0 1735689600",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_influxdb_http() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let received = Arc::new(Mutex::new(vec![]));
+        let app = Router::new().route(
+            "/api/v2/write",
+            post({
+                let received = received.clone();
+                move |headers: HeaderMap, body: String| async move {
+                    let token = headers["Authorization"].to_str().unwrap().to_owned();
+                    received.lock().unwrap().push((token, body));
+                    axum::http::StatusCode::NO_CONTENT
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config = config(&format!(
+            "{{ influxdb: {{ url: 'http://{addr}/api/v2/write?org=home&bucket=stylus', token: secret }}, batch_size: 2 }}"
+        ));
+        let mut pending: Vec<_> = ["a", "b", "c"].map(String::from).into();
+        config.flush(&reqwest::Client::new(), &mut pending).await?;
+        assert!(pending.is_empty());
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                ("Token secret".to_owned(), "a\nb\n".to_owned()),
+                ("Token secret".to_owned(), "c\n".to_owned())
+            ]
+        );
+
+        // Lines that fail to send are kept
+        let config = config_with_url(&config, format!("http://{addr}/missing"));
+        let mut pending = vec!["d".to_string()];
+        assert!(config
+            .flush(&reqwest::Client::new(), &mut pending)
+            .await
+            .is_err());
+        assert_eq!(pending, vec!["d"]);
+        Ok(())
+    }
+
+    fn config_with_url(config: &ExporterConfig, url: String) -> ExporterConfig {
+        let mut config = config.clone();
+        if let ExporterTargetType::Influxdb(influxdb) = &mut config.target {
+            influxdb.url = url;
+        }
+        config
+    }
+
+    #[tokio::test]
+    async fn test_influxdb_udp() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let port = socket.local_addr()?.port();
+        let config = config(&format!(
+            "influxdb_udp: {{ host: 127.0.0.1, port: {port} }}"
+        ));
+        let long = "x".repeat(1396);
+        let mut pending = vec!["a".to_string(), long.clone(), "b".to_string()];
+        config.flush(&reqwest::Client::new(), &mut pending).await?;
+
+        // Lines are packed into datagrams without splitting them
+        let mut buf = [0; 2048];
+        let n = socket.recv(&mut buf).await?;
+        assert_eq!(std::str::from_utf8(&buf[..n])?, format!("a\n{long}\n"));
+        let n = socket.recv(&mut buf).await?;
+        assert_eq!(std::str::from_utf8(&buf[..n])?, "b\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_influxdb_udp_ipv6() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let socket = UdpSocket::bind("[::1]:0").await?;
+        let port = socket.local_addr()?.port();
+        let config = config(&format!("influxdb_udp: {{ host: '::1', port: {port} }}"));
+        let mut pending = vec!["a".to_string()];
+        config.flush(&reqwest::Client::new(), &mut pending).await?;
+
+        let mut buf = [0; 2048];
+        let n = socket.recv(&mut buf).await?;
+        assert_eq!(std::str::from_utf8(&buf[..n])?, "a\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_graphite() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let config = config(&format!(
+            "{{ graphite: {{ host: 127.0.0.1, port: {port} }}, batch_size: 1, flush_interval: 1h }}"
+        ));
+        let (sender, receiver) = broadcast::channel(16);
+        tokio::spawn(export("graphite".into(), config, vec![], receiver));
+
+        // The batch is full after one run, so it is sent without waiting for the interval
+        sender.send(state())?;
+        let (mut stream, _) = listener.accept().await?;
+        let mut received = String::new();
+        stream.read_to_string(&mut received).await?;
+        assert!(received.starts_with("stylus.severity;id=switch;monitor=switch 5 1735689600\n"));
+        Ok(())
+    }
+}
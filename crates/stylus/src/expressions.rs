@@ -2,9 +2,10 @@ use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap};
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq)]
 pub enum Value {
     Int(i64),
+    Float(f64),
     Str(Cow<'static, str>),
 }
 
@@ -17,6 +18,7 @@ impl std::fmt::Debug for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Int(i) => write!(f, "{i:?}"),
+            Value::Float(n) => write!(f, "{n:?}"),
             Value::Str(s) => write!(f, "{s:?}"),
         }
     }
@@ -26,13 +28,23 @@ impl Value {
     pub fn as_int(&self) -> i64 {
         match self {
             Value::Int(i) => *i,
+            Value::Float(n) => *n as i64,
             Value::Str(s) => s.parse::<i64>().unwrap_or_default(),
         }
     }
 
+    pub fn as_float(&self) -> f64 {
+        match self {
+            Value::Int(i) => *i as f64,
+            Value::Float(n) => *n,
+            Value::Str(s) => s.parse::<f64>().unwrap_or_default(),
+        }
+    }
+
     pub fn as_str(&self) -> Cow<'static, str> {
         match self {
             Value::Int(i) => i.to_string().into(),
+            Value::Float(n) => n.to_string().into(),
             Value::Str(s) => s.clone(),
         }
     }
@@ -48,6 +60,7 @@ impl Value {
     fn is_truthy(&self) -> bool {
         match self {
             Value::Int(i) => *i != 0,
+            Value::Float(n) => *n != 0.0,
             Value::Str(s) => !s.is_empty(),
         }
     }
@@ -64,11 +77,22 @@ impl Value {
         Value::from_bool(self.is_truthy() || other.is_truthy())
     }
 
-    fn cmp(self, other: Value) -> Ordering {
+    fn is_float(&self) -> bool {
+        matches!(self, Value::Float(_))
+    }
+
+    /// Arithmetic is done in floating point if either side is a float.
+    fn either_float(&self, other: &Value) -> bool {
+        self.is_float() || other.is_float()
+    }
+
+    /// Compare two values, or `None` if either is NaN, so that every comparison but `!=` is false.
+    fn cmp(self, other: Value) -> Option<Ordering> {
         match (self, other) {
-            (Value::Int(a), Value::Int(b)) => a.cmp(&b),
-            (Value::Str(a), Value::Str(b)) => a.cmp(&b),
-            (a, b) => a.as_int().cmp(&b.as_int()),
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(&b)),
+            (Value::Str(a), Value::Str(b)) => Some(a.cmp(&b)),
+            (a, b) if a.either_float(&b) => a.as_float().partial_cmp(&b.as_float()),
+            (a, b) => Some(a.as_int().cmp(&b.as_int())),
         }
     }
 
@@ -76,20 +100,38 @@ impl Value {
         match (self, other) {
             (Value::Int(a), Value::Int(b)) => Value::Int(a + b),
             (Value::Str(a), Value::Str(b)) => Value::Str(format!("{}{}", a, b).into()),
+            (Value::Float(a), Value::Float(b)) => Value::Float(a + b),
+            (Value::Float(a), Value::Int(b)) | (Value::Int(b), Value::Float(a)) => {
+                Value::Float(a + b as f64)
+            }
             (a, b) => Value::Str(format!("{}{}", a.as_str(), b.as_str()).into()),
         }
     }
 
     fn sub(self, other: Value) -> Value {
-        Value::Int(self.as_int() - other.as_int())
+        if self.either_float(&other) {
+            Value::Float(self.as_float() - other.as_float())
+        } else {
+            Value::Int(self.as_int() - other.as_int())
+        }
     }
 
     fn mul(self, other: Value) -> Value {
-        Value::Int(self.as_int() * other.as_int())
+        if self.either_float(&other) {
+            Value::Float(self.as_float() * other.as_float())
+        } else {
+            Value::Int(self.as_int() * other.as_int())
+        }
     }
 
     fn div(self, other: Value) -> Result {
-        if other.as_int() == 0 {
+        if self.either_float(&other) {
+            if other.as_float() == 0.0 {
+                Err(Error("division by zero".to_string()))
+            } else {
+                Ok(Value::Float(self.as_float() / other.as_float()))
+            }
+        } else if other.as_int() == 0 {
             Err(Error("division by zero".to_string()))
         } else {
             Ok(Value::Int(self.as_int() / other.as_int()))
@@ -97,6 +139,9 @@ impl Value {
     }
 
     fn pow_val(self, other: Value) -> Result {
+        if self.either_float(&other) {
+            return Ok(Value::Float(self.as_float().powf(other.as_float())));
+        }
         let a = self.as_int();
         let b = other.as_int();
         Ok(Value::Int(
@@ -106,7 +151,10 @@ impl Value {
     }
 
     fn negate(self) -> Value {
-        Value::Int(-self.as_int())
+        match self {
+            Value::Float(n) => Value::Float(-n),
+            v => Value::Int(-v.as_int()),
+        }
     }
 }
 
@@ -132,8 +180,86 @@ fn unescape_string(input: &str) -> String {
     output
 }
 
+/// How a label matcher in a series selector compares the label's value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchOp {
+    Equal,
+    NotEqual,
+    Regex,
+    NotRegex,
+}
+
+/// A label matcher from a series selector such as `name{label="value"}`.
+#[derive(Clone, Debug)]
+pub struct LabelMatcher {
+    pub label: String,
+    pub op: MatchOp,
+    pub value: String,
+    /// The compiled `value` of a regular expression matcher, so that it is only compiled once.
+    regex: Option<regex::Regex>,
+}
+
+impl PartialEq for LabelMatcher {
+    fn eq(&self, other: &Self) -> bool {
+        self.label == other.label && self.op == other.op && self.value == other.value
+    }
+}
+
+impl Eq for LabelMatcher {}
+
+impl LabelMatcher {
+    pub fn new(
+        label: String,
+        op: MatchOp,
+        value: String,
+    ) -> std::result::Result<Self, regex::Error> {
+        let regex = match op {
+            // Like Prometheus, the regular expression must match the whole value
+            MatchOp::Regex | MatchOp::NotRegex => {
+                Some(regex::Regex::new(&format!("^(?:{})$", value))?)
+            }
+            MatchOp::Equal | MatchOp::NotEqual => None,
+        };
+        Ok(LabelMatcher {
+            label,
+            op,
+            value,
+            regex,
+        })
+    }
+
+    /// Matches the labels of a series, where a missing label has an empty value.
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        let value = labels.get(&self.label).map(String::as_str).unwrap_or("");
+        match (self.op, &self.regex) {
+            (MatchOp::Equal, _) => value == self.value,
+            (MatchOp::NotEqual, _) => value != self.value,
+            (MatchOp::Regex, Some(regex)) => regex.is_match(value),
+            (MatchOp::NotRegex, Some(regex)) => !regex.is_match(value),
+            (MatchOp::Regex | MatchOp::NotRegex, None) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for LabelMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self.op {
+            MatchOp::Equal => "=",
+            MatchOp::NotEqual => "!=",
+            MatchOp::Regex => "=~",
+            MatchOp::NotRegex => "!~",
+        };
+        write!(f, "{}{}{:?}", self.label, op, self.value)
+    }
+}
+
 pub trait ExpressionContext {
     fn get(&self, key: &str) -> Option<&Value>;
+
+    /// Looks up the value of a series by name and label matchers, for contexts that have series.
+    fn get_series(&self, _name: &str, _matchers: &[LabelMatcher]) -> Option<Value> {
+        None
+    }
 }
 
 impl ExpressionContext for HashMap<String, Value> {
@@ -149,8 +275,10 @@ impl ExpressionContext for BTreeMap<String, Value> {
 }
 
 peg::parser!( pub grammar expression() for str {
-    rule number() -> i64
-        = n:$(['0'..='9']+) { n.parse().unwrap() }
+    rule number() -> Value
+        = n:$(['0'..='9']+ "." ['0'..='9']+ (['e' | 'E'] ['+' | '-']? ['0'..='9']+)?) { Value::Float(n.parse().unwrap()) }
+        / n:$(['0'..='9']+ ['e' | 'E'] ['+' | '-']? ['0'..='9']+) { Value::Float(n.parse().unwrap()) }
+        / n:$(['0'..='9']+) { Value::Int(n.parse().unwrap()) }
 
     rule string() -> String
         = r#"""# s:$( ( r#"\""# / r#"\\"# / r#"\'"# / (!r#"""# [_]) )* ) r#"""# { unescape_string(s) }
@@ -161,6 +289,24 @@ peg::parser!( pub grammar expression() for str {
 
     rule ws() = quiet!{[ ' ' | '\t' | '\r' | '\n' ]*}
 
+    rule match_op() -> MatchOp
+        = "=~" { MatchOp::Regex }
+        / "!~" { MatchOp::NotRegex }
+        / "!=" { MatchOp::NotEqual }
+        / "=" { MatchOp::Equal }
+
+    rule matcher() -> LabelMatcher
+        = label:ident() ws() op:match_op() ws() value:string() {?
+            LabelMatcher::new(label, op, value).or(Err("valid regular expression"))
+        }
+
+    rule matchers() -> Vec<LabelMatcher>
+        = "{" ws() m:(matcher() ** (ws() "," ws())) ws() ","? ws() "}" { m }
+
+    /// A series selector such as `name{label="value"}`.
+    pub rule selector() -> (String, Vec<LabelMatcher>)
+        = ws() name:ident() ws() m:matchers()? ws() { (name, m.unwrap_or_default()) }
+
     pub rule calculate(ctx: &impl ExpressionContext) -> Result
         = ws() v:expr(ctx) ws() { v }
 
@@ -170,12 +316,12 @@ peg::parser!( pub grammar expression() for str {
         x:(@) ws() "and" ws() y:@ { Ok(x?.logical_and(y?)) }
               ws() "not" ws() v:@ { Ok(v?.logical_not()) }
         --
-        x:(@) ws() ">=" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) >= Ordering::Equal)) }
-        x:(@) ws() "<=" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) <= Ordering::Equal)) }
-        x:(@) ws() "==" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) == Ordering::Equal)) }
-        x:(@) ws() "!=" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) != Ordering::Equal)) }
-        x:(@) ws() ">" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) == Ordering::Greater)) }
-        x:(@) ws() "<" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) == Ordering::Less)) }
+        x:(@) ws() ">=" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?).is_some_and(Ordering::is_ge))) }
+        x:(@) ws() "<=" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?).is_some_and(Ordering::is_le))) }
+        x:(@) ws() "==" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) == Some(Ordering::Equal))) }
+        x:(@) ws() "!=" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) != Some(Ordering::Equal))) }
+        x:(@) ws() ">" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) == Some(Ordering::Greater))) }
+        x:(@) ws() "<" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) == Some(Ordering::Less))) }
         --
         x:(@) ws() "+" ws() y:@ { Ok(x?.add(y?)) }
         x:(@) ws() "-" ws() y:@ { Ok(x?.sub(y?)) }
@@ -190,7 +336,7 @@ peg::parser!( pub grammar expression() for str {
         ws() "int" ws() "(" ws() v:expr(ctx) ws() ")" { Ok(Value::Int(v?.as_int())) }
         ws() "(" ws() v:expr(ctx) ws() ")" { Ok(v?) }
         ws() s:string() { Ok(Value::Str(s.into())) }
-        ws() n:number() { Ok(Value::Int(n)) }
+        ws() n:number() { Ok(n) }
         ws() "startswith" ws() "(" ws() a:expr(ctx) ws() "," ws() b:expr(ctx) ws() ")" {
             let s1 = a?.as_str();
             let s2 = b?.as_str();
@@ -212,6 +358,15 @@ peg::parser!( pub grammar expression() for str {
         }
         ws() "true" { Ok(Value::from_bool(true)) }
         ws() "false" { Ok(Value::from_bool(false)) }
+        ws() id:ident() ws() m:matchers() {
+            match ctx.get_series(&id, &m) {
+                Some(v) => Ok(v),
+                None => {
+                    let m: Vec<_> = m.iter().map(|m| m.to_string()).collect();
+                    Err(Error(format!("no matching series: {}{{{}}}", id, m.join(","))))
+                }
+            }
+        }
         ws() id:ident() { match ctx.get(&id) { Some(v) => Ok(v.clone()), None => Err(Error(format!("unknown identifier: {}", id))) } }
     }
 });
@@ -326,4 +481,83 @@ mod tests {
         .unwrap();
         assert_eq!(v, Value::Int(1));
     }
+
+    #[test]
+    fn test_floats() {
+        let mut ctx: HashMap<String, Value> = HashMap::new();
+        ctx.insert("f".into(), Value::Float(0.5));
+        ctx.insert("n".into(), Value::Int(3));
+
+        let v = expression::calculate("f + n", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(3.5));
+        let v = expression::calculate("n / 2", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = expression::calculate("n / 2.0", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(1.5));
+        let v = expression::calculate("10e9 > 9999999999 and 1.5e-3 < f", &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = expression::calculate("str(-f)", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Str("-0.5".into()));
+
+        ctx.insert("nan".into(), Value::Float(f64::NAN));
+        for (expr, expected) in [
+            ("nan == 0", 0),
+            ("nan >= 0", 0),
+            ("nan < 1.0", 0),
+            ("nan == nan", 0),
+            ("nan != 0", 1),
+        ] {
+            let v = expression::calculate(expr, &ctx).unwrap().unwrap();
+            assert_eq!(v, Value::Int(expected), "{expr}");
+        }
+    }
+
+    struct Series(Vec<(&'static str, BTreeMap<String, String>, f64)>);
+
+    impl ExpressionContext for Series {
+        fn get(&self, _key: &str) -> Option<&Value> {
+            None
+        }
+
+        fn get_series(&self, name: &str, matchers: &[LabelMatcher]) -> Option<Value> {
+            self.0
+                .iter()
+                .find(|(n, labels, _)| *n == name && matchers.iter().all(|m| m.matches(labels)))
+                .map(|(_, _, value)| Value::Float(*value))
+        }
+    }
+
+    #[test]
+    fn test_series_selectors() {
+        let labels =
+            |mountpoint: &str| BTreeMap::from([("mountpoint".to_string(), mountpoint.to_string())]);
+        let ctx = Series(vec![
+            ("avail", labels("/boot"), 1e8),
+            ("avail", labels("/"), 5e9),
+        ]);
+
+        let v = expression::calculate(r#"avail{mountpoint="/"} < 10e9"#, &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = expression::calculate(r#"avail{mountpoint!~"/b.*", } / 1e9"#, &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Float(5.0));
+        let v = expression::calculate(r#"avail{mountpoint=~"/b"}"#, &ctx).unwrap();
+        assert_eq!(
+            v,
+            Err(Error(
+                r#"no matching series: avail{mountpoint=~"/b"}"#.to_string()
+            ))
+        );
+
+        let (name, matchers) = expression::selector(r#" avail{mountpoint="/"} "#).unwrap();
+        assert_eq!(name, "avail");
+        assert_eq!(matchers[0].to_string(), r#"mountpoint="/""#);
+        assert_eq!(expression::selector("avail").unwrap().1, vec![]);
+        assert!(expression::selector(r#"avail{mountpoint=~"("}"#).is_err());
+    }
 }
@@ -0,0 +1,216 @@
+use serde::de::{value::StrDeserializer, IntoDeserializer};
+use serde::Deserialize;
+
+use crate::status::{MonitorState, MonitorStatus, Status, StatusState};
+
+/// Which monitors and group children the status API returns. Each list matches if any of its
+/// entries match, and an empty list matches everything.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StatusFilter {
+    /// Globs of monitor or group child ids, where `*` matches anything and `?` any character.
+    pub ids: Vec<String>,
+    /// Metadata that must be present, either as `key` or `key=value`.
+    pub tags: Vec<String>,
+    pub statuses: Vec<StatusState>,
+}
+
+impl StatusFilter {
+    /// Parse the comma-separated `id`, `tag` and `status` query parameters.
+    pub fn parse(
+        id: Option<&str>,
+        tag: Option<&str>,
+        status: Option<&str>,
+    ) -> Result<Self, String> {
+        fn split(list: Option<&str>) -> impl Iterator<Item = &str> {
+            list.unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+        }
+        Ok(StatusFilter {
+            ids: split(id).map(str::to_owned).collect(),
+            tags: split(tag).map(str::to_owned).collect(),
+            statuses: split(status)
+                .map(|s| {
+                    let deserializer: StrDeserializer<serde::de::value::Error> =
+                        s.into_deserializer();
+                    StatusState::deserialize(deserializer)
+                        .map_err(|_| format!("Invalid status {}", s))
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty() && self.tags.is_empty() && self.statuses.is_empty()
+    }
+
+    fn matches_id(&self, id: &str) -> bool {
+        self.ids.is_empty() || self.ids.iter().any(|pattern| glob_matches(pattern, id))
+    }
+
+    /// Does the status match the `tag` and `status` filters?
+    fn matches_status(&self, status: &MonitorStatus) -> bool {
+        let tags = self.tags.is_empty()
+            || self.tags.iter().any(|tag| match tag.split_once('=') {
+                Some((key, value)) => status.metadata.get(key).is_some_and(|v| v == value),
+                None => status.metadata.contains_key(tag),
+            });
+        let statuses = self.statuses.is_empty()
+            || status
+                .status
+                .is_some_and(|state| self.statuses.contains(&state));
+        tags && statuses
+    }
+
+    /// A copy of a monitor with only the matching group children, or `None` if neither it nor
+    /// any of its children match. Children match the `id` filter if their group does.
+    fn monitor(&self, monitor: &MonitorState) -> Option<MonitorState> {
+        let id_matches = self.matches_id(&monitor.id);
+        monitor.filter_children(
+            id_matches && self.matches_status(&monitor.status),
+            |id, child| (id_matches || self.matches_id(id)) && self.matches_status(&child.status),
+        )
+    }
+
+    /// The matching part of the status.
+    pub fn status(&self, status: &Status) -> Status {
+        status.filter_map(|monitor| self.monitor(monitor))
+    }
+}
+
+/// Match a glob where `*` matches any number of characters and `?` matches one.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // The position of the last `*` and the text it was matched against, to backtrack to
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use keepcalm::SharedMut;
+
+    use super::*;
+    use crate::status::MonitorChildStatus;
+
+    fn status(state: StatusState, room: &str) -> MonitorStatus {
+        MonitorStatus {
+            status: Some(state),
+            metadata: BTreeMap::from([("room".to_string(), room.to_string())]),
+            ..Default::default()
+        }
+    }
+
+    fn monitor(
+        id: &str,
+        state: MonitorStatus,
+        children: &[(&str, MonitorStatus)],
+    ) -> SharedMut<MonitorState> {
+        let mut monitor = MonitorState::new_test(id);
+        monitor.status = state;
+        for (child, status) in children {
+            monitor.children.insert(
+                child.to_string(),
+                MonitorChildStatus {
+                    axes: Default::default(),
+                    status: status.clone(),
+                },
+            );
+        }
+        SharedMut::new(monitor)
+    }
+
+    fn ids(filter: &StatusFilter, status: &Status) -> Vec<(String, Vec<String>)> {
+        filter.status(status).ids()
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob_matches("router", "router"));
+        assert!(!glob_matches("router", "router-2"));
+        assert!(glob_matches("router*", "router-2"));
+        assert!(glob_matches("*-sd?", "disks-sda"));
+        assert!(!glob_matches("*-sd?", "disks-sda1"));
+        assert!(glob_matches("*a*b*", "xaxxbaab"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("?", ""));
+    }
+
+    #[test]
+    fn test_parse() {
+        let filter = StatusFilter::parse(Some("plex, disks-*"), None, Some("red,orange")).unwrap();
+        assert_eq!(filter.ids, vec!["plex", "disks-*"]);
+        assert_eq!(filter.statuses, vec![StatusState::Red, StatusState::Orange]);
+        assert!(StatusFilter::parse(None, None, None).unwrap().is_empty());
+        assert!(StatusFilter::parse(None, None, Some("purple")).is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        let status = Status {
+            monitors: vec![
+                monitor("plex", status(StatusState::Green, "office"), &[]),
+                monitor("router", status(StatusState::Red, "office"), &[]),
+                monitor(
+                    "disks",
+                    status(StatusState::Red, "basement"),
+                    &[
+                        ("disks-sda", status(StatusState::Green, "basement")),
+                        ("disks-sdb", status(StatusState::Red, "basement")),
+                    ],
+                ),
+            ],
+        };
+        let filter = |id, tag, state| StatusFilter::parse(id, tag, state).unwrap();
+
+        assert_eq!(ids(&filter(None, None, None), &status).len(), 3);
+        assert_eq!(
+            ids(&filter(Some("disks"), None, None), &status),
+            vec![(
+                "disks".to_string(),
+                vec!["disks-sda".to_string(), "disks-sdb".to_string()]
+            )]
+        );
+        assert_eq!(
+            ids(&filter(Some("*-sda"), None, None), &status),
+            vec![("disks".to_string(), vec!["disks-sda".to_string()])]
+        );
+        assert_eq!(
+            ids(&filter(None, None, Some("red")), &status),
+            vec![
+                ("router".to_string(), vec![]),
+                ("disks".to_string(), vec!["disks-sdb".to_string()])
+            ]
+        );
+        assert_eq!(
+            ids(&filter(None, Some("room=office"), Some("green")), &status),
+            vec![("plex".to_string(), vec![])]
+        );
+        assert_eq!(ids(&filter(None, Some("rack"), None), &status), vec![]);
+    }
+}
@@ -0,0 +1,357 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::MonitorHistoryConfig;
+use crate::status::{MonitorState, MonitorStatus, MonitorUptime, StatusState};
+
+/// A run of consecutive, identical results for a monitor or group child. Every run result is
+/// recorded, but runs that don't change anything only extend the current span.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HistorySpan {
+    pub id: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub status: StatusState,
+    pub code: i64,
+    pub description: String,
+    pub runs: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl HistorySpan {
+    fn extends(&self, other: &HistorySpan) -> bool {
+        self.status == other.status
+            && self.code == other.code
+            && self.description == other.description
+            && self.metadata == other.metadata
+    }
+
+    /// Does this span count towards uptime? Failing statuses count as downtime, while a blank
+    /// status is excluded entirely.
+    fn is_up(&self) -> Option<bool> {
+        match self.status {
+            StatusState::Blank => None,
+            status => Some(!status.is_failing()),
+        }
+    }
+}
+
+/// The history for one top-level monitor (and its children), backed by a JSON lines file. Spans
+/// are appended to the file as they are created or extended, and the file is compacted once it
+/// grows too far beyond the number of spans it holds.
+#[derive(Debug, Default)]
+struct MonitorHistory {
+    spans: BTreeMap<String, VecDeque<HistorySpan>>,
+    lines: usize,
+}
+
+impl MonitorHistory {
+    fn span_count(&self) -> usize {
+        self.spans.values().map(|spans| spans.len()).sum()
+    }
+
+    fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut history = MonitorHistory::default();
+        for line in std::fs::read_to_string(path)?.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let span: HistorySpan = serde_json::from_str(line)?;
+            let spans = history.spans.entry(span.id.clone()).or_default();
+            // Later lines for the same span replace earlier ones
+            match spans.back_mut() {
+                Some(last) if last.start == span.start => *last = span,
+                _ => spans.push_back(span),
+            }
+            history.lines += 1;
+        }
+        Ok(history)
+    }
+
+    fn apply_uptime(&self, state: &mut MonitorState) {
+        let now = Utc::now();
+        state.status.uptime = self.spans.get(&state.id).map(|s| uptime(s, now));
+        for (id, child) in state.children.iter_mut() {
+            child.status.uptime = self.spans.get(id).map(|s| uptime(s, now));
+        }
+    }
+
+    fn prune(&mut self, retention: Duration) {
+        let Ok(retention) = chrono::Duration::from_std(retention) else {
+            return;
+        };
+        let cutoff = Utc::now() - retention;
+        for spans in self.spans.values_mut() {
+            while spans.front().is_some_and(|span| span.end < cutoff) {
+                spans.pop_front();
+            }
+        }
+        self.spans.retain(|_, spans| !spans.is_empty());
+    }
+
+    fn compact(&mut self, path: &Path, retention: Duration) -> Result<(), Box<dyn Error>> {
+        self.prune(retention);
+        let temp = path.with_extension("tmp");
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&temp)?);
+        for span in self.spans.values().flatten() {
+            serde_json::to_writer(&mut file, span)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+        drop(file);
+        std::fs::rename(&temp, path)?;
+        self.lines = self.span_count();
+        Ok(())
+    }
+}
+
+/// Records every run result and status transition per monitor and group child, and calculates
+/// uptime from them.
+#[derive(Debug)]
+pub struct HistoryStore {
+    dir: PathBuf,
+    config: MonitorHistoryConfig,
+    monitors: Mutex<HashMap<String, MonitorHistory>>,
+}
+
+impl HistoryStore {
+    /// Open the history store, loading (and compacting) any existing history.
+    pub fn open(dir: &Path, config: &MonitorHistoryConfig) -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(dir)?;
+        let mut monitors = HashMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().unwrap_or_default() != "jsonl" {
+                continue;
+            }
+            let id = path.file_stem().unwrap().to_string_lossy().to_string();
+            match MonitorHistory::load(&path) {
+                Ok(mut history) => {
+                    history.compact(&path, config.retention)?;
+                    monitors.insert(id, history);
+                }
+                Err(e) => warn!("Ignoring unreadable history file {:?}: {}", path, e),
+            }
+        }
+        Ok(HistoryStore {
+            dir: dir.to_owned(),
+            config: config.clone(),
+            monitors: Mutex::new(monitors),
+        })
+    }
+
+    fn path(&self, monitor_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", monitor_id))
+    }
+
+    fn span(&self, id: &str, status: &MonitorStatus) -> Option<HistorySpan> {
+        let now = status.last_run.unwrap_or_else(Utc::now);
+        Some(HistorySpan {
+            id: id.to_owned(),
+            start: now,
+            end: now,
+            status: status.status?,
+            code: status.code,
+            description: status.description.clone(),
+            runs: 1,
+            duration_ms: status.duration_ms,
+            metadata: self
+                .config
+                .metadata
+                .iter()
+                .filter_map(|k| Some((k.clone(), status.metadata.get(k)?.clone())))
+                .collect(),
+        })
+    }
+
+    /// Record the result of a completed run of this monitor, and update the uptime for the
+    /// monitor and its children.
+    pub fn record(&self, state: &mut MonitorState) -> Result<(), Box<dyn Error>> {
+        let mut monitors = self.monitors.lock().unwrap();
+        let history = monitors.entry(state.id.clone()).or_default();
+
+        let mut updated = vec![];
+        let results = std::iter::once((&state.id, &state.status))
+            .chain(state.children.iter().map(|(id, child)| (id, &child.status)));
+        for (id, status) in results {
+            let Some(span) = self.span(id, status) else {
+                continue;
+            };
+            let spans = history.spans.entry(id.clone()).or_default();
+            match spans.back_mut() {
+                Some(last) if last.extends(&span) => {
+                    last.end = span.end;
+                    last.runs += 1;
+                    last.duration_ms = span.duration_ms;
+                }
+                _ => spans.push_back(span),
+            }
+            updated.push(spans.back().unwrap().clone());
+        }
+
+        let path = self.path(&state.id);
+        if history.lines > history.span_count() * 2 + 100 {
+            history.compact(&path, self.config.retention)?;
+        } else {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?;
+            let mut lines = String::new();
+            for span in &updated {
+                lines += &serde_json::to_string(span)?;
+                lines += "\n";
+            }
+            file.write_all(lines.as_bytes())?;
+            history.lines += updated.len();
+        }
+
+        history.apply_uptime(state);
+        Ok(())
+    }
+
+    /// Fill in the uptime for a monitor and its children from the stored history.
+    pub fn update_uptime(&self, state: &mut MonitorState) {
+        let monitors = self.monitors.lock().unwrap();
+        if let Some(history) = monitors.get(&state.id) {
+            history.apply_uptime(state);
+        }
+    }
+
+    /// Get the history for a monitor or group child since the given time.
+    pub fn history(&self, id: &str, since: DateTime<Utc>) -> Option<Vec<HistorySpan>> {
+        let monitors = self.monitors.lock().unwrap();
+        let spans = monitors
+            .values()
+            .find_map(|history| history.spans.get(id))?;
+        Some(
+            spans
+                .iter()
+                .filter(|span| span.end >= since)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Calculate the uptime for a monitor or group child.
+    pub fn uptime(&self, id: &str) -> Option<MonitorUptime> {
+        let monitors = self.monitors.lock().unwrap();
+        let spans = monitors
+            .values()
+            .find_map(|history| history.spans.get(id))?;
+        Some(uptime(spans, Utc::now()))
+    }
+}
+
+fn uptime(spans: &VecDeque<HistorySpan>, now: DateTime<Utc>) -> MonitorUptime {
+    MonitorUptime {
+        day: uptime_percentage(spans, now - chrono::Duration::days(1)),
+        week: uptime_percentage(spans, now - chrono::Duration::days(7)),
+        month: uptime_percentage(spans, now - chrono::Duration::days(30)),
+    }
+}
+
+/// The percentage of runs since `since` that were up. Spans that straddle `since` contribute the
+/// fraction of their runs that fall inside the window.
+fn uptime_percentage(spans: &VecDeque<HistorySpan>, since: DateTime<Utc>) -> f64 {
+    let mut up = 0.0;
+    let mut total = 0.0;
+    for span in spans.iter().rev() {
+        if span.end < since {
+            break;
+        }
+        let Some(is_up) = span.is_up() else {
+            continue;
+        };
+        let runs = if span.start >= since || span.end == span.start {
+            span.runs as f64
+        } else {
+            let inside = (span.end - since).num_milliseconds() as f64;
+            let length = (span.end - span.start).num_milliseconds() as f64;
+            (span.runs as f64 * inside / length).max(1.0)
+        };
+        total += runs;
+        if is_up {
+            up += runs;
+        }
+    }
+    if total == 0.0 {
+        100.0
+    } else {
+        (up / total * 10000.0).round() / 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(store: &HistoryStore, state: &mut MonitorState, status: StatusState) {
+        state.status.status = Some(status);
+        state.status.last_run = Some(Utc::now());
+        store.record(state).unwrap();
+    }
+
+    #[test]
+    fn test_record_and_uptime() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("stylus-history-{}", std::process::id()));
+        let config = MonitorHistoryConfig::default();
+        let store = HistoryStore::open(&dir, &config)?;
+
+        let mut state = MonitorState::new_test("test");
+        for status in [
+            StatusState::Green,
+            StatusState::Green,
+            StatusState::Red,
+            StatusState::Green,
+            StatusState::Orange,
+            StatusState::Blue,
+            StatusState::Yellow,
+            StatusState::Green,
+        ] {
+            run(&store, &mut state, status);
+        }
+
+        let history = store.history("test", Utc::now() - chrono::Duration::days(1));
+        let history = history.unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|s| (s.status, s.runs))
+                .collect::<Vec<_>>(),
+            vec![
+                (StatusState::Green, 2),
+                (StatusState::Red, 1),
+                (StatusState::Green, 1),
+                (StatusState::Orange, 1),
+                (StatusState::Blue, 1),
+                (StatusState::Yellow, 1),
+                (StatusState::Green, 1)
+            ]
+        );
+        // Red, orange and yellow are down
+        assert_eq!(state.status.uptime.unwrap().day, 62.5);
+
+        // Reloading from disk gives the same spans
+        drop(store);
+        let store = HistoryStore::open(&dir, &config)?;
+        assert_eq!(
+            store.history("test", Utc::now() - chrono::Duration::days(1)),
+            Some(history)
+        );
+        assert_eq!(store.uptime("test").unwrap().month, 62.5);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
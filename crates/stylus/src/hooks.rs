@@ -0,0 +1,218 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+
+use subprocess::Exec;
+use tokio::sync::broadcast;
+
+use crate::config::HooksConfig;
+use crate::events::MonitorEvent;
+use crate::monitor::Monitor;
+use crate::notify::{Notification, NotificationKind};
+use crate::status::StatusState;
+use crate::worker::{run_process, WorkerMessage};
+
+impl HooksConfig {
+    /// The hooks that are triggered by a status change.
+    fn triggered(&self, event: &MonitorEvent) -> Vec<(&'static str, &Path)> {
+        let was_down = event.old_status.is_some_and(StatusState::is_failing);
+        // The first run of a monitor is not a change, or every startup and reload would run it
+        let first_run = matches!(event.old_status, None | Some(StatusState::Blank));
+        let mut hooks = vec![];
+        match &self.on_change {
+            Some(command) if !first_run => hooks.push(("on_change", command.as_path())),
+            _ => {}
+        }
+        match &self.on_fail {
+            Some(command) if event.status.is_failing() && !was_down && !first_run => {
+                hooks.push(("on_fail", command.as_path()))
+            }
+            _ => {}
+        }
+        match &self.on_recover {
+            Some(command)
+                if was_down && !event.status.is_failing() && event.status != StatusState::Blank =>
+            {
+                hooks.push(("on_recover", command.as_path()))
+            }
+            _ => {}
+        }
+        hooks
+    }
+}
+
+/// Run a hook command, passing the status change as `STYLUS_*` environment variables and as JSON
+/// on stdin. Returns an error if the command fails or times out.
+fn run_hook(
+    hook: &str,
+    command: &Path,
+    hooks: &HooksConfig,
+    event: &MonitorEvent,
+) -> Result<(), Box<dyn Error>> {
+    let mut notification: Notification = event.clone().into();
+    if hook == "on_recover" {
+        notification.kind = NotificationKind::Recovery;
+    }
+    let input = serde_json::to_vec(&notification)?;
+
+    let mut exec = if command.is_file() {
+        Exec::cmd(command)
+    } else {
+        Exec::cmd("/bin/sh").arg("-c").arg(command)
+    };
+    exec = exec
+        .cwd(&hooks.base_path)
+        .env("STYLUS_HOOK", hook)
+        .env("STYLUS_ID", &notification.id)
+        .env("STYLUS_MONITOR_ID", &event.monitor)
+        .env(
+            "STYLUS_CHILD_ID",
+            event.child.as_deref().unwrap_or_default(),
+        )
+        .env(
+            "STYLUS_OLD_STATUS",
+            event
+                .old_status
+                .map(|s| s.to_string().to_lowercase())
+                .unwrap_or_default(),
+        )
+        .env("STYLUS_STATUS", event.status.to_string().to_lowercase())
+        .env("STYLUS_CODE", event.code.to_string())
+        .env("STYLUS_DESCRIPTION", &event.description)
+        .env("STYLUS_TIME", event.time.to_rfc3339())
+        .env("STYLUS_EVENT_ID", event.id.to_string());
+
+    debug!("[{}] Running {} hook {:?}", notification.id, hook, command);
+    let mut result = Ok(());
+    run_process(
+        &notification.id,
+        exec,
+        Some(input),
+        hooks.timeout,
+        &mut |id, msg| {
+            match msg {
+                WorkerMessage::LogMessage(_, line) => {
+                    info!("[{}] {}: {}", id, hook, line.trim_end())
+                }
+                WorkerMessage::Termination(0) => {}
+                WorkerMessage::Termination(code) => {
+                    result = Err(format!("Exited with code {}", code).into())
+                }
+                WorkerMessage::AbnormalTermination(reason) => result = Err(reason.into()),
+                _ => {}
+            }
+            Ok(())
+        },
+        None,
+    )?;
+    result
+}
+
+/// Run the per-monitor and global hooks for status changes as they happen.
+pub async fn run(monitor: Arc<Monitor>) {
+    let mut events = monitor.events().subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                warn!("Skipped hooks for {} status change(s)", count);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        let global = monitor.config().notify.hooks.clone();
+        let local = monitor.hooks(&event.monitor);
+        for hooks in local.into_iter().chain([global]) {
+            for (hook, command) in hooks.triggered(&event) {
+                let command = command.to_owned();
+                let hooks = hooks.clone();
+                let event = event.clone();
+                tokio::task::spawn_blocking(move || {
+                    if let Err(e) = run_hook(hook, &command, &hooks, &event) {
+                        warn!(
+                            "[{}] {} hook {:?} failed: {}",
+                            event.child.as_deref().unwrap_or(&event.monitor),
+                            hook,
+                            command,
+                            e
+                        );
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::MonitorStatus;
+    use std::time::Duration;
+
+    fn event(old_status: StatusState, status: StatusState) -> MonitorEvent {
+        let status = MonitorStatus {
+            status: Some(status),
+            description: "Failed".into(),
+            ..Default::default()
+        };
+        MonitorEvent::from_change(
+            "modem",
+            None,
+            Some(old_status),
+            &status,
+            &Default::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_hooks() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let output = std::env::temp_dir().join(format!("stylus-hook-{}", std::process::id()));
+        let hooks = HooksConfig {
+            on_change: Some("true".into()),
+            on_fail: Some(
+                format!(
+                    "echo $STYLUS_HOOK $STYLUS_ID $STYLUS_OLD_STATUS $STYLUS_STATUS > {0}; cat >> {0}",
+                    output.display()
+                )
+                .into(),
+            ),
+            on_recover: Some("exit 1".into()),
+            timeout: Duration::from_secs(10),
+            base_path: std::env::temp_dir(),
+        };
+
+        let names = |event| {
+            hooks
+                .triggered(&event)
+                .into_iter()
+                .map(|(hook, _)| hook)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(event(Green, Red)), vec!["on_change", "on_fail"]);
+        assert_eq!(names(event(Red, Yellow)), vec!["on_change"]);
+        assert_eq!(names(event(Yellow, Green)), vec!["on_change", "on_recover"]);
+        assert_eq!(names(event(Blank, Green)), Vec::<&str>::new());
+        assert_eq!(names(event(Blank, Orange)), Vec::<&str>::new());
+
+        let event = event(Green, Red);
+        run_hook("on_fail", hooks.on_fail.as_ref().unwrap(), &hooks, &event)?;
+        let written = std::fs::read_to_string(&output)?;
+        let (env, json) = written.split_once('\n').unwrap();
+        assert_eq!(env, "on_fail modem green red");
+        let json: serde_json::Value = serde_json::from_str(json)?;
+        assert_eq!(json["description"], "Failed");
+        std::fs::remove_file(&output)?;
+
+        assert!(run_hook(
+            "on_recover",
+            hooks.on_recover.as_ref().unwrap(),
+            &hooks,
+            &event
+        )
+        .is_err());
+        Ok(())
+    }
+}
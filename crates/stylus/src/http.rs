@@ -1,36 +1,162 @@
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::{
-    extract::{Path, State},
-    http::{HeaderMap, HeaderValue, StatusCode},
-    response::{IntoResponse, Json},
-    routing::get,
-    Router,
+    extract::{OriginalUri, Path, Query, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json, Redirect, Response,
+    },
+    routing::{get, post},
+    Extension, Router,
 };
-use tokio::net::TcpListener;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tower_http::compression::CompressionLayer;
 
+use crate::auth::{required_role, Authenticator};
+use crate::badge::{status_badge, BadgeStyle};
 use crate::config::Config;
-use crate::css::generate_css_for_state;
+use crate::css::{generate_css_for_state, generate_css_rules};
+use crate::display::DisplayConfig;
+use crate::events::{EventFilter, MonitorEvent};
+use crate::filter::StatusFilter;
+use crate::history::HistorySpan;
+use crate::listener::{Connection, Listener};
 use crate::monitor::Monitor;
+use crate::reload::reload;
+use crate::status::{MonitorUptime, Status, StatusState};
+use crate::view::{UiConfigResponse, ViewConfig};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// How often a heartbeat is sent on idle streams.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
 #[derive(Clone)]
 struct AppState {
     monitor: Arc<Monitor>,
-    config: Arc<Config>,
+    /// Becomes true when the server is shutting down, so that streams can end.
+    shutdown: watch::Receiver<bool>,
+    authenticator: Arc<Authenticator>,
+}
+
+/// Checks that the request is allowed by the `auth` configuration, if there is any. This runs
+/// as a route layer, after routing, so that the routes of a view can be told apart.
+async fn auth_middleware(
+    State(state): State<AppState>,
+    connection: Option<Extension<Connection>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let config = state.monitor.config();
+    let listener = connection.as_ref().map(|connection| &connection.listener);
+    let Some(auth) = listener
+        .and_then(|listener| listener.auth.as_ref())
+        .or(config.auth.as_ref())
+    else {
+        return next.run(request).await;
+    };
+    // Views only have read-only routes, which are tagged with the view they belong to
+    let view = request
+        .extensions()
+        .get::<ViewName>()
+        .and_then(|ViewName(name)| config.views.get(name));
+    let required = match view {
+        Some(view) => view.role,
+        None => required_role(auth, request.method(), request.uri().path()),
+    };
+    let www_authenticate = if auth.users.is_empty() {
+        "Bearer realm=\"Stylus\""
+    } else {
+        "Basic realm=\"Stylus\""
+    };
+
+    // Verifying a password is slow by design, so keep it off the async threads
+    let auth = auth.clone();
+    let headers = request.headers().clone();
+    let remote = connection.map(|connection| connection.remote);
+    let authenticator = state.authenticator.clone();
+    let identity = tokio::task::spawn_blocking(move || {
+        authenticator
+            .authenticate(&auth, &headers, remote)
+            .map(|identity| {
+                let logs = identity.can_read_logs(&auth);
+                (identity, logs)
+            })
+    })
+    .await;
+
+    match identity {
+        Ok(Ok((identity, logs))) if identity.role >= required => {
+            request.extensions_mut().insert(LogAccess(logs));
+            next.run(request).await
+        }
+        Ok(Ok((identity, _))) if identity.name.is_some() => (
+            StatusCode::FORBIDDEN,
+            [("Content-Type", "text/plain")],
+            "Forbidden".to_string(),
+        )
+            .into_response(),
+        Ok(result) => {
+            let message = match result {
+                Ok(_) => "Unauthorized".to_string(),
+                Err(e) => {
+                    warn!(
+                        "Rejected credentials for {} from {:?}: {}",
+                        request.uri().path(),
+                        remote,
+                        e
+                    );
+                    e.to_string()
+                }
+            };
+            (
+                StatusCode::UNAUTHORIZED,
+                [
+                    ("Content-Type", "text/plain"),
+                    ("WWW-Authenticate", www_authenticate),
+                ],
+                message,
+            )
+                .into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [("Content-Type", "text/plain")],
+            e.to_string(),
+        )
+            .into_response(),
+    }
+}
+
+async fn css_request(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    let css = generate_css_for_state(&state.monitor.config().css, &state.monitor.status());
+    css_with_etag(&headers, css)
 }
 
-async fn css_request(State(state): State<AppState>) -> impl IntoResponse {
-    let css = generate_css_for_state(&state.monitor.config.css, &state.monitor.status());
-    (StatusCode::OK, [("Content-Type", "text/css")], css)
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatusQuery {
+    /// Comma-separated globs of monitor or group child ids.
+    id: Option<String>,
+    /// Comma-separated metadata, as `key` or `key=value`.
+    tag: Option<String>,
+    /// Comma-separated statuses.
+    status: Option<String>,
 }
 
-async fn status_request(State(state): State<AppState>) -> impl IntoResponse {
+async fn status_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Query(query): Query<StatusQuery>,
+) -> Response {
     let status = state.monitor.status();
     if let Ok(delay) = std::env::var("_STYLUS_STATUS_DELAY") {
         tokio::time::sleep(std::time::Duration::from_millis(
@@ -38,18 +164,293 @@ async fn status_request(State(state): State<AppState>) -> impl IntoResponse {
         ))
         .await;
     }
-    Json(status)
+    status_response(&headers, status, query)
+}
+
+async fn monitor_status_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path(monitor_id): Path<String>,
+) -> Response {
+    monitor_status_response(&headers, state.monitor.status(), &monitor_id, None)
+}
+
+async fn child_status_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path((monitor_id, child_id)): Path<(String, String)>,
+) -> Response {
+    monitor_status_response(
+        &headers,
+        state.monitor.status(),
+        &monitor_id,
+        Some(&child_id),
+    )
+}
+
+/// The status, filtered by the query.
+fn status_response(headers: &HeaderMap, status: Status, query: StatusQuery) -> Response {
+    let filter = match StatusFilter::parse(
+        query.id.as_deref(),
+        query.tag.as_deref(),
+        query.status.as_deref(),
+    ) {
+        Ok(filter) => filter,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, [("Content-Type", "text/plain")], e).into_response()
+        }
+    };
+    if filter.is_empty() {
+        json_with_etag(headers, &status)
+    } else {
+        json_with_etag(headers, &filter.status(&status))
+    }
+}
+
+/// The status of a single monitor, or of one of its group children.
+fn monitor_status_response(
+    headers: &HeaderMap,
+    status: Status,
+    monitor_id: &str,
+    child_id: Option<&str>,
+) -> Response {
+    for monitor in status.monitors {
+        let monitor = monitor.read();
+        if monitor.id != monitor_id {
+            continue;
+        }
+        return match child_id {
+            None => json_with_etag(headers, &*monitor),
+            Some(child_id) => match monitor.children.get(child_id) {
+                Some(child) => json_with_etag(headers, child),
+                None => break,
+            },
+        };
+    }
+    not_found()
+}
+
+async fn metrics_request(State(state): State<AppState>) -> impl IntoResponse {
+    let metrics =
+        crate::metrics::generate_metrics(&state.monitor.config().metrics, &state.monitor.status());
+    (
+        StatusCode::OK,
+        [(
+            "Content-Type",
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )],
+        metrics,
+    )
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BadgeQuery {
+    /// The text on the left, instead of the monitor or child id.
+    label: Option<String>,
+    #[serde(default)]
+    style: BadgeStyle,
+}
+
+async fn badge_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+    Query(query): Query<BadgeQuery>,
+) -> Response {
+    let Some(monitor_id) = file.strip_suffix(".svg") else {
+        return not_found();
+    };
+    badge_response(&headers, state.monitor.status(), monitor_id, None, query)
+}
+
+async fn child_badge_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path((monitor_id, file)): Path<(String, String)>,
+    Query(query): Query<BadgeQuery>,
+) -> Response {
+    let Some(child_id) = file.strip_suffix(".svg") else {
+        return not_found();
+    };
+    badge_response(
+        &headers,
+        state.monitor.status(),
+        &monitor_id,
+        Some(child_id),
+        query,
+    )
+}
+
+/// The badge for a monitor, or for one of its group children.
+fn badge_response(
+    headers: &HeaderMap,
+    status: Status,
+    monitor_id: &str,
+    child_id: Option<&str>,
+    query: BadgeQuery,
+) -> Response {
+    let Some(monitor) = status.monitors.iter().find(|m| m.read().id == monitor_id) else {
+        return not_found();
+    };
+    let monitor = monitor.read();
+    let (id, status) = match child_id {
+        None => (monitor_id, &monitor.status),
+        Some(child_id) => match monitor.children.get(child_id) {
+            Some(child) => (child_id, &child.status),
+            None => return not_found(),
+        },
+    };
+    let label = query.label.as_deref().unwrap_or(id);
+    let svg = status_badge(label, status, query.style);
+    let etag = generate_etag_from_string(&svg);
+    dynamic_content_with_etag(headers, "image/svg+xml", etag, svg)
+}
+
+async fn render_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+) -> Response {
+    let config = state.monitor.config();
+    render_response(&headers, &config, state.monitor.status(), &file, |_| true)
+}
+
+/// An SVG diagram, rendered with the status.
+fn render_response(
+    headers: &HeaderMap,
+    config: &Config,
+    status: Status,
+    file: &str,
+    config_d: impl Fn(&str) -> bool,
+) -> Response {
+    let Some(full_path) = crate::diagram::find(config, file, config_d) else {
+        return not_found();
+    };
+
+    let css = generate_css_rules(&config.css, &status);
+    let svg = std::fs::read_to_string(&full_path)
+        .map_err(|e| e.into())
+        .and_then(|svg| crate::diagram::render(&svg, &css, &status));
+    match svg {
+        Ok(svg) => {
+            let etag = generate_etag_from_string(&svg);
+            dynamic_content_with_etag(headers, "image/svg+xml", etag, svg)
+        }
+        Err(e) => render_error(file, e.to_string()),
+    }
+}
+
+fn render_error(file: &str, e: String) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        [("Content-Type", "text/plain")],
+        format!("Failed to render {}: {}", file, e),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DisplayQuery {
+    width: Option<u32>,
+    height: Option<u32>,
+    rotate: Option<u16>,
+    depth: Option<u8>,
+    dither: Option<bool>,
+}
+
+/// A dashboard rendered to a PNG. The ETag is that of the SVG it is rendered from, so that a
+/// display polling for changes can skip rendering it at all.
+async fn display_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+    Query(query): Query<DisplayQuery>,
+) -> Response {
+    let config = state.monitor.config();
+    let Some(mut display) = file
+        .strip_suffix(".png")
+        .and_then(|name| config.displays.get(name))
+        .cloned()
+    else {
+        return not_found();
+    };
+    display.width = query.width.unwrap_or(display.width);
+    display.height = query.height.unwrap_or(display.height);
+    display.rotate = query.rotate.unwrap_or(display.rotate);
+    display.depth = query.depth.unwrap_or(display.depth);
+    display.dither = query.dither.unwrap_or(display.dither);
+    if let Err(e) = display.validate() {
+        return (StatusCode::BAD_REQUEST, [("Content-Type", "text/plain")], e).into_response();
+    }
+
+    let svg = match display_svg(&config, state.monitor.status(), &display) {
+        Ok(svg) => svg,
+        Err(e) => return render_error(&file, e.to_string()),
+    };
+    let etag = generate_etag_from_string(&format!("{:?}{}", display, svg));
+    if etag_matches(&headers, &etag) {
+        return not_modified(&etag);
+    }
+
+    let resources_dir = config.server.static_path.clone();
+    let png = tokio::task::spawn_blocking(move || {
+        crate::display::rasterize(&svg, &display, resources_dir).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()));
+    match png {
+        Ok(png) => dynamic_content_with_etag(&headers, "image/png", etag, png),
+        Err(e) => render_error(&file, e),
+    }
+}
+
+/// The SVG that a display is rendered from: its diagram, or a grid of its monitors.
+fn display_svg(
+    config: &Config,
+    mut status: Status,
+    display: &DisplayConfig,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(view) = display
+        .view
+        .as_ref()
+        .and_then(|name| config.views.get(name))
+    {
+        status = view.status(&status);
+    }
+    match &display.diagram {
+        Some(diagram) => {
+            let full_path = crate::diagram::find(config, diagram, |_| true)
+                .ok_or_else(|| format!("Diagram {} not found", diagram))?;
+            let svg = std::fs::read_to_string(full_path)?;
+            let css = generate_css_rules(&config.css, &status);
+            crate::diagram::render(&svg, &css, &status)
+        }
+        None => {
+            let (width, height) = display.content_size();
+            let color = display.depth == 24;
+            Ok(crate::display::grid(
+                &status,
+                display.columns,
+                width,
+                height,
+                color,
+            ))
+        }
+    }
 }
 
-async fn config_request(State(state): State<AppState>) -> impl IntoResponse {
-    let config = state.config.clone();
+async fn config_request(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    let config = state.monitor.config();
     if let Ok(delay) = std::env::var("_STYLUS_CONFIG_DELAY") {
         tokio::time::sleep(std::time::Duration::from_millis(
             delay.parse::<u64>().unwrap(),
         ))
         .await;
     }
-    Json(config)
+    let ui = config.ui.clone();
+    json_with_etag(&headers, &UiConfigResponse::new(&config, ui, |_| true))
 }
 
 async fn log_request(
@@ -74,43 +475,434 @@ async fn log_request(
     )
 }
 
-async fn default_index(state: AppState) -> impl IntoResponse {
-    use crate::status::MonitorState;
-    use handlebars::Handlebars;
-    use keepcalm::SharedMut;
-    use serde::Serialize;
-    use std::convert::identity;
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AckRequest {
+    #[serde(default)]
+    comment: Option<String>,
+    #[serde(default, with = "humantime_serde")]
+    expires: Option<Duration>,
+}
+
+async fn ack_request(
+    State(state): State<AppState>,
+    Path(monitor_id): Path<String>,
+    request: Option<Json<AckRequest>>,
+) -> impl IntoResponse {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let Some(res) = state
+        .monitor
+        .acknowledge(&monitor_id, request.comment, request.expires)
+    else {
+        return (
+            StatusCode::NOT_FOUND,
+            [("Content-Type", "text/plain")],
+            "Not found".to_string(),
+        )
+            .into_response();
+    };
+    match res {
+        Ok(ack) => {
+            info!("[{}] Acknowledged: {:?}", monitor_id, ack);
+            Json(ack).into_response()
+        }
+        Err(e) => (
+            StatusCode::CONFLICT,
+            [("Content-Type", "text/plain")],
+            e.to_string(),
+        )
+            .into_response(),
+    }
+}
+
+async fn run_request(
+    State(state): State<AppState>,
+    Path(monitor_id): Path<String>,
+) -> impl IntoResponse {
+    let Some((monitor, control)) = state.monitor.control(&monitor_id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            [("Content-Type", "text/plain")],
+            "Not found".to_string(),
+        )
+            .into_response();
+    };
+
+    // Allow for the process to be terminated and killed after the timeout
+    let timeout = monitor.read().config.timeout + Duration::from_secs(15);
+    let target = control.request_run();
+    let completed =
+        tokio::task::spawn_blocking(move || control.wait_for_run(target, timeout)).await;
+    if completed.unwrap_or_default() {
+        Json(monitor).into_response()
+    } else {
+        (StatusCode::GATEWAY_TIMEOUT, Json(monitor)).into_response()
+    }
+}
+
+async fn pause_request(
+    State(state): State<AppState>,
+    Path(monitor_id): Path<String>,
+) -> impl IntoResponse {
+    set_paused(state, monitor_id, true)
+}
+
+async fn resume_request(
+    State(state): State<AppState>,
+    Path(monitor_id): Path<String>,
+) -> impl IntoResponse {
+    set_paused(state, monitor_id, false)
+}
 
-    let mut handlebars = Handlebars::new();
-    if let Err(e) = handlebars.register_template_string("t", include_str!("./index.html")) {
+fn set_paused(state: AppState, monitor_id: String, paused: bool) -> impl IntoResponse {
+    let Some(monitor) = state.monitor.set_paused(&monitor_id, paused) else {
         return (
+            StatusCode::NOT_FOUND,
+            [("Content-Type", "text/plain")],
+            "Not found".to_string(),
+        )
+            .into_response();
+    };
+    info!(
+        "[{}] {}",
+        monitor_id,
+        if paused { "Paused" } else { "Resumed" }
+    );
+    Json(monitor).into_response()
+}
+
+async fn reload_request(State(state): State<AppState>) -> impl IntoResponse {
+    let monitor = state.monitor.clone();
+    match tokio::task::spawn_blocking(move || reload(&monitor, "API request")).await {
+        Ok(Ok(summary)) => Json(summary).into_response(),
+        Ok(Err(e)) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            [("Content-Type", "text/plain")],
+            e,
+        )
+            .into_response(),
+        Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            [("Content-Type", "text/html")],
+            [("Content-Type", "text/plain")],
             e.to_string(),
-        );
+        )
+            .into_response(),
     }
+}
 
-    let mut monitors = vec![];
-    for monitor in state.monitor.status().monitors {
-        monitors.push(monitor);
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HistoryQuery {
+    #[serde(default = "default_history_window", with = "humantime_serde")]
+    window: Duration,
+}
+
+fn default_history_window() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+async fn history_request(
+    State(state): State<AppState>,
+    Path(monitor_id): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> impl IntoResponse {
+    let Some(history) = state.monitor.history() else {
+        return (
+            StatusCode::NOT_FOUND,
+            [("Content-Type", "text/plain")],
+            "History is disabled".to_string(),
+        )
+            .into_response();
+    };
+    let since = chrono::Duration::from_std(query.window)
+        .ok()
+        .and_then(|window| chrono::Utc::now().checked_sub_signed(window))
+        .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+
+    #[derive(Serialize)]
+    struct HistoryResponse {
+        id: String,
+        uptime: MonitorUptime,
+        history: Vec<HistorySpan>,
+    }
+
+    match (
+        history.history(&monitor_id, since),
+        history.uptime(&monitor_id),
+    ) {
+        (Some(history), Some(uptime)) => Json(HistoryResponse {
+            id: monitor_id,
+            uptime,
+            history,
+        })
+        .into_response(),
+        _ => (
+            StatusCode::NOT_FOUND,
+            [("Content-Type", "text/plain")],
+            "Not found".to_string(),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EventsQuery {
+    /// Either an event id, or an RFC 3339 timestamp.
+    since: Option<String>,
+    monitor: Option<String>,
+    status: Option<StatusState>,
+    limit: Option<usize>,
+}
+
+async fn events_request(
+    State(state): State<AppState>,
+    log_access: Option<Extension<LogAccess>>,
+    Query(query): Query<EventsQuery>,
+) -> impl IntoResponse {
+    let mut filter = EventFilter {
+        id: query.monitor,
+        status: query.status,
+        limit: query.limit,
+        ..Default::default()
+    };
+    if let Some(since) = query.since {
+        if let Ok(id) = since.parse::<u64>() {
+            filter.after = Some(id);
+        } else if let Ok(time) = chrono::DateTime::parse_from_rfc3339(&since) {
+            filter.since = Some(time.to_utc());
+        } else {
+            return (
+                StatusCode::BAD_REQUEST,
+                [("Content-Type", "text/plain")],
+                format!("Invalid since parameter: {}", since),
+            )
+                .into_response();
+        }
     }
 
     #[derive(Serialize)]
-    struct Model {
-        monitors: Vec<SharedMut<MonitorState>>,
+    struct EventsResponse {
+        events: Vec<MonitorEvent>,
+    }
+
+    let mut events = state.monitor.events().query(&filter);
+    if let Some(Extension(LogAccess(false))) = log_access {
+        for event in &mut events {
+            event.log.clear();
+        }
     }
+    Json(EventsResponse { events }).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StreamQuery {
+    /// The id of the last event received, for clients that can't send `Last-Event-ID`.
+    since: Option<String>,
+}
+
+async fn stream_request(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<StreamQuery>,
+) -> impl IntoResponse {
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_owned)
+        .or(query.since);
+    let stream = crate::stream::stream(
+        state.monitor.clone(),
+        last_event_id.as_deref(),
+        state.shutdown.clone(),
+    );
+    Sse::new(stream).keep_alive(
+        KeepAlive::new()
+            .interval(HEARTBEAT_INTERVAL)
+            .event(Event::default().event("heartbeat")),
+    )
+}
+
+/// Whether the authenticated caller may read the logs of monitors. Missing when there is no
+/// `auth` configuration.
+#[derive(Clone)]
+struct LogAccess(bool);
 
-    let html = handlebars
-        .render("t", &Model { monitors })
-        .map_or_else(|e| e.to_string(), identity)
-        .trim()
-        .to_owned();
+/// The name of the view that a nested route belongs to.
+#[derive(Clone)]
+struct ViewName(String);
+
+/// The current configuration of a view, which may have been removed by a reload.
+fn view(state: &AppState, name: &str) -> Option<ViewConfig> {
+    state.monitor.config().views.get(name).cloned()
+}
 
+fn not_found() -> Response {
     (
-        StatusCode::OK,
-        [("Content-Type", "text/html; charset=utf-8")],
-        html,
+        StatusCode::NOT_FOUND,
+        [("Content-Type", "text/plain")],
+        "Not found".to_string(),
     )
+        .into_response()
+}
+
+async fn view_css_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Extension(ViewName(name)): Extension<ViewName>,
+) -> Response {
+    let Some(view) = view(&state, &name) else {
+        return not_found();
+    };
+    let status = view.status(&state.monitor.status());
+    let css = generate_css_for_state(&state.monitor.config().css, &status);
+    css_with_etag(&headers, css)
+}
+
+async fn view_status_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Extension(ViewName(name)): Extension<ViewName>,
+    Query(query): Query<StatusQuery>,
+) -> Response {
+    let Some(view) = view(&state, &name) else {
+        return not_found();
+    };
+    status_response(&headers, view.status(&state.monitor.status()), query)
+}
+
+async fn view_monitor_status_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Extension(ViewName(name)): Extension<ViewName>,
+    Path(monitor_id): Path<String>,
+) -> Response {
+    let Some(view) = view(&state, &name) else {
+        return not_found();
+    };
+    let status = view.status(&state.monitor.status());
+    monitor_status_response(&headers, status, &monitor_id, None)
+}
+
+async fn view_child_status_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Extension(ViewName(name)): Extension<ViewName>,
+    Path((monitor_id, child_id)): Path<(String, String)>,
+) -> Response {
+    let Some(view) = view(&state, &name) else {
+        return not_found();
+    };
+    let status = view.status(&state.monitor.status());
+    monitor_status_response(&headers, status, &monitor_id, Some(&child_id))
+}
+
+async fn view_badge_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Extension(ViewName(name)): Extension<ViewName>,
+    Path(file): Path<String>,
+    Query(query): Query<BadgeQuery>,
+) -> Response {
+    let Some(view) = view(&state, &name) else {
+        return not_found();
+    };
+    let Some(monitor_id) = file.strip_suffix(".svg") else {
+        return not_found();
+    };
+    let status = view.status(&state.monitor.status());
+    badge_response(&headers, status, monitor_id, None, query)
+}
+
+async fn view_child_badge_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Extension(ViewName(name)): Extension<ViewName>,
+    Path((monitor_id, file)): Path<(String, String)>,
+    Query(query): Query<BadgeQuery>,
+) -> Response {
+    let Some(view) = view(&state, &name) else {
+        return not_found();
+    };
+    let Some(child_id) = file.strip_suffix(".svg") else {
+        return not_found();
+    };
+    let status = view.status(&state.monitor.status());
+    badge_response(&headers, status, &monitor_id, Some(child_id), query)
+}
+
+async fn view_render_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Extension(ViewName(name)): Extension<ViewName>,
+    Path(file): Path<String>,
+) -> Response {
+    let Some(view) = view(&state, &name) else {
+        return not_found();
+    };
+    let status = view.status(&state.monitor.status());
+    render_response(&headers, &state.monitor.config(), status, &file, |name| {
+        view.config_d.iter().any(|allowed| allowed == name)
+    })
+}
+
+async fn view_config_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Extension(ViewName(name)): Extension<ViewName>,
+) -> Response {
+    let Some(view) = view(&state, &name) else {
+        return not_found();
+    };
+    json_with_etag(&headers, &view.config(&state.monitor.config()))
+}
+
+async fn view_index_handler(
+    headers: HeaderMap,
+    OriginalUri(uri): OriginalUri,
+    State(state): State<AppState>,
+    Extension(ViewName(name)): Extension<ViewName>,
+) -> Response {
+    let Some(view) = view(&state, &name) else {
+        return not_found();
+    };
+    if let Some(redirect) = trailing_slash_redirect(&headers, &uri) {
+        return redirect;
+    }
+    let status = view.status(&state.monitor.status());
+    let ui = view.ui.or_else(|| state.monitor.config().ui.clone());
+    index(headers, state, status, ui, false).await
+}
+
+async fn view_dashboard_request(
+    State(state): State<AppState>,
+    Extension(ViewName(name)): Extension<ViewName>,
+) -> Response {
+    let Some(view) = view(&state, &name) else {
+        return not_found();
+    };
+    let status = view.status(&state.monitor.status());
+    let ui = view.ui.or_else(|| state.monitor.config().ui.clone());
+    default_index(&status, ui.as_ref(), false)
+}
+
+/// The dashboard rendered on the server, for browsers that can't run the web UI.
+fn default_index(status: &Status, ui: Option<&serde_value::Value>, logs: bool) -> Response {
+    match crate::dashboard::render(ui, status, logs) {
+        Ok(html) => (
+            StatusCode::OK,
+            [("Content-Type", "text/html; charset=utf-8")],
+            html,
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [("Content-Type", "text/plain")],
+            e.to_string(),
+        )
+            .into_response(),
+    }
 }
 
 /// Generate an ETag from file content hash
@@ -211,6 +1003,66 @@ fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
     false
 }
 
+/// Handle ETag cache validation for content that is generated on every request, so that clients
+/// polling for changes don't need to download it again.
+fn dynamic_content_with_etag(
+    headers: &HeaderMap,
+    content_type: &'static str,
+    etag: String,
+    content: impl IntoResponse,
+) -> Response {
+    if etag_matches(headers, &etag) {
+        return not_modified(&etag);
+    }
+
+    (
+        StatusCode::OK,
+        [
+            ("Content-Type", HeaderValue::from_static(content_type)),
+            ("Cache-Control", HeaderValue::from_static("no-cache")),
+            ("ETag", HeaderValue::from_str(&etag).unwrap()),
+        ],
+        content,
+    )
+        .into_response()
+}
+
+fn not_modified(etag: &str) -> Response {
+    (
+        StatusCode::NOT_MODIFIED,
+        [
+            ("ETag", HeaderValue::from_str(etag).unwrap()),
+            ("Cache-Control", HeaderValue::from_static("no-cache")),
+        ],
+        "",
+    )
+        .into_response()
+}
+
+fn json_with_etag(headers: &HeaderMap, value: &impl Serialize) -> Response {
+    match serde_json::to_string(value) {
+        Ok(json) => {
+            let etag = generate_etag_from_string(&json);
+            dynamic_content_with_etag(headers, "application/json", etag, json)
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [("Content-Type", "text/plain")],
+            e.to_string(),
+        )
+            .into_response(),
+    }
+}
+
+/// The generated CSS starts with the time it was generated, which is left out of the ETag.
+fn css_with_etag(headers: &HeaderMap, css: String) -> Response {
+    let rules = css
+        .split_once('\n')
+        .map_or(css.as_str(), |(_, rules)| rules);
+    let etag = generate_etag_from_string(rules);
+    dynamic_content_with_etag(headers, "text/css", etag, css)
+}
+
 #[allow(unused)]
 fn handle_static_content_with_etag(
     headers: HeaderMap,
@@ -261,7 +1113,7 @@ async fn static_files_handler(
     Path(file): Path<String>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    if let Some(static_path) = &state.monitor.config.server.static_path {
+    if let Some(static_path) = &state.monitor.config().server.static_path {
         let full_path = static_path.join(&file);
 
         // Security check: ensure the file is within the static directory
@@ -289,8 +1141,59 @@ async fn static_files_handler(
         .into_response()
 }
 
-async fn index_handler(headers: HeaderMap, State(state): State<AppState>) -> impl IntoResponse {
-    if let Some(static_path) = &state.monitor.config.server.static_path {
+async fn index_handler(
+    headers: HeaderMap,
+    OriginalUri(uri): OriginalUri,
+    State(state): State<AppState>,
+) -> Response {
+    if let Some(redirect) = trailing_slash_redirect(&headers, &uri) {
+        return redirect;
+    }
+    let status = state.monitor.status();
+    let ui = state.monitor.config().ui.clone();
+    index(headers, state, status, ui, true).await
+}
+
+async fn dashboard_request(State(state): State<AppState>) -> Response {
+    let config = state.monitor.config();
+    default_index(&state.monitor.status(), config.ui.as_ref(), true)
+}
+
+/// The prefix that a reverse proxy stripped from the path, from `X-Forwarded-Prefix`.
+fn forwarded_prefix(headers: &HeaderMap) -> &str {
+    let prefix = headers
+        .get("x-forwarded-prefix")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .trim_end_matches('/');
+    // Only accept a plain path, so that a redirect can't point to another host
+    if prefix.starts_with('/') && !prefix.starts_with("//") && !prefix.contains(['\\', '?', '#']) {
+        prefix
+    } else {
+        ""
+    }
+}
+
+/// Redirects to the path with a trailing slash, which the UI's relative URLs need to resolve
+/// correctly, unless it already has one.
+fn trailing_slash_redirect(headers: &HeaderMap, uri: &Uri) -> Option<Response> {
+    if uri.path().ends_with('/') {
+        return None;
+    }
+    let location = format!("{}{}/", forwarded_prefix(headers), uri.path());
+    Some(Redirect::permanent(&location).into_response())
+}
+
+/// The index page, which falls back to the server-rendered dashboard of the given status if
+/// there is no static `index.html` or built-in UI.
+async fn index(
+    headers: HeaderMap,
+    state: AppState,
+    status: Status,
+    ui: Option<serde_value::Value>,
+    logs: bool,
+) -> Response {
+    if let Some(static_path) = &state.monitor.config().server.static_path {
         let full_path = static_path.join("index.html");
         if full_path.exists() {
             return handle_static_file_with_etag(headers, full_path)
@@ -300,7 +1203,7 @@ async fn index_handler(headers: HeaderMap, State(state): State<AppState>) -> imp
     }
 
     if cfg!(not(feature = "builtin-ui")) {
-        return default_index(state).await.into_response();
+        return default_index(&status, ui.as_ref(), logs);
     }
 
     #[cfg(feature = "builtin-ui")]
@@ -323,19 +1226,33 @@ async fn index_handler(headers: HeaderMap, State(state): State<AppState>) -> imp
         .into_response()
 }
 
-pub async fn run(config: Config, dry_run: bool) {
-    let config = Arc::new(config);
-    let monitor = Arc::new(Monitor::new(&config).expect("Unable to create monitor"));
-    let state = AppState { monitor, config };
-
-    // Build the router
-    let mut app = Router::new()
-        .route("/style.css", get(css_request))
-        .route("/status.json", get(status_request))
-        .route("/config.json", get(config_request))
-        .route("/log/:monitor_id", get(log_request))
-        .route("/", get(index_handler));
+/// The routes of a view, which only serve what the UI needs to show its filtered status, and
+/// never logs.
+fn view_routes(name: &str, config: &Config, state: &AppState) -> Router<AppState> {
+    let app = Router::new()
+        .route("/style.css", get(view_css_request))
+        .route("/status.json", get(view_status_request))
+        .route("/status/:monitor_id", get(view_monitor_status_request))
+        .route(
+            "/status/:monitor_id/children/:child_id",
+            get(view_child_status_request),
+        )
+        .route("/badge/:file", get(view_badge_request))
+        .route("/badge/:monitor_id/:file", get(view_child_badge_request))
+        .route("/render/*file", get(view_render_request))
+        .route("/config.json", get(view_config_request))
+        .route("/dashboard.html", get(view_dashboard_request))
+        .route("/", get(view_index_handler));
+    ui_routes(app, config)
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth_middleware,
+        ))
+        .layer(Extension(ViewName(name.to_owned())))
+}
 
+/// The routes for the built-in UI and static files, which are served at the root and by views.
+fn ui_routes(mut app: Router<AppState>, config: &Config) -> Router<AppState> {
     #[cfg(feature = "builtin-ui")]
     {
         app = app
@@ -345,7 +1262,7 @@ pub async fn run(config: Config, dry_run: bool) {
                     handle_static_content_with_etag(
                         headers,
                         "text/javascript; charset=utf-8",
-                        Some("/stylus.js.map"),
+                        Some("stylus.js.map"),
                         &stylus_ui::STYLUS_JAVASCRIPT,
                     )
                 }),
@@ -389,31 +1306,158 @@ pub async fn run(config: Config, dry_run: bool) {
     }
 
     // Add static files route if configured
-    if state.config.server.static_path.is_some() {
+    if config.server.static_path.is_some() {
         app = app.route("/*file", get(static_files_handler));
     }
 
-    let ip_addr = state
-        .config
+    app
+}
+
+pub async fn run(mut config: Config, dry_run: bool) {
+    // A dry run only checks the configuration, so leave the state and history on disk alone
+    if dry_run {
+        config.monitor.state.file = None;
+        config.monitor.history.dir = None;
+    }
+    let monitor = Arc::new(Monitor::new(&config).expect("Unable to create monitor"));
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    let state = AppState {
+        monitor,
+        shutdown,
+        authenticator: Default::default(),
+    };
+
+    let path_prefix = config.server.path_prefix();
+
+    // Build the router
+    let mut app = Router::new()
+        .route("/style.css", get(css_request))
+        .route("/status.json", get(status_request))
+        .route("/status/:monitor_id", get(monitor_status_request))
+        .route(
+            "/status/:monitor_id/children/:child_id",
+            get(child_status_request),
+        )
+        .route("/badge/:file", get(badge_request))
+        .route("/badge/:monitor_id/:file", get(child_badge_request))
+        .route("/render/*file", get(render_request))
+        .route("/display/:file", get(display_request))
+        .route("/config.json", get(config_request))
+        .route("/dashboard.html", get(dashboard_request))
+        .route("/log/:monitor_id", get(log_request))
+        .route("/monitor/:monitor_id/ack", post(ack_request))
+        .route("/monitor/:monitor_id/run", post(run_request))
+        .route("/monitor/:monitor_id/pause", post(pause_request))
+        .route("/monitor/:monitor_id/resume", post(resume_request))
+        .route("/reload", post(reload_request))
+        .route("/history/:monitor_id", get(history_request))
+        .route("/events.json", get(events_request))
+        .route("/events/stream", get(stream_request))
+        .route("/metrics", get(metrics_request))
+        .route("/", get(index_handler));
+
+    // Every route, including static files, is subject to the `auth` configuration
+    let auth = middleware::from_fn_with_state(state.clone(), auth_middleware);
+    app = ui_routes(app, &config).route_layer(auth.clone());
+
+    for (name, view) in &config.views {
+        let path = view.path(name);
+        // A nested `/` only matches the path without a trailing slash
+        app = app
+            .route(
+                &format!("{path}/"),
+                get(view_index_handler)
+                    .route_layer(auth.clone())
+                    .layer(Extension(ViewName(name.clone()))),
+            )
+            .nest(&path, view_routes(name, &config, &state));
+    }
+
+    let listeners = config
         .server
-        .listen_addr
-        .parse::<IpAddr>()
+        .listeners()
         .expect("Failed to parse listen address");
-    let addr = SocketAddr::new(ip_addr, state.config.server.port);
 
     // We print one and only one message
-    eprintln!("Stylus {} is listening on {}!", VERSION, addr);
+    eprintln!(
+        "Stylus {} is listening on {}!",
+        VERSION,
+        listeners.iter().join(", ")
+    );
 
     if dry_run {
         eprintln!("Dry run complete. Exiting.");
         return;
     }
 
-    // Run the server
-    let listener = TcpListener::bind(&addr)
-        .await
-        .expect("Failed to bind to address");
-    axum::serve(listener, app.with_state(state))
-        .await
-        .expect("Server error");
+    tokio::spawn(crate::reload::watch(state.monitor.clone()));
+    #[cfg(unix)]
+    tokio::spawn(crate::reload::watch_signal(state.monitor.clone()));
+
+    tokio::spawn(crate::snapshot::persist(state.monitor.clone()));
+    tokio::spawn(crate::monitor::expire_acknowledgements(
+        state.monitor.clone(),
+    ));
+    tokio::spawn(crate::notify::run(state.monitor.clone()));
+    tokio::spawn(crate::hooks::run(state.monitor.clone()));
+    tokio::spawn(crate::mqtt::run(state.monitor.clone()));
+    tokio::spawn(crate::export::run(state.monitor.clone()));
+
+    // Run a server for each listener
+    let mut servers = vec![];
+    for listener_config in listeners {
+        let listener_app = match &listener_config.view {
+            Some(name) => view_routes(name, &config, &state),
+            None => app.clone(),
+        };
+        let listener_app = listener_app
+            .layer(CompressionLayer::new())
+            .with_state(state.clone());
+        let listener_app = match &path_prefix {
+            Some(path_prefix) => Router::new().nest_service(path_prefix, listener_app),
+            None => listener_app,
+        };
+        let description = listener_config.to_string();
+        let listener = Listener::bind(listener_config)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to bind to {}: {}", description, e));
+        servers.push(tokio::spawn(
+            listener.serve(listener_app, state.shutdown.clone()),
+        ));
+    }
+
+    shutdown_signal().await;
+    // Streams never finish on their own, so ask them to, along with the listeners
+    let _ = shutdown_sender.send(true);
+    for server in servers {
+        let _ = server.await;
+    }
+
+    if let Err(e) = crate::snapshot::save(&state.monitor) {
+        error!("Failed to save monitor state: {}", e);
+    }
+}
+
+/// Resolves when the process is asked to shut down via Ctrl+C or `SIGTERM`.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => drop(term.recv().await),
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("Shutting down");
 }
@@ -41,6 +41,20 @@ pub fn interpolate_id(
     Ok(handlebars.render("t", values)?.trim().to_owned())
 }
 
+handlebars_helper!(json: |value: Json| serde_json::to_string(value).unwrap_or_default());
+
+/// Render a template for a notification, which is not HTML and is therefore not escaped. The
+/// `json` helper may be used to embed values in JSON payloads (eg: `{"text": {{json description}}}`).
+pub fn interpolate_template<T: Serialize>(context: &T, s: &str) -> Result<String, Box<dyn Error>> {
+    // TODO: avoid creating this handlebars registry every time
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(no_escape);
+    handlebars.register_helper("json", Box::new(json));
+    handlebars.register_template_string("t", s)?;
+
+    Ok(handlebars.render("t", context)?.trim().to_owned())
+}
+
 pub fn interpolate_modify<'a>(
     mut status: &'a mut MonitorStatus,
     children: &'a mut BTreeMap<String, MonitorChildStatus>,
@@ -148,6 +162,16 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_interpolate_template() -> Result<(), Box<dyn Error>> {
+        let context = serde_json::json!({"id": "a&b", "description": "Say \"hi\""});
+        assert_eq!(
+            interpolate_template(&context, "{{id}}: {\"text\": {{json description}}}")?,
+            "a&b: {\"text\": \"Say \\\"hi\\\"\"}"
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_modify() -> Result<(), Box<dyn Error>> {
         let status = update("status.status=\"red\"")?;
@@ -0,0 +1,469 @@
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use axum::extract::Request;
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::{GracefulShutdown, Watcher};
+use hyper_util::service::TowerToHyperService;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_rustls::rustls::crypto::{aws_lc_rs, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+use crate::auth::AuthConfig;
+
+/// How often certificate and key files are checked for changes.
+const CERTIFICATE_WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait before accepting again after a failure, such as running out of file
+/// descriptors.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// An address the HTTP server listens on, and how requests to it are served.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// An IPv4 or IPv6 address and port, such as `0.0.0.0:8000` or `[::]:8000`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<SocketAddr>,
+    /// The path of a Unix domain socket.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix: Option<PathBuf>,
+    /// Serve HTTPS with these certificate and key files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    /// Authentication for this listener, instead of the top-level `auth` section.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthConfig>,
+    /// Serve only this view, at the root of the listener.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// A PEM file containing the certificate chain.
+    pub cert: PathBuf,
+    /// A PEM file containing the private key.
+    pub key: PathBuf,
+}
+
+impl ListenerConfig {
+    /// Check that the listener has exactly one address.
+    pub fn validate(&self) -> Result<(), String> {
+        match (&self.address, &self.unix) {
+            (Some(_), None) => Ok(()),
+            (None, Some(_)) if cfg!(unix) => Ok(()),
+            (None, Some(_)) => Err("Unix sockets are not supported on this platform".into()),
+            _ => Err("Each listener needs exactly one of address or unix".into()),
+        }
+    }
+}
+
+impl fmt::Display for ListenerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.address, &self.unix) {
+            (Some(address), _) => write!(f, "{}", address)?,
+            (None, Some(path)) => write!(f, "unix:{}", path.display())?,
+            (None, None) => write!(f, "nowhere")?,
+        }
+        if self.tls.is_some() {
+            write!(f, " (TLS)")?;
+        }
+        if let Some(view) = &self.view {
+            write!(f, " (view {})", view)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where a connection came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Remote {
+    Ip(IpAddr),
+    Unix,
+}
+
+/// The connection that a request arrived on, available to handlers as a request extension.
+#[derive(Clone, Debug)]
+pub struct Connection {
+    pub listener: Arc<ListenerConfig>,
+    pub remote: Remote,
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+enum Socket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+/// Serves certificates that are reloaded when their files change.
+#[derive(Debug)]
+struct CertificateResolver {
+    tls: TlsConfig,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.read().unwrap().clone())
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(aws_lc_rs::default_provider())
+}
+
+fn load_certificate(tls: &TlsConfig) -> Result<CertifiedKey, Box<dyn Error>> {
+    let certs = CertificateDer::pem_file_iter(&tls.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Unable to read {}: {}", tls.cert.display(), e))?;
+    let key = PrivateKeyDer::from_pem_file(&tls.key)
+        .map_err(|e| format!("Unable to read {}: {}", tls.key.display(), e))?;
+    Ok(CertifiedKey::from_der(certs, key, &provider())?)
+}
+
+/// Poll the certificate and key files, reloading them when either changes. A certificate that
+/// fails to load is logged, and the current one is kept.
+async fn watch_certificate(resolver: Arc<CertificateResolver>) {
+    let fingerprint = |tls: &TlsConfig| -> [Option<SystemTime>; 2] {
+        [&tls.cert, &tls.key].map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+    };
+    let mut last = fingerprint(&resolver.tls);
+    loop {
+        tokio::time::sleep(CERTIFICATE_WATCH_INTERVAL).await;
+        let current = fingerprint(&resolver.tls);
+        if current == last {
+            continue;
+        }
+        last = current;
+        match load_certificate(&resolver.tls) {
+            Ok(key) => {
+                *resolver.key.write().unwrap() = Arc::new(key);
+                info!("Reloaded TLS certificate {}", resolver.tls.cert.display());
+            }
+            Err(e) => warn!(
+                "Failed to reload TLS certificate, keeping the current one: {}",
+                e
+            ),
+        }
+    }
+}
+
+/// A bound listener, ready to serve.
+pub struct Listener {
+    config: Arc<ListenerConfig>,
+    socket: Socket,
+    tls: Option<(TlsAcceptor, Arc<CertificateResolver>)>,
+}
+
+impl Listener {
+    /// Bind the listener's address or socket, and load its certificate if it serves TLS.
+    pub async fn bind(config: ListenerConfig) -> Result<Self, Box<dyn Error>> {
+        config.validate()?;
+        let tls = match &config.tls {
+            Some(tls) => {
+                let resolver = Arc::new(CertificateResolver {
+                    tls: tls.clone(),
+                    key: RwLock::new(Arc::new(load_certificate(tls)?)),
+                });
+                let mut server_config = ServerConfig::builder_with_provider(provider())
+                    .with_safe_default_protocol_versions()?
+                    .with_no_client_auth()
+                    .with_cert_resolver(resolver.clone());
+                server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+                Some((TlsAcceptor::from(Arc::new(server_config)), resolver))
+            }
+            None => None,
+        };
+
+        let socket = match (&config.address, &config.unix) {
+            (Some(address), _) => Socket::Tcp(TcpListener::bind(address).await?),
+            #[cfg(unix)]
+            (None, Some(path)) => {
+                // A socket left behind by a previous run would stop us from binding
+                use std::os::unix::fs::FileTypeExt;
+                if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                Socket::Unix(tokio::net::UnixListener::bind(path)?, path.clone())
+            }
+            _ => unreachable!("validated above"),
+        };
+
+        Ok(Listener {
+            config: Arc::new(config),
+            socket,
+            tls,
+        })
+    }
+
+    /// The address that was bound, which may differ from the configured one when binding to
+    /// port 0.
+    #[cfg(test)]
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.socket {
+            Socket::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Socket::Unix(..) => None,
+        }
+    }
+
+    async fn accept(&self) -> std::io::Result<(Box<dyn Stream>, Remote)> {
+        match &self.socket {
+            Socket::Tcp(listener) => {
+                let (stream, address) = listener.accept().await?;
+                let _ = stream.set_nodelay(true);
+                Ok((Box::new(stream), Remote::Ip(address.ip())))
+            }
+            #[cfg(unix)]
+            Socket::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Box::new(stream), Remote::Unix))
+            }
+        }
+    }
+
+    /// Serve requests until `shutdown` becomes true, then wait for open connections to finish.
+    pub async fn serve(self, app: Router, mut shutdown: watch::Receiver<bool>) {
+        let graceful = GracefulShutdown::new();
+        let watch_task = self
+            .tls
+            .as_ref()
+            .map(|(_, resolver)| tokio::spawn(watch_certificate(resolver.clone())));
+
+        loop {
+            let accepted = tokio::select! {
+                accepted = self.accept() => accepted,
+                _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+            };
+            let (stream, remote) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept a connection on {}: {}", self.config, e);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+            let connection = Connection {
+                listener: self.config.clone(),
+                remote,
+            };
+            let tls = self.tls.as_ref().map(|(acceptor, _)| acceptor.clone());
+            let app = app.clone();
+            let watcher = graceful.watcher();
+            tokio::spawn(async move {
+                let stream: Box<dyn Stream> = match tls {
+                    Some(acceptor) => match acceptor.accept(stream).await {
+                        Ok(stream) => Box::new(stream),
+                        Err(e) => {
+                            debug!("TLS handshake with {:?} failed: {}", connection.remote, e);
+                            return;
+                        }
+                    },
+                    None => stream,
+                };
+                serve_connection(stream, app, connection, watcher).await;
+            });
+        }
+
+        if let Some(task) = watch_task {
+            task.abort();
+        }
+        #[cfg(unix)]
+        if let Socket::Unix(_, path) = &self.socket {
+            let _ = std::fs::remove_file(path);
+        }
+        drop(self);
+        graceful.shutdown().await;
+    }
+}
+
+async fn serve_connection(
+    stream: Box<dyn Stream>,
+    app: Router,
+    connection: Connection,
+    watcher: Watcher,
+) {
+    let service = app.map_request(move |mut request: Request<hyper::body::Incoming>| {
+        request.extensions_mut().insert(connection.clone());
+        request
+    });
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let conn = builder
+        .serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(service));
+    if let Err(e) = watcher.watch(conn.into_owned()).await {
+        debug!("Connection error: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Extension;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn app() -> Router {
+        Router::new().route(
+            "/",
+            get(|Extension(connection): Extension<Connection>| async move {
+                format!("{:?}", connection.remote)
+            }),
+        )
+    }
+
+    async fn request(mut stream: impl AsyncRead + AsyncWrite + Unpin) -> String {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_serve_tcp() {
+        let listener = Listener::bind(ListenerConfig {
+            address: Some("127.0.0.1:0".parse().unwrap()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let address = listener.local_addr().unwrap();
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let server = tokio::spawn(listener.serve(app(), shutdown));
+
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let response = request(stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("Ip(127.0.0.1)"), "{response}");
+
+        shutdown_sender.send(true).unwrap();
+        server.await.unwrap();
+        assert!(tokio::net::TcpStream::connect(address).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_unix() {
+        let path = std::env::temp_dir().join(format!("stylus-test-{}.sock", std::process::id()));
+        // A stale socket is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path));
+        let listener = Listener::bind(ListenerConfig {
+            unix: Some(path.clone()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let server = tokio::spawn(listener.serve(app(), shutdown));
+
+        let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let response = request(stream).await;
+        assert!(response.ends_with("Unix"), "{response}");
+
+        shutdown_sender.send(true).unwrap();
+        server.await.unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_serve_tls() {
+        use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("stylus-test-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tls = TlsConfig {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+        };
+        std::fs::write(&tls.cert, certified.cert.pem()).unwrap();
+        std::fs::write(&tls.key, certified.signing_key.serialize_pem()).unwrap();
+
+        let listener = Listener::bind(ListenerConfig {
+            address: Some("127.0.0.1:0".parse().unwrap()),
+            tls: Some(tls),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let address = listener.local_addr().unwrap();
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let server = tokio::spawn(listener.serve(app(), shutdown));
+
+        let mut roots = RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let client_config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let stream = connector
+            .connect("localhost".try_into().unwrap(), stream)
+            .await
+            .unwrap();
+        let response = request(stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+        shutdown_sender.send(true).unwrap();
+        server.await.unwrap();
+
+        // A missing key is reported when binding
+        let error = Listener::bind(ListenerConfig {
+            address: Some("127.0.0.1:0".parse().unwrap()),
+            tls: Some(TlsConfig {
+                cert: dir.join("cert.pem"),
+                key: dir.join("missing.pem"),
+            }),
+            ..Default::default()
+        })
+        .await
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("missing.pem"), "{error}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate() {
+        let address = Some("[::1]:8000".parse().unwrap());
+        let unix = Some(PathBuf::from("/run/stylus.sock"));
+        let listener = |address, unix| ListenerConfig {
+            address,
+            unix,
+            ..Default::default()
+        };
+        assert!(listener(address, None).validate().is_ok());
+        assert_eq!(listener(None, unix.clone()).validate().is_ok(), cfg!(unix));
+        assert!(listener(address, unix).validate().is_err());
+        assert!(listener(None, None).validate().is_err());
+        assert_eq!(
+            listener(address, None).to_string(),
+            "[::1]:8000".to_string()
+        );
+    }
+}
@@ -5,14 +5,32 @@ use env_logger::Env;
 use include_directory::{include_directory, Dir};
 use serde::Serialize;
 
+mod auth;
+mod badge;
 mod config;
 mod css;
+mod dashboard;
+mod diagram;
+mod display;
+mod events;
+mod export;
 mod expressions;
+mod filter;
+mod history;
+mod hooks;
 mod http;
 mod interpolate;
+mod listener;
+mod metrics;
 mod monitor;
 mod monitors;
+mod mqtt;
+mod notify;
+mod reload;
+mod snapshot;
 mod status;
+mod stream;
+mod view;
 mod worker;
 
 #[macro_use]
@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::config::{MetricsConfig, MonitorDirAxisValue};
+use crate::status::{MonitorStatus, Status, StatusState};
+
+/// The labels that stylus uses itself, which axes are renamed to avoid.
+const RESERVED_LABELS: &[&str] = &["id", "monitor", "status", "key"];
+
+const STATES: [StatusState; 6] = [
+    StatusState::Blank,
+    StatusState::Green,
+    StatusState::Yellow,
+    StatusState::Red,
+    StatusState::Blue,
+    StatusState::Orange,
+];
+
+type Labels = Vec<(String, String)>;
+
+/// The samples for each metric family, which must be written together.
+#[derive(Default)]
+struct Metrics {
+    status: Vec<String>,
+    exit_code: Vec<String>,
+    run_duration: Vec<String>,
+    last_run: Vec<String>,
+    metadata: Vec<String>,
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &Labels, extra: Option<(&str, &str)>) -> String {
+    let labels: Vec<_> = labels
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .chain(extra)
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Sample values, with the spellings of the special values that OpenMetrics requires.
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// The labels for the axes of a group child. Axis names are made into valid label names that do
+/// not clash with our own labels, or with each other.
+pub fn axis_labels(axes: &BTreeMap<String, MonitorDirAxisValue>) -> Labels {
+    let mut labels: Labels = vec![];
+    for (name, value) in axes {
+        let mut name: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if RESERVED_LABELS.contains(&name.as_str())
+            || name.is_empty()
+            || name.starts_with(|c: char| c.is_ascii_digit())
+        {
+            name = format!("axis_{}", name);
+        }
+        // Different axis names may still end up the same, such as `a-b` and `a_b`
+        let mut unique = name.clone();
+        let mut suffix = 2;
+        while labels.iter().any(|(label, _)| *label == unique) {
+            unique = format!("{}_{}", name, suffix);
+            suffix += 1;
+        }
+        let value = match value {
+            MonitorDirAxisValue::String(s) => s.clone(),
+            MonitorDirAxisValue::Number(n) => n.to_string(),
+        };
+        labels.push((unique, value));
+    }
+    labels
+}
+
+impl Metrics {
+    fn add(&mut self, config: &MetricsConfig, labels: &Labels, status: &MonitorStatus) {
+        let current = status.status.unwrap_or(StatusState::Blank);
+        for state in STATES {
+            let name = state.to_string().to_lowercase();
+            self.status.push(format!(
+                "stylus_status{} {}",
+                format_labels(labels, Some(("status", &name))),
+                (state == current) as u8
+            ));
+        }
+        let labels_text = format_labels(labels, None);
+        if status.status.is_some() {
+            self.exit_code
+                .push(format!("stylus_exit_code{} {}", labels_text, status.code));
+        }
+        if let Some(duration_ms) = status.duration_ms {
+            self.run_duration.push(format!(
+                "stylus_run_duration_seconds{} {}",
+                labels_text,
+                format_value(duration_ms as f64 / 1000.0)
+            ));
+        }
+        if let Some(last_run) = status.last_run {
+            self.last_run.push(format!(
+                "stylus_last_run_timestamp_seconds{} {}",
+                labels_text,
+                format_value(last_run.timestamp_millis() as f64 / 1000.0)
+            ));
+        }
+        for (key, value) in &status.metadata {
+            if !config.is_exported(key) {
+                continue;
+            }
+            // Only numeric metadata can be a sample
+            let Ok(value) = value.trim().parse::<f64>() else {
+                continue;
+            };
+            self.metadata.push(format!(
+                "stylus_metadata{} {}",
+                format_labels(labels, Some(("key", key))),
+                format_value(value)
+            ));
+        }
+    }
+}
+
+/// Generate the metrics for every monitor and group child in the OpenMetrics text format.
+pub fn generate_metrics(config: &MetricsConfig, status: &Status) -> String {
+    let mut metrics = Metrics::default();
+    for monitor in &status.monitors {
+        let monitor = monitor.read();
+        let labels = vec![
+            ("id".to_string(), monitor.id.clone()),
+            ("monitor".to_string(), monitor.id.clone()),
+        ];
+        metrics.add(config, &labels, &monitor.status);
+        for (id, child) in &monitor.children {
+            let mut labels = vec![
+                ("id".to_string(), id.clone()),
+                ("monitor".to_string(), monitor.id.clone()),
+            ];
+            labels.extend(axis_labels(&child.axes));
+            metrics.add(config, &labels, &child.status);
+        }
+    }
+
+    let mut output = String::new();
+    for (name, unit, help, samples) in [
+        (
+            "stylus_status",
+            None,
+            "The status of the monitor, which is 1 for the current status and 0 otherwise.",
+            metrics.status,
+        ),
+        (
+            "stylus_exit_code",
+            None,
+            "The exit code of the last run.",
+            metrics.exit_code,
+        ),
+        (
+            "stylus_run_duration_seconds",
+            Some("seconds"),
+            "How long the last run took.",
+            metrics.run_duration,
+        ),
+        (
+            "stylus_last_run_timestamp_seconds",
+            Some("seconds"),
+            "When the last run finished.",
+            metrics.last_run,
+        ),
+        (
+            "stylus_metadata",
+            None,
+            "The numeric metadata values set by the last run.",
+            metrics.metadata,
+        ),
+    ] {
+        let _ = writeln!(output, "# TYPE {} gauge", name);
+        if let Some(unit) = unit {
+            let _ = writeln!(output, "# UNIT {} {}", name, unit);
+        }
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        for sample in samples {
+            output += &sample;
+            output += "\n";
+        }
+    }
+    output += "# EOF\n";
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::{MonitorChildStatus, MonitorState};
+
+    #[test]
+    fn test_generate_metrics() {
+        let mut state = MonitorState::new_test("switch");
+        state.status.status = Some(StatusState::Red);
+        state.status.code = 1;
+        state.status.duration_ms = Some(1500);
+        state.status.metadata = BTreeMap::from([
+            ("rtt_avg".to_string(), "1234".to_string()),
+            ("model".to_string(), "GS108".to_string()),
+            ("secret".to_string(), "42".to_string()),
+            ("loss".to_string(), "NaN".to_string()),
+            ("rtt_max".to_string(), "inf".to_string()),
+        ]);
+        let mut child = MonitorChildStatus::default();
+        child
+            .axes
+            .insert("index".into(), MonitorDirAxisValue::Number(1));
+        child
+            .axes
+            .insert("status".into(), MonitorDirAxisValue::String("a\"b".into()));
+        child.axes.insert(
+            "port-name".into(),
+            MonitorDirAxisValue::String("uplink".into()),
+        );
+        child.axes.insert(
+            "port_name".into(),
+            MonitorDirAxisValue::String("wan".into()),
+        );
+        child.status.status = Some(StatusState::Green);
+        state.children.insert("switch-1".into(), child);
+        let status = Status {
+            monitors: vec![keepcalm::SharedMut::new(state)],
+        };
+
+        let config = MetricsConfig {
+            metadata_exclude: vec!["secret".into()],
+            ..Default::default()
+        };
+        let metrics = generate_metrics(&config, &status);
+        let lines: Vec<_> = metrics.lines().collect();
+        for expected in [
+            r#"stylus_status{id="switch",monitor="switch",status="red"} 1"#,
+            r#"stylus_status{id="switch",monitor="switch",status="green"} 0"#,
+            r#"stylus_status{id="switch-1",monitor="switch",index="1",port_name="uplink",port_name_2="wan",axis_status="a\"b",status="green"} 1"#,
+            r#"stylus_exit_code{id="switch",monitor="switch"} 1"#,
+            r#"stylus_run_duration_seconds{id="switch",monitor="switch"} 1.5"#,
+            r#"stylus_metadata{id="switch",monitor="switch",key="rtt_avg"} 1234"#,
+            r#"stylus_metadata{id="switch",monitor="switch",key="loss"} NaN"#,
+            r#"stylus_metadata{id="switch",monitor="switch",key="rtt_max"} +Inf"#,
+            "# UNIT stylus_run_duration_seconds seconds",
+            "# EOF",
+        ] {
+            assert!(lines.contains(&expected), "{expected} in {metrics}");
+        }
+        assert!(!metrics.contains("model"));
+        assert!(!metrics.contains("secret"));
+        assert_eq!(lines.last(), Some(&"# EOF"));
+    }
+}
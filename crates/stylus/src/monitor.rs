@@ -1,24 +1,85 @@
+use std::collections::HashMap;
 use std::error::Error;
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 use keepcalm::SharedMut;
+use serde::Serialize;
+use tokio::sync::broadcast;
 
 use crate::config::*;
+use crate::events::EventLog;
+use crate::history::HistoryStore;
+use crate::snapshot;
 use crate::status::*;
-use crate::worker::{monitor_thread, ShuttingDown};
+use crate::stream::UpdateLog;
+use crate::worker::{monitor_thread, ShuttingDown, WorkerControl, WorkerMessage};
 
 #[derive(Debug)]
 struct MonitorThread {
     /// This is solely used to detect when [`MonitorThread`] is dropped.
     #[allow(unused)]
     drop_detect: SharedMut<()>,
+    /// The configuration this thread was started with, used to detect changes on reload.
+    config: MonitorDirConfig,
     state: SharedMut<MonitorState>,
+    control: Arc<WorkerControl>,
+}
+
+/// Shared services that every monitor thread reports to.
+#[derive(Clone, Debug)]
+struct MonitorContext {
+    css_config: SharedMut<CssMetadataConfig>,
+    history: Option<Arc<HistoryStore>>,
+    events: Arc<EventLog>,
+    /// The state of a monitor after each completed run.
+    runs: broadcast::Sender<MonitorState>,
+    updates: Arc<UpdateLog>,
+}
+
+impl MonitorContext {
+    /// Process a message from the monitor thread, updating the state and notifying the shared
+    /// services once a run completes.
+    fn process_message(
+        &self,
+        state: &mut MonitorState,
+        id: &str,
+        msg: WorkerMessage,
+    ) -> Result<(), Box<dyn Error>> {
+        let is_final = msg.is_final();
+        state.process_message(id, msg, &self.css_config.read(), &mut |_| {})?;
+        if is_final {
+            self.events.push(std::mem::take(&mut state.events));
+            if let Some(history) = &self.history {
+                if let Err(e) = history.record(state) {
+                    error!("[{}] Failed to record history: {}", id, e);
+                }
+            }
+            // Avoid the clone if nobody is listening
+            if self.runs.receiver_count() > 0 {
+                let _ = self.runs.send(state.clone());
+            }
+            self.updates.push(&state.id);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub struct Monitor {
-    pub config: Config,
-    monitors: Vec<MonitorThread>,
+    config: SharedMut<Arc<Config>>,
+    context: MonitorContext,
+    monitors: SharedMut<Vec<MonitorThread>>,
+}
+
+/// The changes applied by [`Monitor::reload`].
+#[derive(Debug, Default, Serialize)]
+pub struct ReloadSummary {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub restarted: Vec<String>,
+    pub unchanged: Vec<String>,
 }
 
 pub trait MonitorMessageProcessor: Send + Sync + std::fmt::Debug + 'static {
@@ -40,19 +101,35 @@ impl MonitorThread {
     fn create(
         monitor: MonitorDirConfig,
         mut state: MonitorState,
-        css_config: CssMetadataConfig,
+        context: MonitorContext,
     ) -> Result<Self, Box<dyn Error>> {
-        state.status.initialize(&css_config);
-        for state in &mut state.children {
-            state.1.status.initialize(&css_config);
+        {
+            // Restored states are left as-is until the monitor runs again
+            let css_config = context.css_config.read();
+            if state.status.is_uninitialized() {
+                state.status.initialize(&css_config);
+            }
+            for state in &mut state.children {
+                if state.1.status.is_uninitialized() {
+                    state.1.status.initialize(&css_config);
+                }
+            }
+        }
+        if let Some(history) = &context.history {
+            history.update_uptime(&mut state);
         }
+        let paused = state.paused;
         let state = SharedMut::new(state);
 
         let monitor_state = state.clone();
         let drop_detect = SharedMut::new(());
         let mut drop_detect_clone = Some(drop_detect.clone());
+        let control = Arc::new(WorkerControl::default());
+        control.set_paused(paused);
+        let thread_control = control.clone();
+        let thread_monitor = monitor.clone();
         let _thread = thread::spawn(move || {
-            monitor_thread(&monitor, move |id, m| {
+            monitor_thread(&thread_monitor, &thread_control, move |id, m| {
                 drop_detect_clone = if let Some(drop_detect) = drop_detect_clone.take() {
                     drop_detect.try_unwrap().err()
                 } else {
@@ -62,37 +139,315 @@ impl MonitorThread {
                 if drop_detect_clone.is_none() {
                     return Err(ShuttingDown::default().into());
                 }
-                monitor_state
-                    .write()
-                    .process_message(id, m, &css_config, &mut |_| {})
+                context.process_message(&mut monitor_state.write(), id, m)
             });
         });
 
-        let thread = MonitorThread { state, drop_detect };
+        let thread = MonitorThread {
+            state,
+            config: monitor,
+            drop_detect,
+            control,
+        };
 
         Ok(thread)
     }
+
+    /// Has the configuration for this thread changed? Secrets such as passwords aren't
+    /// serialized, so the configurations are compared directly.
+    fn is_changed(&self, config: &MonitorDirConfig) -> bool {
+        self.config != *config
+    }
+}
+
+impl Drop for MonitorThread {
+    fn drop(&mut self) {
+        // Wake up the thread if it is sleeping so it can exit
+        self.control.shutdown();
+    }
 }
 
 impl Monitor {
     pub fn new(config: &Config) -> Result<Monitor, Box<dyn Error>> {
         let config = config.clone();
+        let history = match &config.monitor.history.dir {
+            Some(dir) => match HistoryStore::open(dir, &config.monitor.history) {
+                Ok(store) => Some(Arc::new(store)),
+                Err(e) => {
+                    warn!("Unable to open status history in {:?}: {}", dir, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let context = MonitorContext {
+            css_config: SharedMut::new(config.css.metadata.clone()),
+            history,
+            events: Arc::new(EventLog::new(&config.monitor.events)),
+            runs: broadcast::channel(1024).0,
+            updates: Arc::new(UpdateLog::new()),
+        };
+        let snapshot = match &config.monitor.state.file {
+            Some(path) => snapshot::load(path).unwrap_or_else(|e| {
+                warn!("Unable to restore monitor state from {:?}: {}", path, e);
+                None
+            }),
+            None => None,
+        };
         let mut monitors = Vec::new();
         for monitor_config in &parse_monitor_configs(&config.monitor.dir)? {
+            let mut state: MonitorState = monitor_config.into();
+            if let Some(snapshot) = &snapshot {
+                snapshot.restore(&mut state, &config.css.metadata);
+            }
             monitors.push(MonitorThread::create(
                 monitor_config.clone(),
-                monitor_config.into(),
-                config.css.metadata.clone(),
+                state,
+                context.clone(),
             )?);
         }
-        Ok(Monitor { config, monitors })
+        Ok(Monitor {
+            config: SharedMut::new(Arc::new(config)),
+            context,
+            monitors: SharedMut::new(monitors),
+        })
+    }
+
+    /// The current configuration.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
     }
 
     pub fn status(&self) -> Status {
         Status {
-            monitors: self.monitors.iter().map(|m| m.state.clone()).collect(),
+            monitors: self
+                .monitors
+                .read()
+                .iter()
+                .map(|m| m.state.clone())
+                .collect(),
         }
     }
+
+    /// Re-read `config.yaml`, `config.d` and the monitor directory, starting added monitors,
+    /// stopping removed ones and restarting changed ones. Unchanged monitors keep their state. If
+    /// any part of the new configuration fails to parse, the running configuration is left as-is.
+    pub fn reload(&self) -> Result<ReloadSummary, Box<dyn Error>> {
+        let old_config = self.config();
+        let config_dir = old_config
+            .config_file
+            .parent()
+            .ok_or("Failed to get configuration directory")?;
+        let mut config = parse_config(config_dir)?;
+        let monitor_configs = parse_monitor_configs(&config.monitor.dir)?;
+
+        // The listeners cannot be changed while running. These are compared directly, as secrets
+        // such as passwords and tokens are not serialized.
+        if config.server != old_config.server {
+            warn!("Server configuration changes require a restart and will be ignored");
+        }
+        config.server = old_config.server.clone();
+        if config.mqtt != old_config.mqtt {
+            warn!("MQTT configuration changes require a restart and will be ignored");
+        }
+        config.mqtt = old_config.mqtt.clone();
+        if config.exporters != old_config.exporters {
+            warn!("Exporter configuration changes require a restart and will be ignored");
+        }
+        config.exporters = old_config.exporters.clone();
+        // Views are routed at startup, but their filters can change
+        let paths = |config: &Config| {
+            config
+                .views
+                .iter()
+                .map(|(name, view)| view.path(name))
+                .collect::<Vec<_>>()
+        };
+        if paths(&config) != paths(&old_config) {
+            warn!("Adding, removing or moving views requires a restart and will be ignored");
+            config.views = old_config.views.clone();
+        }
+
+        let mut summary = ReloadSummary::default();
+        let mut monitors = self.monitors.write();
+
+        // Start the new and changed monitors before touching the running ones, so that a failure
+        // leaves everything as it was
+        let mut started = HashMap::new();
+        for monitor_config in &monitor_configs {
+            let previous = monitors
+                .iter()
+                .find(|monitor| monitor.config.id == monitor_config.id);
+            if previous.is_none_or(|monitor| monitor.is_changed(monitor_config)) {
+                started.insert(
+                    monitor_config.id.clone(),
+                    MonitorThread::create(
+                        monitor_config.clone(),
+                        monitor_config.into(),
+                        self.context.clone(),
+                    )?,
+                );
+            }
+        }
+
+        let mut existing: HashMap<String, MonitorThread> = std::mem::take(&mut *monitors)
+            .into_iter()
+            .map(|monitor| (monitor.config.id.clone(), monitor))
+            .collect();
+
+        *self.context.css_config.write() = config.css.metadata.clone();
+        for monitor_config in monitor_configs {
+            let id = monitor_config.id;
+            let previous = existing.remove(&id);
+            match (started.remove(&id), previous) {
+                (Some(monitor), previous) => {
+                    monitors.push(monitor);
+                    if previous.is_some() {
+                        summary.restarted.push(id);
+                    } else {
+                        summary.added.push(id);
+                    }
+                }
+                (None, Some(monitor)) => {
+                    // Force the CSS to regenerate in case the rules changed
+                    monitor.state.write().css = None;
+                    monitors.push(monitor);
+                    summary.unchanged.push(id);
+                }
+                // Every monitor that wasn't running was started above
+                (None, None) => {}
+            }
+        }
+        summary.removed = existing.into_keys().collect();
+        summary.removed.sort();
+
+        *self.config.write() = Arc::new(config);
+        drop(monitors);
+        // Every monitor is updated as the css rules may have changed
+        for id in summary
+            .added
+            .iter()
+            .chain(&summary.removed)
+            .chain(&summary.restarted)
+            .chain(&summary.unchanged)
+        {
+            self.context.updates.push(id);
+        }
+        Ok(summary)
+    }
+
+    /// The status history store, if enabled.
+    pub fn history(&self) -> Option<&HistoryStore> {
+        self.context.history.as_deref()
+    }
+
+    /// The hooks configured for a top-level monitor.
+    pub fn hooks(&self, id: &str) -> Option<HooksConfig> {
+        self.monitors
+            .read()
+            .iter()
+            .find(|monitor| monitor.config.id == id)
+            .map(|monitor| monitor.config.hooks.clone())
+    }
+
+    /// The log of recent status changes.
+    pub fn events(&self) -> &EventLog {
+        &self.context.events
+    }
+
+    /// Receive the state of every monitor after each run that completes after this call.
+    pub fn subscribe_runs(&self) -> broadcast::Receiver<MonitorState> {
+        self.context.runs.subscribe()
+    }
+
+    /// The log of monitor updates, for streaming.
+    pub fn updates(&self) -> &UpdateLog {
+        &self.context.updates
+    }
+
+    /// Locate the state and scheduling control for a top-level monitor by id.
+    pub fn control(&self, id: &str) -> Option<(SharedMut<MonitorState>, Arc<WorkerControl>)> {
+        self.monitors
+            .read()
+            .iter()
+            .find(|monitor| monitor.config.id == id)
+            .map(|monitor| (monitor.state.clone(), monitor.control.clone()))
+    }
+
+    /// Pause or resume scheduling for a monitor, returning its state if the monitor exists.
+    pub fn set_paused(&self, id: &str, paused: bool) -> Option<SharedMut<MonitorState>> {
+        let (state, control) = self.control(id)?;
+        control.set_paused(paused);
+        let mut locked = state.write();
+        locked.paused = paused;
+        locked.css = None;
+        drop(locked);
+        self.context.updates.push(id);
+        Some(state)
+    }
+
+    /// Acknowledge the current status of a monitor or group child, returning `None` if there is
+    /// no such monitor.
+    pub fn acknowledge(
+        &self,
+        id: &str,
+        comment: Option<String>,
+        expires: Option<Duration>,
+    ) -> Option<Result<MonitorAcknowledgement, Box<dyn Error>>> {
+        let (state, child) = self.find(id)?;
+        let mut locked = state.write();
+        let res = locked.acknowledge(child.as_deref(), comment, expires);
+        if res.is_ok() {
+            self.context.updates.push(&locked.id);
+        }
+        Some(res)
+    }
+
+    /// Drop acknowledgements that have expired since the monitors last ran.
+    pub fn expire_acknowledgements(&self) {
+        for monitor in self.monitors.read().iter() {
+            // Most monitors have nothing to expire, so avoid taking the write lock for them
+            let expiring = {
+                let state = monitor.state.read();
+                std::iter::once(&state.status)
+                    .chain(state.children.values().map(|child| &child.status))
+                    .any(|status| {
+                        status
+                            .acknowledged
+                            .as_ref()
+                            .is_some_and(|ack| ack.expires.is_some())
+                    })
+            };
+            if expiring && monitor.state.write().expire_acknowledgements() {
+                self.context.updates.push(&monitor.config.id);
+            }
+        }
+    }
+
+    /// Locate the state for a monitor or group child by id. If the id refers to a group child,
+    /// the child's id is returned alongside the parent monitor's state.
+    pub fn find(&self, id: &str) -> Option<(SharedMut<MonitorState>, Option<String>)> {
+        for monitor in self.monitors.read().iter() {
+            let state = monitor.state.read();
+            if state.id == id {
+                return Some((monitor.state.clone(), None));
+            }
+            if state.children.contains_key(id) {
+                return Some((monitor.state.clone(), Some(id.to_owned())));
+            }
+        }
+        None
+    }
+}
+
+/// Expire acknowledgements as they run out, rather than when their monitor next runs, so that
+/// the dashboard stops showing them.
+pub async fn expire_acknowledgements(monitor: Arc<Monitor>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        monitor.expire_acknowledgements();
+    }
 }
 
 #[cfg(test)]
@@ -186,6 +541,47 @@ mod test {
         Ok(())
     }
 
+    /// Tests that acknowledgements persist while the status is unchanged and clear on recovery.
+    #[test]
+    fn acknowledge_test() -> Result<(), Box<dyn Error>> {
+        use crate::worker::WorkerMessage;
+        let metadata = CssMetadataConfig::default();
+        let mut state = run_test("metadata_fail")?;
+        state.acknowledge(None, Some("on it".into()), None)?;
+        assert!(state.status.is_acknowledged());
+
+        state.process_message(
+            "metadata_fail",
+            WorkerMessage::Termination(1),
+            &metadata,
+            &mut |_| {},
+        )?;
+        assert!(state.status.is_acknowledged());
+
+        state.process_message(
+            "metadata_fail",
+            WorkerMessage::Termination(0),
+            &metadata,
+            &mut |_| {},
+        )?;
+        assert!(!state.status.is_acknowledged());
+        assert!(state.status.acknowledged.is_none());
+
+        // Green monitors cannot be acknowledged
+        assert!(state.acknowledge(None, None, None).is_err());
+
+        // Expired acknowledgements are dropped along with the CSS that shows them
+        let mut state = run_test("metadata_fail")?;
+        state.acknowledge(None, None, Some(Duration::from_secs(60)))?;
+        assert!(!state.expire_acknowledgements());
+        state.status.acknowledged.as_mut().unwrap().expires = Some(chrono::Utc::now());
+        state.css = Some("--monitor-acknowledged: 1;".into());
+        assert!(state.expire_acknowledgements());
+        assert!(state.status.acknowledged.is_none());
+        assert_eq!(state.css, None);
+        Ok(())
+    }
+
     /// Tests whether the incomplete members of a group are correctly blanked out.
     #[test]
     fn group_incomplete_test() -> Result<(), Box<dyn Error>> {
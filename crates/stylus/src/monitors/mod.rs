@@ -1,2 +1,4 @@
+pub mod mqtt;
 pub mod ping;
+pub mod prometheus;
 pub mod snmp;
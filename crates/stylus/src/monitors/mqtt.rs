@@ -0,0 +1,565 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{MonitorDirAxisValue, MonitorDirTestConfig},
+    expressions::{self, Value},
+    interpolate::interpolate_id,
+    monitor::{MonitorMessageProcessor, MonitorMessageProcessorInstance},
+};
+
+/// How long to wait before reconnecting to the broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct MqttMonitorConfig {
+    broker: MqttMonitorBrokerConfig,
+    /// The topic to subscribe to, which may contain `+` and `#` wildcards.
+    pub topic: String,
+    /// The ID pattern for the children created for each topic matching a wildcard.
+    #[serde(default = "default_id")]
+    pub id: String,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    /// A topic turns red when no message has been received for this long.
+    #[serde(with = "humantime_serde")]
+    pub stale_after: Duration,
+    #[serde(default = "default_red")]
+    pub red: String,
+    #[serde(default = "default_green")]
+    pub green: String,
+    #[serde(default = "default_blue")]
+    pub blue: String,
+    #[serde(default = "default_orange")]
+    pub orange: String,
+    #[serde(default = "default_yellow")]
+    pub yellow: String,
+    #[serde(skip_deserializing)]
+    pub test: Option<MonitorDirTestConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct MqttMonitorBrokerConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+}
+
+fn default_id() -> String {
+    "{{ wildcard }}".to_string()
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_red() -> String {
+    "false".to_string()
+}
+
+fn default_green() -> String {
+    "true".to_string()
+}
+
+fn default_blue() -> String {
+    "false".to_string()
+}
+
+fn default_orange() -> String {
+    "false".to_string()
+}
+
+fn default_yellow() -> String {
+    "false".to_string()
+}
+
+impl MqttMonitorConfig {
+    pub fn test(&self) -> MonitorDirTestConfig {
+        // Messages are collected in the background, so the test itself only evaluates them
+        MonitorDirTestConfig {
+            interval: self.interval,
+            timeout: Duration::from_secs(10),
+            command: PathBuf::from("/usr/bin/env"),
+            args: vec!["true".to_string()],
+            processor: Some(Arc::new(MqttMonitorMessageProcessor {
+                broker: self.broker.clone(),
+                topic: self.topic.clone(),
+                id: self.id.clone(),
+                stale_after: self.stale_after,
+                red: self.red.clone(),
+                green: self.green.clone(),
+                blue: self.blue.clone(),
+                orange: self.orange.clone(),
+                yellow: self.yellow.clone(),
+                subscription: OnceLock::new(),
+            })),
+        }
+    }
+}
+
+/// The most recent message on each topic matching a subscription. The connection is closed when
+/// this is dropped.
+struct MqttSubscription {
+    client: Client,
+    started: Instant,
+    messages: Mutex<BTreeMap<String, (Instant, String)>>,
+}
+
+impl std::fmt::Debug for MqttSubscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttSubscription")
+            .field("started", &self.started)
+            .field("messages", &self.messages)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MqttSubscription {
+    fn start(broker: &MqttMonitorBrokerConfig, topic: &str) -> Arc<Self> {
+        static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+        let client_id = format!(
+            "stylus-{}-{}",
+            std::process::id(),
+            CONNECTIONS.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut options = MqttOptions::new(client_id, &broker.host, broker.port);
+        if let Some(username) = &broker.username {
+            options.set_credentials(username, broker.password.clone().unwrap_or_default());
+        }
+        let (client, mut connection) = Client::new(options, 10);
+        let subscription = Arc::new(MqttSubscription {
+            client,
+            started: Instant::now(),
+            messages: Default::default(),
+        });
+
+        let weak = Arc::downgrade(&subscription);
+        let topic = topic.to_owned();
+        let broker = format!("{}:{}", broker.host, broker.port);
+        std::thread::spawn(move || {
+            for event in connection.iter() {
+                let Some(subscription) = weak.upgrade() else {
+                    break;
+                };
+                match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        if let Err(e) = subscription.client.try_subscribe(&topic, QoS::AtMostOnce) {
+                            log::warn!("Failed to subscribe to {}: {}", topic, e);
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(message))) => {
+                        let mut messages = subscription.messages.lock().unwrap();
+                        if message.payload.is_empty() {
+                            // A retained message was cleared
+                            messages.remove(&message.topic);
+                        } else {
+                            let payload = String::from_utf8_lossy(&message.payload).into_owned();
+                            messages.insert(message.topic, (Instant::now(), payload));
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::warn!("MQTT connection to {} failed: {}", broker, e);
+                        drop(subscription);
+                        std::thread::sleep(RECONNECT_DELAY);
+                    }
+                }
+            }
+        });
+        subscription
+    }
+}
+
+#[derive(Debug)]
+pub struct MqttMonitorMessageProcessor {
+    broker: MqttMonitorBrokerConfig,
+    topic: String,
+    id: String,
+    stale_after: Duration,
+    red: String,
+    green: String,
+    blue: String,
+    orange: String,
+    yellow: String,
+    /// Started by the first run, so that configurations that are parsed but never run do not
+    /// connect.
+    subscription: OnceLock<Arc<MqttSubscription>>,
+}
+
+#[derive(Debug)]
+pub struct MqttMonitorMessageProcessorInstance {
+    topic: String,
+    id: String,
+    stale_after: Duration,
+    red: String,
+    green: String,
+    blue: String,
+    orange: String,
+    yellow: String,
+    subscription: Arc<MqttSubscription>,
+}
+
+impl MonitorMessageProcessor for MqttMonitorMessageProcessor {
+    fn new(&self) -> Box<dyn MonitorMessageProcessorInstance> {
+        Box::new(MqttMonitorMessageProcessorInstance {
+            topic: self.topic.clone(),
+            id: self.id.clone(),
+            stale_after: self.stale_after,
+            red: self.red.clone(),
+            green: self.green.clone(),
+            blue: self.blue.clone(),
+            orange: self.orange.clone(),
+            yellow: self.yellow.clone(),
+            subscription: self
+                .subscription
+                .get_or_init(|| MqttSubscription::start(&self.broker, &self.topic))
+                .clone(),
+        })
+    }
+}
+
+impl MonitorMessageProcessorInstance for MqttMonitorMessageProcessorInstance {
+    fn process_message(&self, _input: &str) -> Vec<String> {
+        vec![]
+    }
+
+    fn finalize(&self) -> Vec<String> {
+        let messages = self.subscription.messages.lock().unwrap().clone();
+        self.evaluate(&messages, self.subscription.started, Instant::now())
+    }
+}
+
+impl MqttMonitorMessageProcessorInstance {
+    fn evaluate(
+        &self,
+        messages: &BTreeMap<String, (Instant, String)>,
+        started: Instant,
+        now: Instant,
+    ) -> Vec<String> {
+        let mut result = vec![];
+        if messages.is_empty() {
+            let waited = now.duration_since(started);
+            if waited > self.stale_after {
+                result.push("status.status=\"red\"".to_string());
+                result.push(format!(
+                    "status.description={:?}",
+                    format!("No messages on {} for {}s", self.topic, waited.as_secs())
+                ));
+            } else {
+                result.push("status.status=\"blank\"".to_string());
+                result.push(format!(
+                    "status.description={:?}",
+                    format!("Waiting for messages on {}", self.topic)
+                ));
+            }
+            return result;
+        }
+
+        let is_wildcard = self.topic.contains(['+', '#']);
+        for (topic, (received, payload)) in messages {
+            let prefix = if is_wildcard {
+                let mut values = BTreeMap::new();
+                values.insert(
+                    "wildcard".into(),
+                    MonitorDirAxisValue::String(wildcard(&self.topic, topic)),
+                );
+                values.insert("topic".into(), MonitorDirAxisValue::String(topic.clone()));
+                let Ok(id) = interpolate_id(&values, &self.id) else {
+                    log::warn!("Failed to interpolate id for topic {}", topic);
+                    continue;
+                };
+                format!("group.{}.status", id)
+            } else {
+                "status".to_string()
+            };
+
+            let age = now.duration_since(*received);
+            let mut metadata = parse_payload(payload);
+            metadata.insert("age".to_string(), Value::Int(age.as_secs() as i64));
+            for (key, value) in &metadata {
+                result.push(format!(
+                    "{}.metadata.{}={}",
+                    prefix,
+                    key,
+                    serde_json::Value::from(value.as_str())
+                ));
+            }
+
+            // A sensor that stops reporting is failing, whatever it last said
+            let status = if age > self.stale_after {
+                result.push(format!(
+                    "{}.description={:?}",
+                    prefix,
+                    format!("No message for {}s", age.as_secs())
+                ));
+                "red"
+            } else {
+                metadata.insert("topic".to_string(), Value::Str(topic.clone().into()));
+                metadata
+                    .entry("value".to_string())
+                    .or_insert_with(|| Value::Str(payload.clone().into()));
+                result.push(format!(
+                    "{}.description={:?}",
+                    prefix,
+                    format!("Last message {}s ago", age.as_secs())
+                ));
+                if calculate_bool(&self.red, &metadata) {
+                    "red"
+                } else if calculate_bool(&self.orange, &metadata) {
+                    "orange"
+                } else if calculate_bool(&self.yellow, &metadata) {
+                    "yellow"
+                } else if calculate_bool(&self.blue, &metadata) {
+                    "blue"
+                } else if calculate_bool(&self.green, &metadata) {
+                    "green"
+                } else {
+                    "blank"
+                }
+            };
+            result.push(format!("{}.status={:?}", prefix, status));
+        }
+        result
+    }
+}
+
+/// The parts of a topic that matched the wildcards in a filter, joined with `-`.
+fn wildcard(filter: &str, topic: &str) -> String {
+    let mut parts = vec![];
+    let mut topic_parts = topic.split('/');
+    for filter_part in filter.split('/') {
+        match filter_part {
+            "+" => parts.extend(topic_parts.next()),
+            "#" => parts.extend(topic_parts.by_ref()),
+            _ => drop(topic_parts.next()),
+        }
+    }
+    parts.join("-")
+}
+
+/// Parse a payload into expression variables. The fields of JSON objects are available by name
+/// (with nested fields joined by `_`), and any other payload is available as `value`. The raw
+/// payload of a JSON object is added as `value` when evaluating conditions.
+fn parse_payload(payload: &str) -> BTreeMap<String, Value> {
+    let mut values = BTreeMap::new();
+    match serde_json::from_str::<serde_json::Value>(payload) {
+        Ok(json @ serde_json::Value::Object(_)) => flatten("", &json, &mut values),
+        Ok(json @ (serde_json::Value::Number(_) | serde_json::Value::Bool(_))) => {
+            flatten("value", &json, &mut values)
+        }
+        Ok(serde_json::Value::String(s)) => {
+            values.insert("value".to_string(), Value::Str(s.into()));
+        }
+        _ => {
+            let payload = payload.trim();
+            let value = match payload.parse::<i64>() {
+                Ok(i) => Value::Int(i),
+                Err(_) => Value::Str(payload.to_string().into()),
+            };
+            values.insert("value".to_string(), value);
+        }
+    }
+    values
+}
+
+fn flatten(prefix: &str, json: &serde_json::Value, values: &mut BTreeMap<String, Value>) {
+    let value = match json {
+        serde_json::Value::Object(object) => {
+            for (key, json) in object {
+                // Keys must be valid identifiers in expressions
+                let key: String = key
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect();
+                let key = if prefix.is_empty() {
+                    key
+                } else {
+                    format!("{}_{}", prefix, key)
+                };
+                flatten(&key, json, values);
+            }
+            return;
+        }
+        serde_json::Value::Bool(b) => Value::Int(*b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::Float(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => Value::Str(s.clone().into()),
+        serde_json::Value::Null | serde_json::Value::Array(_) => return,
+    };
+    values.insert(prefix.to_string(), value);
+}
+
+fn calculate_bool(expression: &str, metadata: &BTreeMap<String, Value>) -> bool {
+    match expressions::expression::calculate(expression, metadata) {
+        Ok(Ok(value)) => value.as_bool(),
+        Err(e) => {
+            log::warn!("Failed to parse expression {:?}: {}", expression, e);
+            false
+        }
+        Ok(Err(e)) => {
+            log::warn!("Failed to evaluate expression {:?}: {:?}", expression, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(topic: &str, port: u16) -> MqttMonitorConfig {
+        serde_yaml_ng::from_str(&format!(
+            r#"
+            broker:
+              host: 127.0.0.1
+              port: {port}
+            topic: {topic}
+            id: sensor-{{{{ wildcard }}}}
+            interval: 1s
+            stale_after: 1h
+            red: value == 'offline'
+            orange: battery < 20
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_payload() {
+        let values = parse_payload(r#"{"battery": 87.6, "update": {"state": "idle"}, "ok": true}"#);
+        assert_eq!(values["battery"], Value::Float(87.6));
+        assert!(calculate_bool("battery > 87.5 and battery < 88", &values));
+        assert_eq!(values["update_state"].as_str(), "idle");
+        assert_eq!(values["ok"].as_int(), 1);
+        assert_eq!(parse_payload("42")["value"].as_int(), 42);
+        assert_eq!(parse_payload("online\n")["value"].as_str(), "online");
+        assert_eq!(parse_payload(r#""online""#)["value"].as_str(), "online");
+        assert_eq!(
+            wildcard(
+                "zigbee2mqtt/+/availability",
+                "zigbee2mqtt/hall/availability"
+            ),
+            "hall"
+        );
+        assert_eq!(wildcard("tele/#", "tele/plug/LWT"), "plug-LWT");
+    }
+
+    #[test]
+    fn test_password_change() {
+        // The password isn't serialized, but a reload must still restart the monitor
+        let mut changed = config("tasmota/plug/LWT", 1883);
+        changed.broker.password = Some("secret".to_string());
+        assert_ne!(changed, config("tasmota/plug/LWT", 1883));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let config = config("tasmota/plug/LWT", 1883);
+        // The connection is never polled, so nothing connects
+        let (client, _connection) = Client::new(MqttOptions::new("test", "localhost", 1883), 10);
+        let instance = MqttMonitorMessageProcessorInstance {
+            topic: config.topic.clone(),
+            id: config.id.clone(),
+            stale_after: config.stale_after,
+            red: config.red.clone(),
+            green: config.green.clone(),
+            blue: config.blue.clone(),
+            orange: config.orange.clone(),
+            yellow: config.yellow.clone(),
+            subscription: Arc::new(MqttSubscription {
+                client,
+                started: Instant::now(),
+                messages: Default::default(),
+            }),
+        };
+        let start = Instant::now();
+        let later = start + Duration::from_secs(2 * 60 * 60);
+
+        // Nothing has been received yet
+        let mut messages = BTreeMap::new();
+        assert!(instance
+            .evaluate(&messages, start, start)
+            .contains(&"status.status=\"blank\"".to_string()));
+        assert!(instance
+            .evaluate(&messages, start, later)
+            .contains(&"status.status=\"red\"".to_string()));
+
+        messages.insert(
+            "tasmota/plug/LWT".to_string(),
+            (start, "Online".to_string()),
+        );
+        let result = instance.evaluate(&messages, start, start);
+        assert!(
+            result.contains(&"status.status=\"green\"".to_string()),
+            "{result:?}"
+        );
+        assert!(result.contains(&"status.metadata.value=\"Online\"".to_string()));
+
+        // The sensor stopped reporting
+        let result = instance.evaluate(&messages, start, later);
+        assert!(
+            result.contains(&"status.status=\"red\"".to_string()),
+            "{result:?}"
+        );
+        assert!(result.contains(&"status.description=\"No message for 7200s\"".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_subscription() {
+        use rumqttc::Publish;
+
+        let (port, _, sender) = crate::mqtt::tests::mqtt_broker().await;
+        let test = config("zigbee2mqtt/+/availability", port).test();
+        let processor = test.processor.unwrap();
+        let _ = processor.new();
+        for (topic, payload) in [
+            (
+                "zigbee2mqtt/kitchen/availability",
+                r#"{"state": "online", "battery": 15}"#,
+            ),
+            ("zigbee2mqtt/hall/availability", "offline"),
+        ] {
+            sender
+                .send(Publish::new(topic, QoS::AtMostOnce, payload))
+                .unwrap();
+        }
+
+        let mut result = vec![];
+        for _ in 0..50 {
+            result = processor.new().finalize();
+            if result.len() > 4 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(
+            result.contains(&"group.sensor-kitchen.status.status=\"orange\"".to_string()),
+            "{result:?}"
+        );
+        assert!(result.contains(&"group.sensor-kitchen.status.metadata.battery=\"15\"".to_string()));
+        assert!(
+            result.contains(&"group.sensor-hall.status.status=\"red\"".to_string()),
+            "{result:?}"
+        );
+    }
+}
@@ -13,7 +13,7 @@ use crate::{
     monitor::{MonitorMessageProcessor, MonitorMessageProcessorInstance},
 };
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub struct PingMonitorConfig {
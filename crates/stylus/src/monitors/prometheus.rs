@@ -0,0 +1,518 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{MonitorDirAxisValue, MonitorDirTestConfig},
+    expressions::{self, ExpressionContext, LabelMatcher, Value},
+    interpolate::interpolate_id,
+    monitor::{MonitorMessageProcessor, MonitorMessageProcessorInstance},
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct PrometheusMonitorConfig {
+    /// The URL of the Prometheus or OpenMetrics text endpoint.
+    pub url: String,
+    /// Extra HTTP headers to send with the scrape.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+    /// Turn each series matching a selector into a child of this monitor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub children: Option<PrometheusChildrenConfig>,
+    #[serde(default = "default_red")]
+    pub red: String,
+    #[serde(default = "default_green")]
+    pub green: String,
+    #[serde(default = "default_blue")]
+    pub blue: String,
+    #[serde(default = "default_orange")]
+    pub orange: String,
+    #[serde(default = "default_yellow")]
+    pub yellow: String,
+    #[serde(skip_deserializing)]
+    pub test: Option<MonitorDirTestConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct PrometheusChildrenConfig {
+    /// The series selector, such as `node_systemd_unit_state{state="active"}`.
+    pub series: String,
+    /// The ID pattern for each child, which may use the series' labels.
+    pub id: String,
+}
+
+fn default_red() -> String {
+    "false".to_string()
+}
+
+fn default_green() -> String {
+    "true".to_string()
+}
+
+fn default_blue() -> String {
+    "false".to_string()
+}
+
+fn default_orange() -> String {
+    "false".to_string()
+}
+
+fn default_yellow() -> String {
+    "false".to_string()
+}
+
+impl PrometheusMonitorConfig {
+    pub fn test(&self) -> MonitorDirTestConfig {
+        let mut args = vec![
+            "curl".to_string(),
+            "-sSfL".to_string(),
+            "--max-time".to_string(),
+            self.timeout.as_secs_f64().to_string(),
+            "-H".to_string(),
+            "Accept: application/openmetrics-text;q=0.5, text/plain;q=0.4".to_string(),
+        ];
+        for (name, value) in &self.headers {
+            args.push("-H".to_string());
+            args.push(format!("{}: {}", name, value));
+        }
+        args.push(self.url.clone());
+
+        MonitorDirTestConfig {
+            interval: self.interval,
+            timeout: self.timeout,
+            command: PathBuf::from("/usr/bin/env"),
+            args,
+            processor: Some(Arc::new(PrometheusMonitorMessageProcessor {
+                children: self.children.clone(),
+                red: self.red.clone(),
+                green: self.green.clone(),
+                blue: self.blue.clone(),
+                orange: self.orange.clone(),
+                yellow: self.yellow.clone(),
+            })),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PrometheusMonitorMessageProcessor {
+    children: Option<PrometheusChildrenConfig>,
+    red: String,
+    green: String,
+    blue: String,
+    orange: String,
+    yellow: String,
+}
+
+#[derive(Debug)]
+pub struct PrometheusMonitorMessageProcessorInstance {
+    config: PrometheusMonitorMessageProcessor,
+    series: Mutex<Vec<Series>>,
+}
+
+impl MonitorMessageProcessor for PrometheusMonitorMessageProcessor {
+    fn new(&self) -> Box<dyn MonitorMessageProcessorInstance> {
+        Box::new(PrometheusMonitorMessageProcessorInstance {
+            config: self.clone(),
+            series: Mutex::new(vec![]),
+        })
+    }
+}
+
+impl MonitorMessageProcessorInstance for PrometheusMonitorMessageProcessorInstance {
+    fn process_message(&self, input: &str) -> Vec<String> {
+        if let Some(series) = parse_sample(input) {
+            self.series.lock().unwrap().push(series);
+        }
+        vec![]
+    }
+
+    fn finalize(&self) -> Vec<String> {
+        self.config.evaluate(&self.series.lock().unwrap())
+    }
+}
+
+impl PrometheusMonitorMessageProcessor {
+    fn evaluate(&self, series: &[Series]) -> Vec<String> {
+        let mut result = vec![];
+        // A failed scrape is already red, but an empty one would otherwise be green
+        if series.is_empty() {
+            result.push("status.status=\"red\"".to_string());
+            result.push("status.description=\"No metrics were scraped\"".to_string());
+            return result;
+        }
+
+        let Some(children) = &self.children else {
+            let context = SeriesContext::new(series, None);
+            result.push(format!(
+                "status.metadata.series=\"{}\"",
+                context.series.len()
+            ));
+            result.push(format!("status.status={:?}", self.status(&context)));
+            return result;
+        };
+
+        let (name, matchers) = match expressions::expression::selector(&children.series) {
+            Ok(selector) => selector,
+            Err(e) => {
+                log::warn!(
+                    "Failed to parse series selector {:?}: {}",
+                    children.series,
+                    e
+                );
+                result.push("status.status=\"red\"".to_string());
+                result.push(format!(
+                    "status.description={:?}",
+                    format!("Invalid series selector {:?}", children.series)
+                ));
+                return result;
+            }
+        };
+        for child in series.iter().filter(|s| s.matches(&name, &matchers)) {
+            let values = child
+                .labels
+                .iter()
+                .map(|(k, v)| (k.clone(), MonitorDirAxisValue::String(v.clone())))
+                .collect();
+            let Ok(id) = interpolate_id(&values, &children.id) else {
+                log::warn!("Failed to interpolate id for series {:?}", child.labels);
+                continue;
+            };
+            // Labels are often hostnames or addresses, but a `.` would split the update's path
+            let id: String = id
+                .chars()
+                .map(|c| {
+                    if c.is_alphanumeric() || matches!(c, '-' | '_' | '/') {
+                        c
+                    } else {
+                        '-'
+                    }
+                })
+                .collect();
+            let prefix = format!("group.{}.status", id);
+            for (key, value) in &child.labels {
+                result.push(format!(
+                    "{}.metadata.{}={}",
+                    prefix,
+                    key,
+                    serde_json::Value::from(value.as_str())
+                ));
+            }
+            result.push(format!("{}.metadata.value=\"{}\"", prefix, child.value));
+
+            let context = SeriesContext::new(series, Some(child));
+            result.push(format!("{}.status={:?}", prefix, self.status(&context)));
+        }
+        result
+    }
+
+    fn status(&self, context: &SeriesContext) -> &'static str {
+        if calculate_bool(&self.red, context) {
+            "red"
+        } else if calculate_bool(&self.orange, context) {
+            "orange"
+        } else if calculate_bool(&self.yellow, context) {
+            "yellow"
+        } else if calculate_bool(&self.blue, context) {
+            "blue"
+        } else if calculate_bool(&self.green, context) {
+            "green"
+        } else {
+            "blank"
+        }
+    }
+}
+
+/// A single sample from the exposition format.
+#[derive(Clone, Debug, PartialEq)]
+struct Series {
+    name: String,
+    labels: BTreeMap<String, String>,
+    value: f64,
+}
+
+impl Series {
+    fn matches(&self, name: &str, matchers: &[LabelMatcher]) -> bool {
+        self.name == name && matchers.iter().all(|m| m.matches(&self.labels))
+    }
+}
+
+/// The scraped series as expression variables. Bare metric names are the value of their first
+/// series. When evaluating a child, the child's labels and `value` are variables too, and a bare
+/// metric name is the first series that agrees with the child on the labels they share.
+struct SeriesContext<'a> {
+    series: &'a [Series],
+    child: Option<&'a Series>,
+    variables: BTreeMap<String, Value>,
+}
+
+impl<'a> SeriesContext<'a> {
+    fn new(series: &'a [Series], child: Option<&'a Series>) -> Self {
+        let mut context = SeriesContext {
+            series,
+            child,
+            variables: BTreeMap::new(),
+        };
+        for series in series {
+            if !context.variables.contains_key(&series.name) && context.agrees(series) {
+                context
+                    .variables
+                    .insert(series.name.clone(), Value::Float(series.value));
+            }
+        }
+        if let Some(child) = child {
+            for (key, value) in &child.labels {
+                context
+                    .variables
+                    .insert(key.clone(), Value::Str(value.clone().into()));
+            }
+            context
+                .variables
+                .insert("value".to_string(), Value::Float(child.value));
+        }
+        context
+    }
+
+    fn agrees(&self, series: &Series) -> bool {
+        let Some(child) = self.child else {
+            return true;
+        };
+        series
+            .labels
+            .iter()
+            .all(|(key, value)| child.labels.get(key).is_none_or(|v| v == value))
+    }
+}
+
+impl ExpressionContext for SeriesContext<'_> {
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.variables.get(key)
+    }
+
+    fn get_series(&self, name: &str, matchers: &[LabelMatcher]) -> Option<Value> {
+        self.series
+            .iter()
+            .find(|s| s.matches(name, matchers))
+            .map(|s| Value::Float(s.value))
+    }
+}
+
+/// Parse a sample line such as `name{label="value"} 1.5 1700000000`. Comments, metadata and
+/// anything else that isn't a sample return `None`.
+fn parse_sample(line: &str) -> Option<Series> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return None;
+    }
+    let end = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or(line.len());
+    let (name, mut rest) = line.split_at(end);
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut labels = BTreeMap::new();
+    if let Some(mut s) = rest.strip_prefix('{') {
+        loop {
+            s = s.trim_start_matches([' ', ',']);
+            if let Some(s) = s.strip_prefix('}') {
+                rest = s;
+                break;
+            }
+            let (label, s2) = s.split_once('=')?;
+            let mut chars = s2.trim_start().strip_prefix('"')?.char_indices();
+            let mut value = String::new();
+            let end = loop {
+                match chars.next()? {
+                    (i, '"') => break i,
+                    (_, '\\') => match chars.next()?.1 {
+                        'n' => value.push('\n'),
+                        c => value.push(c),
+                    },
+                    (_, c) => value.push(c),
+                }
+            };
+            labels.insert(label.trim().to_string(), value);
+            s = &s2.trim_start()[1 + end + 1..];
+        }
+    }
+
+    // The value may be followed by a timestamp (and an exemplar in OpenMetrics)
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some(Series {
+        name: name.to_string(),
+        labels,
+        value,
+    })
+}
+
+fn calculate_bool(expression: &str, context: &impl ExpressionContext) -> bool {
+    match expressions::expression::calculate(expression, context) {
+        Ok(Ok(value)) => value.as_bool(),
+        Err(e) => {
+            log::warn!("Failed to parse expression {:?}: {}", expression, e);
+            false
+        }
+        Ok(Err(e)) => {
+            log::warn!("Failed to evaluate expression {:?}: {:?}", expression, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE_EXPORTER_OUTPUT: &str = r#"
+# HELP node_filesystem_avail_bytes Filesystem space available to non-root users in bytes.
+# TYPE node_filesystem_avail_bytes gauge
+node_filesystem_avail_bytes{device="/dev/sda1",fstype="ext4",mountpoint="/"} 5.36870912e+09
+node_filesystem_avail_bytes{device="/dev/sda2",fstype="vfat",mountpoint="/boot"} 1.34217728e+08
+node_filesystem_avail_bytes{device="tmpfs",fstype="tmpfs",mountpoint="/run"} 8.388608e+08
+# HELP node_filesystem_size_bytes Filesystem size in bytes.
+# TYPE node_filesystem_size_bytes gauge
+node_filesystem_size_bytes{device="/dev/sda1",fstype="ext4",mountpoint="/"} 1.073741824e+10
+node_filesystem_size_bytes{device="/dev/sda2",fstype="vfat",mountpoint="/boot"} 5.36870912e+08
+node_filesystem_size_bytes{device="tmpfs",fstype="tmpfs",mountpoint="/run"} 8.388608e+08
+# HELP node_load1 1m load average.
+# TYPE node_load1 gauge
+node_load1 0.42
+node_scrape_collector_success{collector="a \"quoted\", {odd} one"} 1 1700000000000
+go_gc_duration_seconds{quantile="+Inf"} NaN
+process_max_fds +Inf
+"#;
+
+    fn scrape() -> Vec<Series> {
+        NODE_EXPORTER_OUTPUT
+            .lines()
+            .filter_map(parse_sample)
+            .collect()
+    }
+
+    fn processor(red: &str, children: Option<(&str, &str)>) -> PrometheusMonitorMessageProcessor {
+        PrometheusMonitorMessageProcessor {
+            children: children.map(|(series, id)| PrometheusChildrenConfig {
+                series: series.to_string(),
+                id: id.to_string(),
+            }),
+            red: red.to_string(),
+            green: default_green(),
+            blue: default_blue(),
+            orange: default_orange(),
+            yellow: default_yellow(),
+        }
+    }
+
+    #[test]
+    fn test_parse_sample() {
+        let series = scrape();
+        assert_eq!(series.len(), 10);
+        assert_eq!(series[0].name, "node_filesystem_avail_bytes");
+        assert_eq!(series[0].labels["mountpoint"], "/");
+        assert_eq!(series[0].value, 5368709120.0);
+        assert_eq!(series[6].labels.len(), 0);
+        assert_eq!(series[6].value, 0.42);
+        assert_eq!(series[7].labels["collector"], "a \"quoted\", {odd} one");
+        assert_eq!(series[7].value, 1.0);
+        assert!(series[8].value.is_nan());
+        assert_eq!(series[8].labels["quantile"], "+Inf");
+        assert_eq!(
+            parse_sample("process_max_fds +Inf").unwrap().value,
+            f64::INFINITY
+        );
+        assert_eq!(parse_sample("curl: (7) Failed to connect"), None);
+        assert_eq!(parse_sample("broken{label=\"x} 1"), None);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let series = scrape();
+        let evaluate = |red: &str| processor(red, None).evaluate(&series);
+
+        assert_eq!(
+            evaluate(r#"node_filesystem_avail_bytes{mountpoint="/"} < 10e9"#),
+            vec!["status.metadata.series=\"10\"", "status.status=\"red\""]
+        );
+        assert_eq!(
+            evaluate(r#"node_filesystem_avail_bytes{mountpoint="/"} < 1e9"#)[1],
+            "status.status=\"green\""
+        );
+        assert_eq!(evaluate("node_load1 > 0.4")[1], "status.status=\"red\"");
+        // Missing series are an error, which never matches
+        assert_eq!(
+            evaluate(r#"node_filesystem_avail_bytes{mountpoint="/home"} < 1e9"#)[1],
+            "status.status=\"green\""
+        );
+
+        assert_eq!(
+            processor("true", None).evaluate(&[]),
+            vec![
+                "status.status=\"red\"",
+                "status.description=\"No metrics were scraped\""
+            ]
+        );
+    }
+
+    #[test]
+    fn test_evaluate_children() {
+        let series = scrape();
+        let result = processor(
+            "value / node_filesystem_size_bytes < 0.3",
+            Some((
+                r#"node_filesystem_avail_bytes{fstype!~"tmpfs|ramfs"}"#,
+                "disk-{{ device }}",
+            )),
+        )
+        .evaluate(&series);
+        let statuses: Vec<_> = result
+            .iter()
+            .filter(|line| line.contains(".status.status="))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                "group.disk-/dev/sda1.status.status=\"green\"",
+                "group.disk-/dev/sda2.status.status=\"red\"",
+            ]
+        );
+        assert!(
+            result.contains(&"group.disk-/dev/sda1.status.metadata.mountpoint=\"/\"".to_string())
+        );
+        assert!(result
+            .contains(&"group.disk-/dev/sda1.status.metadata.value=\"5368709120\"".to_string()));
+
+        // Labels can be used as variables, and selectors aren't limited to the child's labels
+        let result = processor(
+            r#"mountpoint == "/boot" and node_filesystem_avail_bytes{mountpoint="/"} > 1e9"#,
+            Some(("node_filesystem_size_bytes", "{{ mountpoint }}")),
+        )
+        .evaluate(&series);
+        assert!(result.contains(&"group./boot.status.status=\"red\"".to_string()));
+        assert!(result.contains(&"group./.status.status=\"green\"".to_string()));
+
+        let result = processor(
+            "true",
+            Some((
+                "node_filesystem_size_bytes",
+                "{{ mountpoint }}@10.0.0.1:9100",
+            )),
+        )
+        .evaluate(&series);
+        assert!(result.contains(&"group./boot-10-0-0-1-9100.status.status=\"red\"".to_string()));
+    }
+}
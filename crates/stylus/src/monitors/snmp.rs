@@ -17,7 +17,7 @@ use crate::{
     monitor::{MonitorMessageProcessor, MonitorMessageProcessorInstance},
 };
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub struct SnmpNetworkMonitorConfig {
@@ -546,7 +546,7 @@ impl SnmpNetworkMonitorConfig {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub struct SnmpNetworkMonitorSnmpConfig {
@@ -0,0 +1,480 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, ClientError, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use crate::monitor::Monitor;
+use crate::status::{MonitorStatus, Status, StatusState};
+
+/// How often the status is checked for changes to publish.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before reconnecting to the broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Publishes the status of every monitor and group child to retained topics on an MQTT broker,
+/// optionally with Home Assistant discovery messages, and accepts commands.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    /// The prefix for every topic that is published or subscribed to.
+    #[serde(default = "default_topic")]
+    pub topic: String,
+    /// Accept `run` and `ack` commands on `<topic>/<id>/command`. Off by default, as anyone who
+    /// can publish to the broker could then run and acknowledge monitors.
+    #[serde(default)]
+    pub commands: bool,
+    /// Publish Home Assistant MQTT discovery messages.
+    #[serde(default)]
+    pub discovery: bool,
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+    #[serde(with = "humantime_serde", default = "default_keep_alive")]
+    pub keep_alive: Duration,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "stylus".to_string()
+}
+
+fn default_topic() -> String {
+    "stylus".to_string()
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_keep_alive() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Home Assistant only allows these characters in discovery topics and unique ids.
+fn object_id(id: &str) -> String {
+    let id: String = id
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+    format!("stylus_{}", id)
+}
+
+impl MqttConfig {
+    fn availability_topic(&self) -> String {
+        format!("{}/availability", self.topic)
+    }
+
+    fn command_topic(&self, id: &str) -> String {
+        format!("{}/{}/command", self.topic, id)
+    }
+
+    /// Every retained message describing the current status, by topic.
+    fn messages(&self, status: &Status) -> BTreeMap<String, String> {
+        let mut messages = BTreeMap::new();
+        for monitor in &status.monitors {
+            let monitor = monitor.read();
+            self.status_messages(&mut messages, &monitor.id, None, &monitor.status);
+            for (id, child) in &monitor.children {
+                self.status_messages(&mut messages, &monitor.id, Some(id), &child.status);
+            }
+        }
+        messages
+    }
+
+    fn status_messages(
+        &self,
+        messages: &mut BTreeMap<String, String>,
+        monitor: &str,
+        child: Option<&str>,
+        status: &MonitorStatus,
+    ) {
+        let id = child.unwrap_or(monitor);
+        let base = format!("{}/{}", self.topic, id);
+        let state = status.status.unwrap_or(StatusState::Blank);
+        messages.insert(format!("{base}/status"), state.to_string().to_lowercase());
+        messages.insert(format!("{base}/description"), status.description.clone());
+        messages.insert(
+            format!("{base}/metadata"),
+            serde_json::to_string(&status.metadata).unwrap_or_default(),
+        );
+        if !self.discovery {
+            return;
+        }
+
+        // Each top-level monitor is a device, with its group children as extra entities. An
+        // entity without a name takes the name of its device.
+        let entity_id = object_id(id);
+        let device = json!({
+            "identifiers": [object_id(monitor)],
+            "name": monitor,
+            "manufacturer": "Stylus",
+        });
+        let common = json!({
+            "availability_topic": self.availability_topic(),
+            "device": device,
+        });
+        let mut entity = |component: &str, suffix: &str, config: serde_json::Value| {
+            let mut config = config;
+            config["unique_id"] = format!("{entity_id}{suffix}").into();
+            for (key, value) in common.as_object().into_iter().flatten() {
+                config[key] = value.clone();
+            }
+            messages.insert(
+                format!(
+                    "{}/{}/{}{}/config",
+                    self.discovery_prefix, component, entity_id, suffix
+                ),
+                config.to_string(),
+            );
+        };
+        let failing = StatusState::ALL
+            .into_iter()
+            .filter(|status| status.is_failing())
+            .map(|status| format!("'{}'", status.to_string().to_lowercase()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        entity(
+            "binary_sensor",
+            "",
+            json!({
+                "name": child,
+                "device_class": "problem",
+                "state_topic": format!("{base}/status"),
+                "value_template": format!("{{{{ 'ON' if value in [{failing}] else 'OFF' }}}}"),
+                "json_attributes_topic": format!("{base}/metadata"),
+            }),
+        );
+        entity(
+            "sensor",
+            "_status",
+            json!({
+                "name": child.map_or("Status".to_string(), |child| format!("{child} status")),
+                "device_class": "enum",
+                "options": ["blank", "green", "yellow", "red", "blue", "orange"],
+                "state_topic": format!("{base}/status"),
+            }),
+        );
+        if self.commands {
+            if child.is_none() {
+                entity(
+                    "button",
+                    "_run",
+                    json!({
+                        "name": "Run",
+                        "command_topic": self.command_topic(id),
+                        "payload_press": "run",
+                    }),
+                );
+            }
+            entity(
+                "button",
+                "_ack",
+                json!({
+                    "name": child.map_or("Acknowledge".to_string(), |child| format!("Acknowledge {child}")),
+                    "command_topic": self.command_topic(id),
+                    "payload_press": "ack",
+                }),
+            );
+        }
+    }
+}
+
+/// Publish the messages that changed since the last call, clearing the retained messages for
+/// monitors that no longer exist.
+async fn publish(
+    client: &AsyncClient,
+    messages: BTreeMap<String, String>,
+    published: &mut BTreeMap<String, String>,
+) -> Result<(), ClientError> {
+    for topic in published.keys() {
+        if !messages.contains_key(topic) {
+            client
+                .publish(topic, QoS::AtLeastOnce, true, Vec::new())
+                .await?;
+        }
+    }
+    published.retain(|topic, _| messages.contains_key(topic));
+    for (topic, payload) in messages {
+        if published.get(&topic) != Some(&payload) {
+            client
+                .publish(&topic, QoS::AtLeastOnce, true, payload.clone())
+                .await?;
+            published.insert(topic, payload);
+        }
+    }
+    Ok(())
+}
+
+/// Run a command received on `<topic>/<id>/command`.
+fn command(monitor: &Monitor, config: &MqttConfig, topic: &str, payload: &[u8]) {
+    let Some(id) = topic
+        .strip_prefix(&format!("{}/", config.topic))
+        .and_then(|topic| topic.strip_suffix("/command"))
+    else {
+        return;
+    };
+    let Some((state, _)) = monitor.find(id) else {
+        warn!("[{}] MQTT command for unknown monitor", id);
+        return;
+    };
+    match String::from_utf8_lossy(payload).trim() {
+        "run" => {
+            // Running a group child runs the whole group
+            let monitor_id = state.read().id.clone();
+            if let Some((_, control)) = monitor.control(&monitor_id) {
+                control.request_run();
+                info!("[{}] Run requested over MQTT", id);
+            }
+        }
+        "ack" => match monitor.acknowledge(id, None, None) {
+            Some(Ok(ack)) => info!("[{}] Acknowledged over MQTT: {:?}", id, ack),
+            Some(Err(e)) => warn!("[{}] Unable to acknowledge over MQTT: {}", id, e),
+            None => warn!("[{}] MQTT command for unknown monitor", id),
+        },
+        other => warn!("[{}] Unknown MQTT command {:?}", id, other),
+    }
+}
+
+/// Publish status changes to the MQTT broker, if one is configured, until shutdown.
+pub async fn run(monitor: Arc<Monitor>) {
+    let Some(config) = monitor.config().mqtt.clone() else {
+        return;
+    };
+
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(config.keep_alive);
+    options.set_last_will(LastWill::new(
+        config.availability_topic(),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, 100);
+
+    // The connection is polled separately so that publishing never waits on itself. A `None`
+    // means the connection was lost and will be retried.
+    let (sender, mut incoming) = mpsc::unbounded_channel();
+    let broker = format!("{}:{}", config.host, config.port);
+    tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(packet)) => {
+                    if sender.send(Some(packet)).is_err() {
+                        return;
+                    }
+                }
+                Ok(Event::Outgoing(_)) => {}
+                Err(e) => {
+                    warn!("MQTT connection to {} failed: {}", broker, e);
+                    if sender.send(None).is_err() {
+                        return;
+                    }
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    });
+
+    let mut connected = false;
+    let mut published = BTreeMap::new();
+    let mut interval = tokio::time::interval(PUBLISH_INTERVAL);
+    loop {
+        let result = tokio::select! {
+            packet = incoming.recv() => match packet {
+                Some(Some(Packet::ConnAck(_))) => {
+                    info!("Connected to MQTT broker {}:{}", config.host, config.port);
+                    connected = true;
+                    // The broker may have lost our retained messages, so publish everything
+                    published.clear();
+                    let mut result = client
+                        .publish(config.availability_topic(), QoS::AtLeastOnce, true, "online")
+                        .await;
+                    if config.commands && result.is_ok() {
+                        result = client
+                            .subscribe(config.command_topic("+"), QoS::AtLeastOnce)
+                            .await;
+                    }
+                    result
+                }
+                Some(Some(Packet::Publish(message))) => {
+                    command(&monitor, &config, &message.topic, &message.payload);
+                    Ok(())
+                }
+                Some(Some(_)) => Ok(()),
+                Some(None) => {
+                    connected = false;
+                    Ok(())
+                }
+                None => return,
+            },
+            _ = interval.tick(), if connected => {
+                publish(&client, config.messages(&monitor.status()), &mut published).await
+            }
+        };
+        if let Err(e) = result {
+            warn!("Failed to publish to MQTT broker: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::config::parse_config;
+    use crate::stream::StreamStart;
+    use bytes::BytesMut;
+    use rumqttc::{ConnAck, ConnectReturnCode, PingResp, PubAck, Publish, SubAck};
+    use rumqttc::{SubscribeReasonCode, SubscribeReasonCode::Success};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// A minimal MQTT broker that accepts a single client, sending the messages it publishes to
+    /// the returned receiver and, once it has subscribed, the messages from the returned sender
+    /// to the client.
+    pub async fn mqtt_broker() -> (
+        u16,
+        mpsc::UnboundedReceiver<Publish>,
+        mpsc::UnboundedSender<Publish>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (published, received) = mpsc::unbounded_channel();
+        let (sender, mut outgoing) = mpsc::unbounded_channel::<Publish>();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            let mut read = BytesMut::new();
+            let mut subscribed = false;
+            loop {
+                let mut write = BytesMut::new();
+                tokio::select! {
+                    n = stream.read_buf(&mut read) => {
+                        if n? == 0 {
+                            return Ok::<_, std::io::Error>(());
+                        }
+                    }
+                    Some(message) = outgoing.recv(), if subscribed => {
+                        message.write(&mut write).unwrap();
+                    }
+                }
+                while let Ok(packet) = Packet::read(&mut read, 1 << 20) {
+                    match packet {
+                        Packet::Connect(..) => ConnAck::new(ConnectReturnCode::Success, false)
+                            .write(&mut write)
+                            .map(drop),
+                        Packet::Subscribe(subscribe) => {
+                            subscribed = true;
+                            let codes: Vec<SubscribeReasonCode> =
+                                subscribe.filters.iter().map(|f| Success(f.qos)).collect();
+                            SubAck::new(subscribe.pkid, codes)
+                                .write(&mut write)
+                                .map(drop)
+                        }
+                        Packet::Publish(publish) => {
+                            let ack = PubAck::new(publish.pkid).write(&mut write).map(drop);
+                            let _ = published.send(publish);
+                            ack
+                        }
+                        Packet::PingReq => PingResp.write(&mut write).map(drop),
+                        _ => Ok(()),
+                    }
+                    .unwrap();
+                }
+                stream.write_all(&write).await?;
+            }
+        });
+        (port, received, sender)
+    }
+
+    #[tokio::test]
+    async fn test_mqtt() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, mut received, sender) = mqtt_broker().await;
+        let dir = std::env::temp_dir().join(format!("stylus-mqtt-{}", std::process::id()));
+        let monitor_dir = dir.join("monitor.d/router");
+        std::fs::create_dir_all(&monitor_dir)?;
+        std::fs::write(
+            dir.join("config.yaml"),
+            format!(
+                "version: 1\nserver:\n  port: 8000\nmonitor:\n  state:\n    file: null\n  \
+                 history:\n    dir: null\nmqtt:\n  host: 127.0.0.1\n  port: {port}\n  \
+                 discovery: true\n  commands: true\n"
+            ),
+        )?;
+        std::fs::write(
+            monitor_dir.join("config.yaml"),
+            "test:\n  interval: 60s\n  timeout: 30s\n  command: /bin/false\n",
+        )?;
+        let monitor = Arc::new(Monitor::new(&parse_config(&dir)?)?);
+        tokio::spawn(run(monitor.clone()));
+
+        // Wait for the result of the first run to be published
+        let mut messages = BTreeMap::new();
+        while messages.get("stylus/router/status") != Some(&"red".to_string()) {
+            let message = received.recv().await.unwrap();
+            assert!(message.retain, "{message:?}");
+            messages.insert(
+                message.topic,
+                String::from_utf8_lossy(&message.payload).to_string(),
+            );
+        }
+        assert_eq!(messages["stylus/availability"], "online");
+        assert_eq!(messages["stylus/router/description"], "Failed");
+        let discovery: serde_json::Value =
+            serde_json::from_str(&messages["homeassistant/binary_sensor/stylus_router/config"])?;
+        assert_eq!(discovery["state_topic"], "stylus/router/status");
+        assert_eq!(
+            discovery["value_template"],
+            "{{ 'ON' if value in ['yellow', 'red', 'orange'] else 'OFF' }}"
+        );
+        assert_eq!(discovery["device"]["name"], "router");
+        assert!(messages.contains_key("homeassistant/button/stylus_router_run/config"));
+
+        let StreamStart::Snapshot(before) = monitor.updates().subscribe(None).0 else {
+            unreachable!()
+        };
+        sender.send(Publish::new(
+            "stylus/router/command",
+            QoS::AtMostOnce,
+            "ack",
+        ))?;
+        let (state, _) = monitor.find("router").unwrap();
+        for _ in 0..50 {
+            if state.read().status.is_acknowledged() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(state.read().status.is_acknowledged());
+        // Streams are told about the acknowledgement
+        let event_id = monitor.updates().event_id(before);
+        let (start, _) = monitor.updates().subscribe(Some(&event_id));
+        assert_eq!(
+            start,
+            StreamStart::Resume(vec![(before + 1, "router".to_string())])
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
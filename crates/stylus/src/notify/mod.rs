@@ -0,0 +1,313 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::config::{NotifyRouteConfig, NotifyTargetConfig, NotifyTargetType};
+use crate::events::MonitorEvent;
+use crate::monitor::Monitor;
+use crate::status::StatusState;
+use policy::{AlertTracker, Outbox};
+
+pub mod policy;
+pub mod smtp;
+pub mod webhook;
+
+/// How often alert policies are checked for delayed alerts, reminders and escalations.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Why a notification is being sent.
+#[derive(Clone, Copy, Debug, Default, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationKind {
+    /// The status changed.
+    #[default]
+    Change,
+    /// The monitor is still failing.
+    Reminder,
+    /// The monitor is still failing, and this target is being notified as an escalation.
+    Escalation,
+    /// The monitor recovered from a failure that was notified.
+    Recovery,
+}
+
+/// A status change, as sent to notification targets and used to render their templates.
+#[derive(Clone, Debug, Serialize)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    /// The monitor or group child that changed.
+    pub id: String,
+    /// The id of the [`MonitorEvent`].
+    pub event_id: u64,
+    pub time: DateTime<Utc>,
+    pub monitor: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub child: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_status: Option<StatusState>,
+    pub status: StatusState,
+    pub code: i64,
+    pub description: String,
+    pub metadata: BTreeMap<String, String>,
+    pub log: Vec<String>,
+}
+
+impl From<MonitorEvent> for Notification {
+    fn from(event: MonitorEvent) -> Self {
+        Notification {
+            kind: NotificationKind::Change,
+            id: event.child.clone().unwrap_or_else(|| event.monitor.clone()),
+            event_id: event.id,
+            time: event.time,
+            monitor: event.monitor,
+            child: event.child,
+            old_status: event.old_status,
+            status: event.status,
+            code: event.code,
+            description: event.description,
+            metadata: event.metadata,
+            log: event.log,
+        }
+    }
+}
+
+type NotificationQueue = mpsc::UnboundedSender<(NotifyTargetConfig, Notification)>;
+
+impl NotifyRouteConfig {
+    pub fn matches(&self, event: &MonitorEvent) -> bool {
+        let from_matches = if self.from.is_empty() {
+            // The first run of a monitor is not a change worth telling anyone about
+            !matches!(event.old_status, None | Some(StatusState::Blank))
+        } else {
+            event
+                .old_status
+                .is_some_and(|status| self.from.contains(&status))
+        };
+        from_matches
+            && (self.to.is_empty() || self.to.contains(&event.status))
+            && (self.monitors.is_empty() || self.monitors.iter().any(|id| event.matches_id(id)))
+    }
+}
+
+/// Send notifications for status changes as they happen. Each target has its own queue so that
+/// a slow or failing target delays neither the others nor the monitors, while notifications to
+/// a single target are delivered in order.
+pub async fn run(monitor: Arc<Monitor>) {
+    let mut events = monitor.events().subscribe();
+    let mut interval = tokio::time::interval(TICK_INTERVAL);
+    let mut tracker = AlertTracker::default();
+    let client = reqwest::Client::new();
+    let mut queues: HashMap<String, NotificationQueue> = HashMap::new();
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => Some(event),
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("Skipped notifications for {} status change(s)", count);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            _ = interval.tick() => None,
+        };
+
+        // Use the latest configuration so that reloads apply immediately
+        let config = monitor.config();
+        let now = Instant::now();
+        let mut outbox = match event {
+            Some(event) => tracker.process(&config.notify, &event, now),
+            None => vec![],
+        };
+        outbox.extend(tracker.tick(&config.notify, now, |id| is_suppressed(&monitor, id)));
+        enqueue(&config.notify.targets, &client, &mut queues, outbox);
+    }
+}
+
+/// Alerts are not sent for acknowledged or paused monitors.
+fn is_suppressed(monitor: &Monitor, id: &str) -> bool {
+    let Some((state, child)) = monitor.find(id) else {
+        return false;
+    };
+    let state = state.read();
+    let status = match &child {
+        Some(child) => state.children.get(child).map(|child| &child.status),
+        None => Some(&state.status),
+    };
+    state.paused || status.is_some_and(|status| status.is_acknowledged())
+}
+
+fn enqueue(
+    targets: &BTreeMap<String, NotifyTargetConfig>,
+    client: &reqwest::Client,
+    queues: &mut HashMap<String, NotificationQueue>,
+    outbox: Outbox,
+) {
+    for (name, notification) in outbox {
+        let Some(target) = targets.get(&name) else {
+            continue;
+        };
+        let queue = queues
+            .entry(name.clone())
+            .or_insert_with(|| spawn_queue(name, client.clone()));
+        let _ = queue.send((target.clone(), notification));
+    }
+}
+
+fn spawn_queue(name: String, client: reqwest::Client) -> NotificationQueue {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<(NotifyTargetConfig, Notification)>();
+    tokio::spawn(async move {
+        while let Some((target, notification)) = receiver.recv().await {
+            // Collect any other notifications that arrive within the digest window
+            let mut notifications = vec![notification];
+            let digest = target.target.digest();
+            if !digest.is_zero() {
+                let deadline = tokio::time::Instant::now() + digest;
+                while let Ok(Some((_, notification))) =
+                    tokio::time::timeout_at(deadline, receiver.recv()).await
+                {
+                    notifications.push(notification);
+                }
+            }
+            deliver(&name, &client, &target, &notifications).await;
+        }
+    });
+    sender
+}
+
+async fn send(
+    client: &reqwest::Client,
+    target: &NotifyTargetConfig,
+    notifications: &[Notification],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match &target.target {
+        NotifyTargetType::Webhook(webhook) => {
+            for notification in notifications {
+                webhook.send(client, notification).await?;
+            }
+            Ok(())
+        }
+        NotifyTargetType::Smtp(smtp) => smtp.send(notifications).await,
+    }
+}
+
+/// Deliver a batch of notifications, retrying with exponential backoff.
+async fn deliver(
+    name: &str,
+    client: &reqwest::Client,
+    target: &NotifyTargetConfig,
+    notifications: &[Notification],
+) {
+    let ids = notifications
+        .iter()
+        .map(|notification| notification.id.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let retry = &target.retry;
+    let attempts = retry.attempts.max(1);
+    let mut backoff = retry.backoff;
+    for attempt in 1..=attempts {
+        match send(client, target, notifications).await {
+            Ok(()) => {
+                debug!("[{}] Notified {}", ids, name);
+                return;
+            }
+            Err(e) if attempt < attempts => {
+                warn!(
+                    "[{}] Failed to notify {} (attempt {}/{}), retrying in {:?}: {}",
+                    ids, name, attempt, attempts, backoff, e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(e) => {
+                error!(
+                    "[{}] Failed to notify {} after {} attempt(s): {}",
+                    ids, name, attempts, e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NotifyConfig;
+    use crate::status::MonitorStatus;
+
+    fn event(child: Option<&str>, old_status: StatusState, status: StatusState) -> MonitorEvent {
+        let status = MonitorStatus {
+            status: Some(status),
+            ..Default::default()
+        };
+        MonitorEvent::from_change(
+            "group",
+            child,
+            Some(old_status),
+            &status,
+            &Default::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_route() -> Result<(), Box<dyn Error>> {
+        let config: NotifyConfig = serde_yaml_ng::from_str(
+            r#"
+            targets:
+              all:
+                webhook:
+                  url: http://localhost/all
+              port:
+                webhook:
+                  url: http://localhost/port
+            routes:
+              - targets: [all]
+              - targets: [all, port]
+                monitors: [group-1]
+                to: [red]
+            "#,
+        )?;
+        let route = |event| {
+            AlertTracker::default()
+                .process(&config, &event, Instant::now())
+                .into_iter()
+                .map(|(target, _)| target)
+                .collect::<Vec<_>>()
+        };
+        use StatusState::*;
+        assert!(route(event(None, Blank, Green)).is_empty());
+        assert_eq!(route(event(None, Green, Red)), vec!["all"]);
+        assert_eq!(
+            route(event(Some("group-1"), Green, Red)),
+            vec!["all", "port"]
+        );
+        assert_eq!(route(event(Some("group-1"), Red, Green)), vec!["all"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_digest() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (port, mut received) = smtp::tests::smtp_server().await;
+        let target = NotifyTargetConfig {
+            target: NotifyTargetType::Smtp(smtp::tests::config(port)),
+            retry: Default::default(),
+        };
+        let queue = spawn_queue("email".into(), reqwest::Client::new());
+        for id in ["switch-port-1", "switch-port-2", "switch-port-3"] {
+            let event = event(Some(id), StatusState::Green, StatusState::Red);
+            queue.send((target.clone(), event.into()))?;
+        }
+
+        let message = received.recv().await.unwrap();
+        assert!(message.contains("Subject: 3 status changes"), "{message}");
+        for id in ["switch-port-1", "switch-port-2", "switch-port-3"] {
+            assert!(message.contains(&format!("{id} changed from green to red")));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::config::{NotifyConfig, NotifyRouteConfig};
+use crate::events::MonitorEvent;
+use crate::notify::{Notification, NotificationKind};
+
+/// An ongoing failure of a monitor or group child on a route with an alert policy.
+#[derive(Debug)]
+struct Alert {
+    /// The most recent status change.
+    notification: Notification,
+    started: Instant,
+    /// When the targets were last notified, if they have been.
+    last_sent: Option<Instant>,
+    escalated: bool,
+}
+
+impl Alert {
+    /// The targets that have been told about this alert, and need to hear about its recovery.
+    fn notified<'a>(&self, route: &'a NotifyRouteConfig) -> Vec<&'a String> {
+        let mut targets = vec![];
+        if self.last_sent.is_some() {
+            targets.extend(&route.targets);
+        }
+        if self.escalated {
+            targets.extend(&route.escalate_to);
+        }
+        targets
+    }
+}
+
+/// The notifications to send, as target names and notifications.
+pub type Outbox = Vec<(String, Notification)>;
+
+fn send(outbox: &mut Outbox, target: &str, notification: &Notification, kind: NotificationKind) {
+    // A target is only told about the same thing once, even if several routes match
+    if outbox
+        .iter()
+        .any(|(t, n)| t == target && n.event_id == notification.event_id && n.kind == kind)
+    {
+        return;
+    }
+    let mut notification = notification.clone();
+    notification.kind = kind;
+    outbox.push((target.to_owned(), notification));
+}
+
+/// Applies the notification routes to status changes. Routes without an alert policy notify
+/// their targets of every matching change, while routes with a policy track each failure until
+/// it recovers, sending delayed alerts, reminders and escalations from [`AlertTracker::tick`].
+#[derive(Debug, Default)]
+pub struct AlertTracker {
+    /// Alerts by route and monitor or group child id. Routes are known by their configuration
+    /// rather than their position, so that a reload that adds or reorders routes leaves the
+    /// alerts of the others alone.
+    routes: Vec<(NotifyRouteConfig, HashMap<String, Alert>)>,
+}
+
+impl AlertTracker {
+    /// The alerts of a route, which are added if the route has none.
+    fn alerts(&mut self, route: &NotifyRouteConfig) -> &mut HashMap<String, Alert> {
+        let index = match self.routes.iter().position(|(r, _)| r == route) {
+            Some(index) => index,
+            None => {
+                self.routes.push((route.clone(), HashMap::new()));
+                self.routes.len() - 1
+            }
+        };
+        &mut self.routes[index].1
+    }
+
+    /// Process a status change, returning the notifications to send immediately.
+    pub fn process(&mut self, config: &NotifyConfig, event: &MonitorEvent, now: Instant) -> Outbox {
+        let notification: Notification = event.clone().into();
+        let mut outbox = vec![];
+        for route in &config.routes {
+            if !route.is_alert() {
+                if route.matches(event) {
+                    for target in &route.targets {
+                        send(&mut outbox, target, &notification, NotificationKind::Change);
+                    }
+                }
+                continue;
+            }
+
+            let alerts = self.alerts(route);
+            match alerts.get_mut(&notification.id) {
+                Some(alert) if route.is_failing(event.status) => {
+                    // Still failing, but in a different way
+                    alert.notification = notification.clone();
+                    for target in alert.notified(route) {
+                        send(&mut outbox, target, &notification, NotificationKind::Change);
+                    }
+                }
+                Some(alert) => {
+                    if route.recovery {
+                        for target in alert.notified(route) {
+                            send(
+                                &mut outbox,
+                                target,
+                                &notification,
+                                NotificationKind::Recovery,
+                            );
+                        }
+                    }
+                    alerts.remove(&notification.id);
+                }
+                None if route.matches(event) && route.is_failing(event.status) => {
+                    alerts.insert(
+                        notification.id.clone(),
+                        Alert {
+                            notification: notification.clone(),
+                            started: now,
+                            last_sent: None,
+                            escalated: false,
+                        },
+                    );
+                }
+                None => {}
+            }
+        }
+        outbox
+    }
+
+    /// Send any alerts, reminders and escalations that are due. Nothing is sent for monitors that
+    /// are `suppressed` (ie: acknowledged or paused), although their alerts stay open.
+    pub fn tick(
+        &mut self,
+        config: &NotifyConfig,
+        now: Instant,
+        suppressed: impl Fn(&str) -> bool,
+    ) -> Outbox {
+        let mut outbox = vec![];
+        // Routes may have been changed or removed by a reload
+        self.routes
+            .retain(|(route, alerts)| !alerts.is_empty() && config.routes.contains(route));
+        for (route, alerts) in &mut self.routes {
+            alerts.retain(|id, alert| {
+                if suppressed(id) {
+                    return true;
+                }
+
+                let elapsed = now.duration_since(alert.started);
+                match alert.last_sent {
+                    None if elapsed >= route.notify_after.unwrap_or_default() => {
+                        for target in &route.targets {
+                            send(
+                                &mut outbox,
+                                target,
+                                &alert.notification,
+                                NotificationKind::Change,
+                            );
+                        }
+                        alert.last_sent = Some(now);
+                    }
+                    Some(last_sent)
+                        if route
+                            .repeat_every
+                            .is_some_and(|repeat| now.duration_since(last_sent) >= repeat) =>
+                    {
+                        for target in alert.notified(route) {
+                            send(
+                                &mut outbox,
+                                target,
+                                &alert.notification,
+                                NotificationKind::Reminder,
+                            );
+                        }
+                        alert.last_sent = Some(now);
+                    }
+                    _ => {}
+                }
+                if !alert.escalated && route.escalate_after.is_some_and(|after| elapsed >= after) {
+                    for target in &route.escalate_to {
+                        send(
+                            &mut outbox,
+                            target,
+                            &alert.notification,
+                            NotificationKind::Escalation,
+                        );
+                    }
+                    alert.escalated = true;
+                }
+                true
+            });
+        }
+        outbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::{MonitorStatus, StatusState};
+    use std::error::Error;
+    use std::time::Duration;
+
+    fn event(old_status: StatusState, status: StatusState) -> MonitorEvent {
+        let status = MonitorStatus {
+            status: Some(status),
+            ..Default::default()
+        };
+        MonitorEvent::from_change("nas", None, Some(old_status), &status, &Default::default())
+            .unwrap()
+    }
+
+    fn sent(outbox: Outbox) -> Vec<(String, StatusState, NotificationKind)> {
+        outbox
+            .into_iter()
+            .map(|(target, n)| (target, n.status, n.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_policy() -> Result<(), Box<dyn Error>> {
+        use NotificationKind::*;
+        use StatusState::*;
+
+        let config: NotifyConfig = serde_yaml_ng::from_str(
+            r#"
+            targets:
+              email:
+                webhook:
+                  url: http://localhost/email
+              phone:
+                webhook:
+                  url: http://localhost/phone
+            routes:
+              - targets: [email]
+                to: [red]
+                notify_after: 5m
+                repeat_every: 1h
+                escalate_after: 2h
+                escalate_to: [phone]
+            "#,
+        )?;
+        let start = Instant::now();
+        let minutes = |m: u64| start + Duration::from_secs(m * 60);
+        let mut tracker = AlertTracker::default();
+
+        // A short failure is never notified
+        assert!(sent(tracker.process(&config, &event(Green, Red), start)).is_empty());
+        assert!(sent(tracker.tick(&config, minutes(4), |_| false)).is_empty());
+        assert!(sent(tracker.process(&config, &event(Red, Green), minutes(4))).is_empty());
+        assert!(sent(tracker.tick(&config, minutes(10), |_| false)).is_empty());
+
+        // A long failure is notified, repeated and escalated
+        tracker.process(&config, &event(Green, Red), minutes(10));
+        assert_eq!(
+            sent(tracker.tick(&config, minutes(15), |_| false)),
+            vec![("email".into(), Red, Change)]
+        );
+        assert!(sent(tracker.tick(&config, minutes(16), |_| false)).is_empty());
+
+        // Adding a route in front of it doesn't lose track of the alert
+        let mut reloaded = config.clone();
+        reloaded.routes.insert(0, config.routes[0].clone());
+        reloaded.routes[0].repeat_every = None;
+        assert_eq!(
+            sent(tracker.tick(&reloaded, minutes(75), |_| false)),
+            vec![("email".into(), Red, Reminder)]
+        );
+        let config = reloaded;
+        assert_eq!(
+            sent(tracker.tick(&config, minutes(130), |_| false)),
+            vec![("phone".into(), Red, Escalation)]
+        );
+        assert_eq!(
+            sent(tracker.process(&config, &event(Red, Green), minutes(140))),
+            vec![
+                ("email".into(), Green, Recovery),
+                ("phone".into(), Green, Recovery)
+            ]
+        );
+
+        // Acknowledged failures are not notified
+        tracker.process(&config, &event(Green, Red), minutes(200));
+        assert!(sent(tracker.tick(&config, minutes(400), |_| true)).is_empty());
+        assert!(sent(tracker.process(&config, &event(Red, Green), minutes(400))).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+use std::error::Error;
+use std::time::Duration;
+
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+
+use crate::interpolate::interpolate_template;
+use crate::notify::Notification;
+
+/// Sends an email for each batch of notifications. The subject and body are handlebars templates
+/// rendered with a [`Digest`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpTargetConfig {
+    pub host: String,
+    /// The port, which defaults to the standard port for the `tls` mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTlsMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default = "default_subject")]
+    pub subject: String,
+    #[serde(default = "default_body")]
+    pub body: String,
+    /// Status changes within this window of the first are sent together in one email.
+    #[serde(with = "humantime_serde", default = "default_digest")]
+    pub digest: Duration,
+    #[serde(with = "humantime_serde", default = "default_timeout")]
+    pub timeout: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTlsMode {
+    /// Plain SMTP, usually on port 25. Only suitable for local relays.
+    None,
+    /// Upgrade to TLS with `STARTTLS`, usually on port 587.
+    #[default]
+    Starttls,
+    /// Implicit TLS, usually on port 465.
+    Tls,
+}
+
+fn default_subject() -> String {
+    "{{#if (eq count 1)}}{{#with notifications.[0]}}{{id}} is {{status}}{{/with}}\
+     {{else}}{{count}} status changes{{/if}}"
+        .to_string()
+}
+
+fn default_body() -> String {
+    "{{#each notifications}}\
+     {{time}}: {{id}} changed from {{old_status}} to {{status}} ({{description}})\n\
+     {{/each}}"
+        .to_string()
+}
+
+fn default_digest() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// The context for email templates: every status change collected in the digest window.
+#[derive(Debug, Serialize)]
+pub struct Digest<'a> {
+    pub count: usize,
+    pub notifications: &'a [Notification],
+}
+
+impl SmtpTargetConfig {
+    fn message(&self, notifications: &[Notification]) -> Result<Message, Box<dyn Error>> {
+        let digest = Digest {
+            count: notifications.len(),
+            notifications,
+        };
+        let mut message = Message::builder()
+            .from(self.from.parse()?)
+            .subject(interpolate_template(&digest, &self.subject)?)
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            message = message.to(to.parse()?);
+        }
+        Ok(message.body(interpolate_template(&digest, &self.body)? + "\n")?)
+    }
+
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>, Box<dyn Error>> {
+        let builder = match self.tls {
+            SmtpTlsMode::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.host)
+            }
+            SmtpTlsMode::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.host)?
+            }
+            SmtpTlsMode::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.host)?,
+        };
+        let mut builder = builder.timeout(Some(self.timeout));
+        if let Some(port) = self.port {
+            builder = builder.port(port);
+        }
+        if let Some(username) = &self.username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                self.password.clone().unwrap_or_default(),
+            ));
+        }
+        Ok(builder.build())
+    }
+
+    pub async fn send(
+        &self,
+        notifications: &[Notification],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (message, transport) = self
+            .message(notifications)
+            .and_then(|message| Ok((message, self.transport()?)))
+            .map_err(|e| format!("Invalid email: {}", e))?;
+        transport.send(message).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// A minimal SMTP server that accepts every message, sending the message data to the
+    /// returned receiver.
+    pub async fn smtp_server() -> (u16, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    let mut data = None::<String>;
+                    write.write_all(b"220 localhost ESMTP\r\n").await?;
+                    while let Some(line) = lines.next_line().await? {
+                        if let Some(message) = &mut data {
+                            if line == "." {
+                                let _ = sender.send(data.take().unwrap());
+                                write.write_all(b"250 OK\r\n").await?;
+                            } else {
+                                message.push_str(&line);
+                                message.push('\n');
+                            }
+                            continue;
+                        }
+                        let command = line.to_uppercase();
+                        let reply: &[u8] = if command.starts_with("EHLO") {
+                            b"250 localhost\r\n"
+                        } else if command.starts_with("DATA") {
+                            data = Some(String::new());
+                            b"354 Go ahead\r\n"
+                        } else if command.starts_with("QUIT") {
+                            write.write_all(b"221 Bye\r\n").await?;
+                            break;
+                        } else {
+                            b"250 OK\r\n"
+                        };
+                        write.write_all(reply).await?;
+                    }
+                    Ok::<_, std::io::Error>(())
+                });
+            }
+        });
+        (port, receiver)
+    }
+
+    pub fn config(port: u16) -> SmtpTargetConfig {
+        serde_yaml_ng::from_str(&format!(
+            r#"
+            host: 127.0.0.1
+            port: {port}
+            tls: none
+            from: Stylus <stylus@example.com>
+            to: [family@example.com]
+            digest: 200ms
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_send() -> Result<(), Box<dyn Error + Send + Sync>> {
+        use crate::events::MonitorEvent;
+        use crate::status::{MonitorStatus, StatusState};
+
+        let (port, mut received) = smtp_server().await;
+        let status = MonitorStatus {
+            status: Some(StatusState::Red),
+            description: "Failed".into(),
+            ..Default::default()
+        };
+        let event = MonitorEvent::from_change(
+            "router",
+            None,
+            Some(StatusState::Green),
+            &status,
+            &Default::default(),
+        )
+        .unwrap();
+        config(port).send(&[event.into()]).await?;
+
+        let message = received.recv().await.unwrap();
+        assert!(message.contains("Subject: router is red"), "{message}");
+        assert!(message.contains("To: family@example.com"), "{message}");
+        assert!(
+            message.contains("router changed from green to red (Failed)"),
+            "{message}"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::interpolate::interpolate_template;
+use crate::notify::Notification;
+
+/// Sends an HTTP request for each notification. The URL, header values and body are handlebars
+/// templates rendered with the [`Notification`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookTargetConfig {
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// The request body. If not specified, the notification is sent as JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(with = "humantime_serde", default = "default_timeout")]
+    pub timeout: Duration,
+}
+
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+impl WebhookTargetConfig {
+    fn request(
+        &self,
+        client: &reqwest::Client,
+        notification: &Notification,
+    ) -> Result<reqwest::RequestBuilder, Box<dyn Error>> {
+        let method = reqwest::Method::from_bytes(self.method.to_uppercase().as_bytes())?;
+        let url = interpolate_template(notification, &self.url)?;
+        let mut request = client.request(method, url).timeout(self.timeout);
+        let body = match &self.body {
+            Some(body) => interpolate_template(notification, body)?,
+            None => {
+                request = request.header("Content-Type", "application/json");
+                serde_json::to_string(notification)?
+            }
+        };
+        for (name, value) in &self.headers {
+            request = request.header(name, interpolate_template(notification, value)?);
+        }
+        Ok(request.body(body))
+    }
+
+    pub async fn send(
+        &self,
+        client: &reqwest::Client,
+        notification: &Notification,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let request = self
+            .request(client, notification)
+            .map_err(|e| format!("Invalid webhook request: {}", e))?;
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(format!("Webhook returned {}", response.status()).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::MonitorEvent;
+    use crate::status::{MonitorStatus, StatusState};
+    use axum::{http::HeaderMap, routing::post, Router};
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_webhook() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let received = Arc::new(Mutex::new(vec![]));
+        let app = Router::new().route(
+            "/hook/:id",
+            post({
+                let received = received.clone();
+                move |headers: HeaderMap, body: String| async move {
+                    let title = headers["Title"].to_str().unwrap().to_owned();
+                    received.lock().unwrap().push((title, body));
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config: WebhookTargetConfig = serde_yaml_ng::from_str(&format!(
+            r#"
+            url: http://{addr}/hook/{{{{id}}}}
+            headers:
+              Title: "{{{{id}}}} is {{{{status}}}}"
+            body: '{{"text": {{{{json description}}}}, "from": "{{{{old_status}}}}"}}'
+            "#
+        ))?;
+        let status = MonitorStatus {
+            status: Some(StatusState::Red),
+            description: "Ping \"failed\"".into(),
+            ..Default::default()
+        };
+        let event = MonitorEvent::from_change(
+            "router",
+            None,
+            Some(StatusState::Green),
+            &status,
+            &Default::default(),
+        )
+        .unwrap();
+        config.send(&reqwest::Client::new(), &event.into()).await?;
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![(
+                "router is red".to_owned(),
+                r#"{"text": "Ping \"failed\"", "from": "green"}"#.to_owned()
+            )]
+        );
+
+        // A missing endpoint fails the delivery
+        let config = WebhookTargetConfig {
+            url: format!("http://{addr}/missing"),
+            ..config
+        };
+        let event = MonitorEvent::from_change(
+            "router",
+            None,
+            Some(StatusState::Green),
+            &status,
+            &Default::default(),
+        )
+        .unwrap();
+        assert!(config
+            .send(&reqwest::Client::new(), &event.into())
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use walkdir::WalkDir;
+
+use crate::config::Config;
+use crate::monitor::{Monitor, ReloadSummary};
+
+/// How often the configuration files are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Reload the configuration, logging the outcome. A failed reload leaves the running
+/// configuration untouched.
+pub fn reload(monitor: &Monitor, reason: &str) -> Result<ReloadSummary, String> {
+    match monitor.reload() {
+        Ok(summary) => {
+            info!(
+                "Reloaded configuration ({}): added={:?} removed={:?} restarted={:?}",
+                reason, summary.added, summary.removed, summary.restarted
+            );
+            Ok(summary)
+        }
+        Err(e) => {
+            error!(
+                "Failed to reload configuration ({}), keeping the current configuration: {}",
+                reason, e
+            );
+            Err(e.to_string())
+        }
+    }
+}
+
+/// Poll `config.yaml`, `config.d` and the monitor directory for changes, reloading when any of
+/// them are modified.
+pub async fn watch(monitor: Arc<Monitor>) {
+    let mut last = fingerprint(&monitor.config());
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await;
+        let current = fingerprint(&monitor.config());
+        if current == last {
+            continue;
+        }
+        debug!("Configuration files changed");
+        let reload_monitor = monitor.clone();
+        let _ = tokio::task::spawn_blocking(move || reload(&reload_monitor, "files changed")).await;
+        // Whether or not the reload succeeded, wait for the next change before trying again
+        last = fingerprint(&monitor.config());
+    }
+}
+
+/// Reload the configuration whenever the process receives `SIGHUP`.
+#[cfg(unix)]
+pub async fn watch_signal(monitor: Arc<Monitor>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hup = match signal(SignalKind::hangup()) {
+        Ok(hup) => hup,
+        Err(e) => {
+            error!("Unable to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hup.recv().await.is_some() {
+        let reload_monitor = monitor.clone();
+        let _ = tokio::task::spawn_blocking(move || reload(&reload_monitor, "SIGHUP")).await;
+    }
+}
+
+type Fingerprint = Vec<(PathBuf, Option<SystemTime>, u64)>;
+
+/// Collect the modification times and sizes of every file that makes up the configuration.
+fn fingerprint(config: &Config) -> Fingerprint {
+    let mut files = vec![];
+    add_file(&mut files, &config.config_file);
+    add_dir(&mut files, &config.base_path.join("config.d"), 1);
+    // Monitor directories, and the config.yaml inside each of them
+    add_dir(&mut files, &config.monitor.dir, 2);
+    files.sort();
+    files
+}
+
+fn add_file(files: &mut Fingerprint, path: &Path) {
+    let metadata = std::fs::metadata(path).ok();
+    files.push((
+        path.to_owned(),
+        metadata.as_ref().and_then(|m| m.modified().ok()),
+        metadata.map(|m| m.len()).unwrap_or_default(),
+    ));
+}
+
+fn add_dir(files: &mut Fingerprint, dir: &Path, depth: usize) {
+    for entry in WalkDir::new(dir)
+        .min_depth(1)
+        .max_depth(depth)
+        .follow_links(true)
+        .into_iter()
+        .flatten()
+    {
+        let path = entry.path();
+        if entry.depth() == 1 && entry.file_type().is_dir() {
+            files.push((path.to_owned(), None, 0));
+        } else if entry.file_type().is_file()
+            && matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("yaml" | "json")
+            )
+        {
+            add_file(files, path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+
+    #[test]
+    fn test_fingerprint_changes() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("stylus-reload-{}", std::process::id()));
+        let monitor_dir = dir.join("monitor.d/test");
+        std::fs::create_dir_all(&monitor_dir)?;
+        std::fs::write(
+            dir.join("config.yaml"),
+            "version: 1\nserver:\n  port: 8000\n",
+        )?;
+        std::fs::write(
+            monitor_dir.join("config.yaml"),
+            "test:\n  interval: 60s\n  timeout: 30s\n  command: /bin/true\n",
+        )?;
+
+        let config = parse_config(&dir)?;
+        let before = fingerprint(&config);
+        assert_eq!(before, fingerprint(&config));
+
+        std::fs::create_dir_all(dir.join("monitor.d/other"))?;
+        assert_ne!(before, fingerprint(&config));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::CssMetadataConfig;
+use crate::monitor::Monitor;
+use crate::status::{MonitorChildStatus, MonitorState, MonitorStatus, Status};
+
+/// A point-in-time copy of every monitor's state, persisted so that a restart does not blank
+/// out the dashboard until every monitor has run again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub saved: DateTime<Utc>,
+    pub monitors: BTreeMap<String, MonitorSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MonitorSnapshot {
+    pub status: MonitorStatus,
+    #[serde(default)]
+    pub children: BTreeMap<String, MonitorChildStatus>,
+    #[serde(default)]
+    pub log: VecDeque<String>,
+    #[serde(default)]
+    pub paused: bool,
+}
+
+impl Snapshot {
+    pub fn from_status(status: &Status) -> Self {
+        let mut snapshot = Snapshot {
+            saved: Utc::now(),
+            monitors: Default::default(),
+        };
+        for monitor in &status.monitors {
+            let monitor = monitor.read();
+            // Monitors that were never initialized have nothing worth saving
+            if monitor.status.is_uninitialized() {
+                continue;
+            }
+            snapshot.monitors.insert(
+                monitor.id.clone(),
+                MonitorSnapshot {
+                    status: monitor.status.clone(),
+                    children: monitor.children.clone(),
+                    log: monitor.status.log.clone(),
+                    paused: monitor.paused,
+                },
+            );
+        }
+        snapshot
+    }
+
+    /// Restore the snapshotted state for a monitor, if there was one, marking it as stale.
+    pub fn restore(&self, state: &mut MonitorState, config: &CssMetadataConfig) {
+        let Some(snapshot) = self.monitors.get(&state.id) else {
+            return;
+        };
+        let stale_since = snapshot.status.stale_since.unwrap_or(self.saved);
+
+        state.status = snapshot.status.clone();
+        state.status.log = snapshot.log.clone();
+        state.status.stale_since = Some(stale_since);
+        state.status.update_css(config);
+        // Children that have since been removed from the group's configuration are dropped
+        for (id, child) in state.children.iter_mut() {
+            if let Some(snapshot) = snapshot.children.get(id) {
+                child.status = snapshot.status.clone();
+                child.status.stale_since = Some(stale_since);
+                child.status.update_css(config);
+            }
+        }
+        state.paused = snapshot.paused;
+    }
+}
+
+pub fn load(path: &Path) -> Result<Option<Snapshot>, Box<dyn Error>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let s = std::fs::read_to_string(path)?;
+    Ok(Some(serde_json::from_str(&s)?))
+}
+
+pub fn save(monitor: &Monitor) -> Result<(), Box<dyn Error>> {
+    let config = monitor.config();
+    let Some(path) = &config.monitor.state.file else {
+        return Ok(());
+    };
+    let snapshot = Snapshot::from_status(&monitor.status());
+
+    // Write to a temporary file first so a crash can't leave a truncated state file behind
+    let temp = path.with_extension("tmp");
+    std::fs::write(&temp, serde_json::to_vec(&snapshot)?)?;
+    std::fs::rename(&temp, path)?;
+    debug!(
+        "Saved state for {} monitor(s) to {:?}",
+        snapshot.monitors.len(),
+        path
+    );
+    Ok(())
+}
+
+/// Periodically save the monitor state.
+pub async fn persist(monitor: Arc<Monitor>) {
+    loop {
+        tokio::time::sleep(monitor.config().monitor.state.interval).await;
+        let save_monitor = monitor.clone();
+        let _ = tokio::task::spawn_blocking(move || {
+            if let Err(e) = save(&save_monitor) {
+                error!("Failed to save monitor state: {}", e);
+            }
+        })
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::StatusState;
+
+    #[test]
+    fn test_round_trip() -> Result<(), Box<dyn Error>> {
+        let config = CssMetadataConfig::default();
+        let mut state = MonitorState::new_test("test");
+        state.status.status = Some(StatusState::Red);
+        state.status.description = "Failed".into();
+        state.status.log.push_back("log line".into());
+        let status = Status {
+            monitors: vec![keepcalm::SharedMut::new(state)],
+        };
+
+        let snapshot = Snapshot::from_status(&status);
+        let snapshot: Snapshot = serde_json::from_str(&serde_json::to_string(&snapshot)?)?;
+
+        let mut restored = MonitorState::new_test("test");
+        snapshot.restore(&mut restored, &config);
+        assert_eq!(restored.status.status, Some(StatusState::Red));
+        assert_eq!(restored.status.description, "Failed");
+        assert_eq!(restored.status.log, vec!["log line".to_string()]);
+        assert_eq!(restored.status.stale_since, Some(snapshot.saved));
+
+        let mut snapshot = snapshot;
+        let child = MonitorChildStatus {
+            axes: Default::default(),
+            status: restored.status.clone(),
+        };
+        let monitor = snapshot.monitors.get_mut("test").unwrap();
+        monitor.children.insert("test-sda".into(), child.clone());
+        monitor.children.insert("test-sdb".into(), child);
+        let mut restored = MonitorState::new_test("test");
+        restored
+            .children
+            .insert("test-sda".into(), Default::default());
+        snapshot.restore(&mut restored, &config);
+        assert_eq!(
+            restored.children.keys().collect::<Vec<_>>(),
+            vec!["test-sda"]
+        );
+        assert_eq!(
+            restored.children["test-sda"].status.status,
+            Some(StatusState::Red)
+        );
+        Ok(())
+    }
+}
@@ -1,11 +1,14 @@
 use std::collections::{BTreeMap, VecDeque};
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 
+use chrono::{DateTime, Utc};
 use keepcalm::SharedMut;
 use serde::{Deserialize, Serialize};
 
 use crate::config::*;
+use crate::events::MonitorEvent;
 use crate::interpolate::interpolate_modify;
 use crate::worker::LogStream;
 use crate::worker::WorkerMessage;
@@ -21,11 +24,61 @@ pub enum StatusState {
     Orange,
 }
 
+impl StatusState {
+    pub const ALL: [StatusState; 6] = [
+        StatusState::Blank,
+        StatusState::Green,
+        StatusState::Yellow,
+        StatusState::Red,
+        StatusState::Blue,
+        StatusState::Orange,
+    ];
+
+    /// Is this a failing (red, orange or yellow) status? This is what uptime, acknowledgements,
+    /// hooks, alerts and the Home Assistant problem sensor consider to be down.
+    pub fn is_failing(self) -> bool {
+        matches!(
+            self,
+            StatusState::Red | StatusState::Orange | StatusState::Yellow
+        )
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct Status {
     pub monitors: Vec<SharedMut<MonitorState>>,
 }
 
+impl Status {
+    /// A copy of the status with each monitor replaced by the result of `f`, or dropped if it
+    /// returns `None`.
+    pub fn filter_map(&self, f: impl Fn(&MonitorState) -> Option<MonitorState>) -> Status {
+        Status {
+            monitors: self
+                .monitors
+                .iter()
+                .filter_map(|monitor| f(&monitor.read()))
+                .map(SharedMut::new)
+                .collect(),
+        }
+    }
+
+    /// The monitor ids and their group children's ids.
+    #[cfg(test)]
+    pub fn ids(&self) -> Vec<(String, Vec<String>)> {
+        self.monitors
+            .iter()
+            .map(|monitor| {
+                let monitor = monitor.read();
+                (
+                    monitor.id.clone(),
+                    monitor.children.keys().cloned().collect(),
+                )
+            })
+            .collect()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MonitorState {
     pub id: String,
@@ -35,6 +88,14 @@ pub struct MonitorState {
     #[serde(skip)]
     pub css: Option<String>,
     pub children: BTreeMap<String, MonitorChildStatus>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub paused: bool,
+    /// When the current run started.
+    #[serde(skip)]
+    pub run_started: Option<DateTime<Utc>>,
+    /// Status changes from the last completed run that have not yet been collected.
+    #[serde(skip)]
+    pub events: Vec<MonitorEvent>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
@@ -52,6 +113,21 @@ pub struct MonitorStatus {
     pub description: String,
     pub css: MonitorCssStatus,
     pub metadata: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged: Option<MonitorAcknowledgement>,
+    /// When the last run that updated this status finished.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<DateTime<Utc>>,
+    /// How long the last run took, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Uptime percentages calculated from the status history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<MonitorUptime>,
+    /// Set when this status was restored from a previous run of stylus and has not been updated
+    /// since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_since: Option<DateTime<Utc>>,
     #[serde(skip)]
     pub log: VecDeque<String>,
     #[serde(skip)]
@@ -65,6 +141,27 @@ pub struct MonitorPendingStatus {
     pub metadata: Option<BTreeMap<String, String>>,
 }
 
+/// An operator's acknowledgement of a failing (red, orange or yellow) status. This is cleared as
+/// soon as the status changes or the acknowledgement expires.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct MonitorAcknowledgement {
+    /// The status that was acknowledged.
+    pub status: StatusState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub since: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+/// The percentage of time a monitor was up over the last day, week and month.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct MonitorUptime {
+    pub day: f64,
+    pub week: f64,
+    pub month: f64,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MonitorCssStatus {
     pub metadata: Arc<BTreeMap<String, String>>,
@@ -87,6 +184,9 @@ impl MonitorState {
             status: Default::default(),
             css: None,
             children: Default::default(),
+            paused: false,
+            run_started: None,
+            events: vec![],
         }
     }
 
@@ -117,6 +217,8 @@ impl MonitorState {
         match msg {
             WorkerMessage::Starting => {
                 // Note that we don't update the state here
+                self.run_started = Some(chrono::Utc::now());
+                self.events.clear();
                 self.status.pending = None;
                 self.status.log.clear();
                 self.process_log_message("exec  ", "Starting".into(), direct_logger);
@@ -171,15 +273,96 @@ impl MonitorState {
     ) {
         self.css = None;
 
+        let now = Utc::now();
+        let duration_ms = self
+            .run_started
+            .take()
+            .and_then(|started| (now - started).to_std().ok())
+            .map(|duration| duration.as_millis() as u64);
+
         for mut child in std::mem::take(&mut self.children) {
             let child_status = &mut child.1.status;
             if child_status.is_pending_status_set() || status != StatusState::Green {
+                let old_status = child_status.status;
                 child_status.finish(status, code, description.clone(), config);
+                child_status.last_run = Some(now);
+                child_status.duration_ms = duration_ms;
+                self.events.extend(MonitorEvent::from_change(
+                    &self.id,
+                    Some(&child.0),
+                    old_status,
+                    &child.1.status,
+                    &self.status.log,
+                ));
                 self.children.insert(child.0, child.1);
             }
         }
 
+        let old_status = self.status.status;
         self.status.finish(status, code, description, config);
+        self.status.last_run = Some(now);
+        self.status.duration_ms = duration_ms;
+        self.events.extend(MonitorEvent::from_change(
+            &self.id,
+            None,
+            old_status,
+            &self.status,
+            &self.status.log,
+        ));
+    }
+
+    /// An empty monitor for tests.
+    #[cfg(test)]
+    pub fn new_test(id: &str) -> Self {
+        MonitorState::new_internal(id.to_owned(), Default::default())
+    }
+
+    /// A copy of this monitor with only the group children that `child` keeps, or `None` if
+    /// the monitor isn't `shown` and none of its children are kept.
+    pub fn filter_children(
+        &self,
+        shown: bool,
+        mut child: impl FnMut(&str, &MonitorChildStatus) -> bool,
+    ) -> Option<MonitorState> {
+        let mut monitor = self.clone();
+        monitor.children.retain(|id, status| child(id, status));
+        (shown || !monitor.children.is_empty()).then_some(monitor)
+    }
+
+    /// Drop the acknowledgements of this monitor and its children that have expired, returning
+    /// whether there were any. The cached CSS is regenerated without them.
+    pub fn expire_acknowledgements(&mut self) -> bool {
+        let mut expired = self.status.expire_acknowledgement();
+        for child in self.children.values_mut() {
+            expired |= child.status.expire_acknowledgement();
+        }
+        if expired {
+            self.css = None;
+        }
+        expired
+    }
+
+    /// Acknowledge the current status of this monitor, or one of its children if `child` is
+    /// specified.
+    pub fn acknowledge(
+        &mut self,
+        child: Option<&str>,
+        comment: Option<String>,
+        expires: Option<Duration>,
+    ) -> Result<MonitorAcknowledgement, Box<dyn Error>> {
+        let status = match child {
+            None => &mut self.status,
+            Some(child) => {
+                &mut self
+                    .children
+                    .get_mut(child)
+                    .ok_or_else(|| format!("Unknown child {}", child))?
+                    .status
+            }
+        };
+        let ack = status.acknowledge(comment, expires)?;
+        self.css = None;
+        Ok(ack)
     }
 }
 
@@ -224,6 +407,57 @@ impl MonitorStatus {
         self.status.is_none()
     }
 
+    /// Is the current status acknowledged (and the acknowledgement still valid)?
+    pub fn is_acknowledged(&self) -> bool {
+        match &self.acknowledged {
+            Some(ack) => {
+                Some(ack.status) == self.status
+                    && ack.expires.is_none_or(|expires| expires > Utc::now())
+            }
+            None => false,
+        }
+    }
+
+    /// Drop the acknowledgement if it has expired, returning whether it did.
+    pub fn expire_acknowledgement(&mut self) -> bool {
+        let expired = self
+            .acknowledged
+            .as_ref()
+            .is_some_and(|ack| ack.expires.is_some_and(|expires| expires <= Utc::now()));
+        if expired {
+            self.acknowledged = None;
+        }
+        expired
+    }
+
+    /// Acknowledge the current status. Only failing statuses may be acknowledged.
+    pub fn acknowledge(
+        &mut self,
+        comment: Option<String>,
+        expires: Option<Duration>,
+    ) -> Result<MonitorAcknowledgement, Box<dyn Error>> {
+        let status = match self.status {
+            Some(status) if status.is_failing() => status,
+            Some(status) => {
+                return Err(format!("Cannot acknowledge a monitor with status {}", status).into())
+            }
+            None => return Err("Cannot acknowledge an uninitialized monitor".into()),
+        };
+        let since = Utc::now();
+        let expires = match expires {
+            Some(expires) => Some(since + chrono::Duration::from_std(expires)?),
+            None => None,
+        };
+        let ack = MonitorAcknowledgement {
+            status,
+            comment,
+            since,
+            expires,
+        };
+        self.acknowledged = Some(ack.clone());
+        Ok(ack)
+    }
+
     fn finish(
         &mut self,
         status: StatusState,
@@ -237,6 +471,7 @@ impl MonitorStatus {
             .map(|pending| (pending.status, pending.description, pending.metadata))
             .unwrap_or_default();
         self.code = code;
+        self.stale_since = None;
 
         // Start with the regular update
         self.status = Some(status);
@@ -256,7 +491,17 @@ impl MonitorStatus {
             }
         }
 
+        // Acknowledgements only survive while the acknowledged status persists
+        if !self.is_acknowledged() {
+            self.acknowledged = None;
+        }
+
         // Update the CSS metadata with the final status
+        self.update_css(config);
+    }
+
+    /// Update the CSS metadata to match the current status.
+    pub fn update_css(&mut self, config: &CssMetadataConfig) {
         if let Some(status) = self.status {
             self.css.metadata = match status {
                 StatusState::Blank => config.blank.clone(),
@@ -0,0 +1,240 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::response::sse::Event;
+use futures_util::Stream;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+
+use crate::css::{cached_css_for_monitor, generate_css_for_state};
+use crate::events::SequenceLog;
+use crate::monitor::Monitor;
+use crate::status::{MonitorState, Status};
+
+/// How many updates are kept for streams to resume from.
+const UPDATE_LOG_CAPACITY: usize = 1000;
+
+/// A bounded log of which monitors were updated, so that streams can send the current state of
+/// each monitor as it changes, and resume from a sequence number after reconnecting.
+#[derive(Debug)]
+pub struct UpdateLog {
+    /// Distinguishes the sequence numbers of this process from those of previous ones.
+    epoch: i64,
+    log: SequenceLog<(u64, String)>,
+}
+
+/// Where a stream starts: either a full snapshot, or the monitors updated since the given
+/// sequence number.
+#[derive(Debug, PartialEq)]
+pub enum StreamStart {
+    Snapshot(u64),
+    Resume(Vec<(u64, String)>),
+}
+
+impl UpdateLog {
+    pub fn new() -> Self {
+        UpdateLog {
+            epoch: chrono::Utc::now().timestamp_millis(),
+            log: SequenceLog::new(UPDATE_LOG_CAPACITY),
+        }
+    }
+
+    /// Record that a top-level monitor was updated, added or removed.
+    pub fn push(&self, id: &str) {
+        self.log.push(|seq| (seq, id.to_owned()));
+    }
+
+    /// The id of an event, which includes the epoch so that ids from before a restart are not
+    /// mistaken for our own.
+    pub fn event_id(&self, seq: u64) -> String {
+        format!("{}-{}", self.epoch, seq)
+    }
+
+    /// Subscribe to updates, working out what needs to be sent first for a client that last saw
+    /// the given event id.
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<&str>,
+    ) -> (StreamStart, broadcast::Receiver<(u64, String)>) {
+        let resume_from = last_event_id
+            .and_then(|id| id.split_once('-'))
+            .filter(|(epoch, _)| *epoch == self.epoch.to_string())
+            .and_then(|(_, seq)| seq.parse::<u64>().ok());
+        self.log.read(|updates, last_seq| {
+            // Subscribe while the log can't change so that no update is missed or sent twice
+            let receiver = self.log.subscribe();
+            let oldest = updates.front().map(|(seq, _)| *seq).unwrap_or(1);
+            match resume_from.filter(|seq| *seq <= last_seq) {
+                // Updates are only missing if the client is behind the oldest one we still have
+                Some(seq) if seq + 1 >= oldest => {
+                    // Each monitor only needs to be sent once, with its latest sequence number
+                    let mut latest = BTreeMap::new();
+                    for (update_seq, id) in updates.iter().filter(|(s, _)| *s > seq) {
+                        latest.insert(id.clone(), *update_seq);
+                    }
+                    let mut updates: Vec<_> =
+                        latest.into_iter().map(|(id, seq)| (seq, id)).collect();
+                    updates.sort();
+                    (StreamStart::Resume(updates), receiver)
+                }
+                _ => (StreamStart::Snapshot(last_seq), receiver),
+            }
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct SnapshotEvent<'a> {
+    status: &'a Status,
+    css: String,
+}
+
+#[derive(Serialize)]
+struct UpdateEvent<'a> {
+    id: &'a str,
+    monitor: &'a MonitorState,
+    css: String,
+}
+
+#[derive(Serialize)]
+struct RemoveEvent<'a> {
+    id: &'a str,
+}
+
+fn snapshot_event(monitor: &Monitor, seq: u64) -> Event {
+    let status = monitor.status();
+    let css = generate_css_for_state(&monitor.config().css, &status);
+    Event::default()
+        .event("snapshot")
+        .id(monitor.updates().event_id(seq))
+        .json_data(SnapshotEvent {
+            status: &status,
+            css,
+        })
+        .unwrap_or_default()
+}
+
+fn update_event(monitor: &Monitor, seq: u64, id: &str) -> Event {
+    let event = Event::default().id(monitor.updates().event_id(seq));
+    let Some((state, _)) = monitor.control(id) else {
+        return event
+            .event("remove")
+            .json_data(RemoveEvent { id })
+            .unwrap_or_default();
+    };
+    let mut state = state.write();
+    let css = cached_css_for_monitor(&monitor.config().css, &mut state);
+    event
+        .event("update")
+        .json_data(UpdateEvent {
+            id,
+            monitor: &state,
+            css,
+        })
+        .unwrap_or_default()
+}
+
+struct StreamState {
+    monitor: Arc<Monitor>,
+    pending: VecDeque<Event>,
+    receiver: broadcast::Receiver<(u64, String)>,
+    shutdown: watch::Receiver<bool>,
+}
+
+/// A stream of status events: a snapshot of every monitor (unless resuming), followed by the
+/// state of each monitor whenever it is updated. The stream ends when the server shuts down.
+pub fn stream(
+    monitor: Arc<Monitor>,
+    last_event_id: Option<&str>,
+    shutdown: watch::Receiver<bool>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let (start, receiver) = monitor.updates().subscribe(last_event_id);
+    let pending = match start {
+        StreamStart::Snapshot(seq) => VecDeque::from([snapshot_event(&monitor, seq)]),
+        StreamStart::Resume(updates) => updates
+            .iter()
+            .map(|(seq, id)| update_event(&monitor, *seq, id))
+            .collect(),
+    };
+    let state = StreamState {
+        monitor,
+        pending,
+        receiver,
+        shutdown,
+    };
+
+    futures_util::stream::unfold(state, |mut state| async move {
+        loop {
+            if *state.shutdown.borrow() {
+                return None;
+            }
+            if let Some(event) = state.pending.pop_front() {
+                return Some((Ok(event), state));
+            }
+            tokio::select! {
+                changed = state.shutdown.changed() => {
+                    if changed.is_err() {
+                        return None;
+                    }
+                }
+                update = state.receiver.recv() => match update {
+                    Ok((seq, id)) => state.pending.push_back(update_event(&state.monitor, seq, &id)),
+                    // Too far behind to catch up, so start again
+                    Err(RecvError::Lagged(_)) => {
+                        let (start, receiver) = state.monitor.updates().subscribe(None);
+                        let StreamStart::Snapshot(seq) = start else {
+                            unreachable!("subscribing without an id always starts with a snapshot");
+                        };
+                        state.receiver = receiver;
+                        state.pending.push_back(snapshot_event(&state.monitor, seq));
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribe() {
+        let log = UpdateLog::new();
+        let (start, _) = log.subscribe(None);
+        assert_eq!(start, StreamStart::Snapshot(0));
+
+        log.push("a");
+        log.push("b");
+        log.push("a");
+        let (start, mut receiver) = log.subscribe(Some(&log.event_id(1)));
+        assert_eq!(
+            start,
+            StreamStart::Resume(vec![(2, "b".into()), (3, "a".into())])
+        );
+        log.push("c");
+        assert_eq!(receiver.try_recv().unwrap(), (4, "c".into()));
+
+        // Up to date
+        let (start, _) = log.subscribe(Some(&log.event_id(4)));
+        assert_eq!(start, StreamStart::Resume(vec![]));
+
+        // Ids from another process, from the future or that are not ids start over
+        for id in ["1-2", &log.event_id(5), "garbage"] {
+            let (start, _) = log.subscribe(Some(id));
+            assert_eq!(start, StreamStart::Snapshot(4), "{id}");
+        }
+
+        // Updates that are no longer in the log start over
+        for _ in 0..UPDATE_LOG_CAPACITY {
+            log.push("d");
+        }
+        let (start, _) = log.subscribe(Some(&log.event_id(2)));
+        assert_eq!(start, StreamStart::Snapshot(1004));
+        let (start, _) = log.subscribe(Some(&log.event_id(4)));
+        assert_eq!(start, StreamStart::Resume(vec![(1004, "d".into())]));
+    }
+}
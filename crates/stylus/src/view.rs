@@ -0,0 +1,322 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::auth::AuthRole;
+use crate::config::Config;
+use crate::status::{MonitorState, MonitorStatus, Status};
+
+/// The first path segments of the built-in routes. A view served under one of them would clash
+/// with, or be mistaken for, the unfiltered endpoints.
+const RESERVED_PATHS: &[&str] = &[
+    "badge",
+    "config.json",
+    "dashboard.html",
+    "display",
+    "events",
+    "events.json",
+    "history",
+    "log",
+    "metrics",
+    "monitor",
+    "reload",
+    "render",
+    "status",
+    "status.json",
+    "style.css",
+    "stylus.css",
+    "stylus.js",
+    "stylus.js.map",
+    "stylus.svg",
+];
+
+/// A filtered view of the status, served under its own path, for sharing with people who
+/// shouldn't see everything. Logs are never available from a view.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ViewConfig {
+    /// The path the view is served under (default: `/<name>`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// The role required to see the view when `auth` is configured. Anyone can see it by default.
+    #[serde(default)]
+    pub role: AuthRole,
+    /// The monitors and group children to show. All are shown if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub monitors: Vec<String>,
+    /// Monitors and group children that are never shown.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hide: Vec<String>,
+    /// Whether descriptions and acknowledgement comments are shown.
+    #[serde(default)]
+    pub descriptions: bool,
+    /// Whether metadata is shown.
+    #[serde(default)]
+    pub metadata: bool,
+    /// The metadata keys to show. All metadata is shown if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub metadata_include: Vec<String>,
+    /// Metadata keys that are never shown.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub metadata_exclude: Vec<String>,
+    /// The `ui` section of the view's `config.json`, instead of the main one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ui: Option<serde_value::Value>,
+    /// The `config.d` entries included in the view's `config.json`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub config_d: Vec<String>,
+}
+
+/// The parts of the configuration that the web UI needs, as served by `/config.json`. Nothing
+/// else is served, as the rest may contain secrets such as webhook URLs.
+#[derive(Debug, Serialize)]
+pub struct UiConfigResponse {
+    pub version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ui: Option<serde_value::Value>,
+    pub config_d: BTreeMap<String, serde_value::Value>,
+}
+
+impl UiConfigResponse {
+    /// The UI configuration, with the `config.d` entries that `config_d` allows.
+    pub fn new(
+        config: &Config,
+        ui: Option<serde_value::Value>,
+        config_d: impl Fn(&str) -> bool,
+    ) -> Self {
+        UiConfigResponse {
+            version: config.version,
+            ui,
+            config_d: config
+                .config_d
+                .iter()
+                .filter(|(name, _)| config_d(name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl ViewConfig {
+    /// The path the view is served under, without a trailing slash.
+    pub fn path(&self, name: &str) -> String {
+        let path = self.path.clone().unwrap_or_else(|| name.to_owned());
+        format!("/{}", path.trim_matches('/'))
+    }
+
+    /// Check that the view's path doesn't clash with the server's own routes.
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        let path = self.path(name);
+        let first = path.trim_start_matches('/').split('/').next();
+        match first {
+            None | Some("") => Err(format!("View {} can't be served at /", name)),
+            Some(first) if RESERVED_PATHS.contains(&first) => Err(format!(
+                "View {} can't be served at {}, which is used by the server",
+                name, path
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_hidden(&self, id: &str) -> bool {
+        self.hide.iter().any(|hidden| hidden == id)
+    }
+
+    fn is_listed(&self, id: &str) -> bool {
+        self.monitors.is_empty() || self.monitors.iter().any(|shown| shown == id)
+    }
+
+    /// Should this metadata key be shown?
+    fn is_metadata_shown(&self, key: &str) -> bool {
+        self.metadata
+            && (self.metadata_include.is_empty() || self.metadata_include.iter().any(|k| k == key))
+            && !self.metadata_exclude.iter().any(|k| k == key)
+    }
+
+    fn redact_status(&self, status: &mut MonitorStatus) {
+        if !self.descriptions {
+            status.description.clear();
+            if let Some(acknowledged) = &mut status.acknowledged {
+                acknowledged.comment = None;
+            }
+        }
+        status.metadata.retain(|key, _| self.is_metadata_shown(key));
+        status.log.clear();
+        status.pending = None;
+    }
+
+    /// A copy of a monitor as seen by this view, or `None` if it is hidden. Listing a group
+    /// child shows its group, with only the listed children.
+    fn monitor(&self, monitor: &MonitorState) -> Option<MonitorState> {
+        if self.is_hidden(&monitor.id) {
+            return None;
+        }
+        let listed = self.is_listed(&monitor.id);
+        let mut monitor = monitor.filter_children(listed, |id, _| {
+            !self.is_hidden(id) && (listed || self.is_listed(id))
+        })?;
+
+        // The command may reveal internal hostnames or paths
+        monitor.config.command = PathBuf::new();
+        monitor.config.args.clear();
+        monitor.css = None;
+        monitor.events.clear();
+        self.redact_status(&mut monitor.status);
+        for child in monitor.children.values_mut() {
+            self.redact_status(&mut child.status);
+        }
+        Some(monitor)
+    }
+
+    /// The status as seen by this view.
+    pub fn status(&self, status: &Status) -> Status {
+        status.filter_map(|monitor| self.monitor(monitor))
+    }
+
+    /// The configuration as seen by this view.
+    pub fn config(&self, config: &Config) -> UiConfigResponse {
+        let ui = self.ui.clone().or_else(|| config.ui.clone());
+        UiConfigResponse::new(config, ui, |name| {
+            self.config_d.iter().any(|allowed| allowed == name)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keepcalm::SharedMut;
+
+    use super::*;
+    use crate::status::{MonitorAcknowledgement, MonitorChildStatus, StatusState};
+
+    fn monitor(id: &str, children: &[&str]) -> SharedMut<MonitorState> {
+        let status = MonitorStatus {
+            status: Some(StatusState::Red),
+            description: "Failed to reach 192.168.1.20".to_string(),
+            metadata: BTreeMap::from([
+                ("host".to_string(), "nas.lan".to_string()),
+                ("rtt".to_string(), "12".to_string()),
+            ]),
+            acknowledged: Some(MonitorAcknowledgement {
+                status: StatusState::Red,
+                comment: Some("Rebooting nas.lan".to_string()),
+                since: chrono::Utc::now(),
+                expires: None,
+            }),
+            log: ["ping 192.168.1.20".to_string()].into(),
+            ..Default::default()
+        };
+        let mut state = MonitorState::new_test(id);
+        state.config.command = "/srv/monitors/test.sh".into();
+        state.config.args = vec!["nas.lan".to_string()];
+        state.status = status.clone();
+        state.css = Some("/* cached */".to_string());
+        for child in children {
+            state.children.insert(
+                child.to_string(),
+                MonitorChildStatus {
+                    axes: Default::default(),
+                    status: status.clone(),
+                },
+            );
+        }
+        SharedMut::new(state)
+    }
+
+    #[test]
+    fn test_filter() {
+        let status = Status {
+            monitors: vec![
+                monitor("plex", &[]),
+                monitor("router", &[]),
+                monitor("disks", &["disks-sda", "disks-sdb"]),
+            ],
+        };
+
+        let view = ViewConfig::default();
+        assert_eq!(view.status(&status).ids().len(), 3);
+
+        let view = ViewConfig {
+            hide: vec!["router".to_string(), "disks-sdb".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            view.status(&status).ids(),
+            vec![
+                ("plex".to_string(), vec![]),
+                ("disks".to_string(), vec!["disks-sda".to_string()])
+            ]
+        );
+
+        let view = ViewConfig {
+            monitors: vec!["plex".to_string(), "disks-sdb".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            view.status(&status).ids(),
+            vec![
+                ("plex".to_string(), vec![]),
+                ("disks".to_string(), vec!["disks-sdb".to_string()])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redact() {
+        let status = Status {
+            monitors: vec![monitor("disks", &["disks-sda"])],
+        };
+
+        let view = ViewConfig::default();
+        let redacted = view.status(&status);
+        let monitor = redacted.monitors[0].read();
+        for status in [&monitor.status, &monitor.children["disks-sda"].status] {
+            assert_eq!(status.status, Some(StatusState::Red));
+            assert_eq!(status.description, "");
+            assert!(status.metadata.is_empty());
+            assert!(status.log.is_empty());
+            assert_eq!(status.acknowledged.as_ref().unwrap().comment, None);
+        }
+        assert_eq!(monitor.config.command, PathBuf::new());
+        assert!(monitor.config.args.is_empty());
+        assert_eq!(monitor.css, None);
+        let json = serde_json::to_string(&*monitor).unwrap();
+        assert!(
+            !json.contains("nas.lan") && !json.contains("192.168"),
+            "{json}"
+        );
+
+        let view = ViewConfig {
+            metadata: true,
+            metadata_exclude: vec!["host".to_string()],
+            descriptions: true,
+            ..Default::default()
+        };
+        let redacted = view.status(&status);
+        let monitor = redacted.monitors[0].read();
+        assert_eq!(monitor.status.description, "Failed to reach 192.168.1.20");
+        assert_eq!(
+            monitor.status.metadata.keys().collect::<Vec<_>>(),
+            vec!["rtt"]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let validate = |name: &str, path: Option<&str>| {
+            ViewConfig {
+                path: path.map(str::to_owned),
+                ..Default::default()
+            }
+            .validate(name)
+        };
+        assert!(validate("family", None).is_ok());
+        assert!(validate("public", Some("/family/public/")).is_ok());
+        assert!(validate("statuses", None).is_ok());
+        assert!(validate("status", None).is_err());
+        assert!(validate("public", Some("/log/public")).is_err());
+        assert!(validate("public", Some("/monitor")).is_err());
+        assert!(validate("public", Some("/")).is_err());
+    }
+}
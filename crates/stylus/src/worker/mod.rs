@@ -2,7 +2,7 @@ use std::error::Error;
 use std::ffi::{OsStr, OsString};
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::thread;
+use std::sync::{Condvar, Mutex};
 use std::time::{Duration, Instant};
 
 use subprocess::{Exec, ExitStatus, Popen, Redirection};
@@ -31,11 +31,118 @@ pub enum WorkerMessage {
     AbnormalTermination(String),
 }
 
+impl WorkerMessage {
+    /// Is this the final message of a run?
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            WorkerMessage::Termination(_) | WorkerMessage::AbnormalTermination(_)
+        )
+    }
+}
+
+/// Controls the scheduling of a monitor thread: on-demand runs, pausing and shutdown.
+#[derive(Debug, Default)]
+pub struct WorkerControl {
+    state: Mutex<WorkerControlState>,
+    condvar: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct WorkerControlState {
+    paused: bool,
+    run_requested: bool,
+    running: bool,
+    shutdown: bool,
+    runs: u64,
+}
+
+impl WorkerControl {
+    /// Request an immediate run, bypassing the interval (and pause). Returns the run count that
+    /// must be reached before the requested run is complete.
+    pub fn request_run(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.run_requested = true;
+        self.condvar.notify_all();
+        // If a run is in progress, the requested run will be the one after it
+        state.runs + if state.running { 2 } else { 1 }
+    }
+
+    /// Wait for the run count to reach `target`, returning false if we timed out or are shutting down.
+    pub fn wait_for_run(&self, target: u64, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.runs >= target {
+                return true;
+            }
+            let now = Instant::now();
+            if state.shutdown || now >= deadline {
+                return false;
+            }
+            state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.state.lock().unwrap().paused = paused;
+        self.condvar.notify_all();
+    }
+
+    /// Signal the monitor thread to exit at the next opportunity.
+    pub fn shutdown(&self) {
+        self.state.lock().unwrap().shutdown = true;
+        self.condvar.notify_all();
+    }
+
+    fn start_run(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.running = true;
+        state.run_requested = false;
+    }
+
+    fn complete_run(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.running = false;
+        state.runs += 1;
+        self.condvar.notify_all();
+    }
+
+    /// Sleep until the interval elapses (unless paused) or a run is requested. Returns false if
+    /// the thread should shut down.
+    fn wait(&self, interval: Duration) -> bool {
+        let deadline = Instant::now() + interval;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.shutdown {
+                return false;
+            }
+            if state.run_requested {
+                return true;
+            }
+            let now = Instant::now();
+            if state.paused {
+                state = self.condvar.wait(state).unwrap();
+            } else if now >= deadline {
+                return true;
+            } else {
+                state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+            }
+        }
+    }
+}
+
 pub fn monitor_thread<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error>>>(
     monitor: &MonitorDirConfig,
+    control: &WorkerControl,
     mut sender: T,
 ) {
+    // Monitors restored in a paused state shouldn't run until resumed
+    if !control.wait(Duration::ZERO) {
+        return;
+    }
     loop {
+        control.start_run();
         let (interval, res) = monitor_run(&monitor, &mut sender);
         if let Err(err) = res {
             // Break the loop on a task failure (but don't log ShuttingDown errors)
@@ -48,12 +155,16 @@ pub fn monitor_thread<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error>
             )
             .is_err()
             {
+                control.complete_run();
                 return;
             }
         }
+        control.complete_run();
 
         trace!("[{}] Sleeping {}ms", monitor.id, interval.as_millis());
-        thread::sleep(interval);
+        if !control.wait(interval) {
+            return;
+        }
     }
 }
 
@@ -196,17 +307,30 @@ fn monitor_thread_impl<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error
     // This will fail if we're supposed to shut down
     sender(id, WorkerMessage::Starting)?;
 
-    let mut exec = Exec::cmd(cmd)
-        .cwd(base_path)
-        .env("STYLUS_MONITOR_ID", id)
-        .stdout(Redirection::Pipe)
-        .stderr(Redirection::Pipe);
+    let mut exec = Exec::cmd(cmd).cwd(base_path).env("STYLUS_MONITOR_ID", id);
     if let Some(args) = args {
         exec = exec.args(args);
         debug!("[{}] Starting {:?} {args:?}", id, cmd);
     } else {
         debug!("[{}] Starting {:?}", id, cmd);
     }
+    run_process(id, exec, None, timeout, sender, processor)
+}
+
+/// Run a process until it exits or the timeout elapses, sending its output and exit status to
+/// `sender`. If `input` is provided, it is written to the process' stdin.
+pub fn run_process<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error>>>(
+    id: &str,
+    exec: Exec,
+    input: Option<Vec<u8>>,
+    timeout: Duration,
+    sender: &mut T,
+    processor: Option<&dyn MonitorMessageProcessorInstance>,
+) -> Result<(), Box<dyn Error>> {
+    let mut exec = exec.stdout(Redirection::Pipe).stderr(Redirection::Pipe);
+    if input.is_some() {
+        exec = exec.stdin(Redirection::Pipe);
+    }
     let mut popen = exec.popen()?;
 
     let failed = AtomicBool::new(false);
@@ -218,7 +342,7 @@ fn monitor_thread_impl<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error
 
     let start = Instant::now();
     let mut comms = popen
-        .communicate_start(None)
+        .communicate_start(input)
         .limit_time(Duration::from_millis(250));
 
     while start.elapsed() < timeout {
@@ -308,6 +432,30 @@ fn monitor_thread_impl<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error
 mod tests {
     use super::*;
     use std::sync::mpsc::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_control_run_and_pause() {
+        let control = Arc::new(WorkerControl::default());
+        control.set_paused(true);
+
+        // A paused worker sleeps until a run is requested
+        let waiter = control.clone();
+        let handle = thread::spawn(move || waiter.wait(Duration::from_millis(10)));
+        thread::sleep(Duration::from_millis(50));
+        assert!(!handle.is_finished());
+        let target = control.request_run();
+        assert_eq!(target, 1);
+        assert!(handle.join().unwrap());
+
+        control.start_run();
+        control.complete_run();
+        assert!(control.wait_for_run(target, Duration::from_millis(10)));
+
+        control.shutdown();
+        assert!(!control.wait(Duration::from_secs(60)));
+    }
 
     #[test]
     fn test_timeout() {